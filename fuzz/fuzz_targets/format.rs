@@ -0,0 +1,20 @@
+//! Feed arbitrary input through the full pipeline (line ending normalization,
+//! frontmatter extraction, parsing, formatting) and check that it never
+//! panics and that formatting is idempotent.
+//!
+//! Run with `cargo fuzz run format` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use md_formatter::{normalize_line_endings, Formatter};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let input = normalize_line_endings(&input);
+
+    let mut formatter = Formatter::new(80);
+    let once = formatter.format_document(&input);
+    let twice = formatter.format_document(&once);
+    assert_eq!(once, twice, "formatting is not idempotent for {:?}", input);
+});
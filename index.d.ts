@@ -5,12 +5,76 @@
 
 /** Options for the markdown formatter */
 export interface FormatOptions {
-  /** Target line width for wrapping (default: 80) */
+  /** Target line width for wrapping (default: 80); 0 puts each paragraph on one line */
   width?: number
   /** How to wrap prose: "always", "never", or "preserve" (default: "preserve") */
   wrap?: string
-  /** How to number ordered lists: "ascending" (1, 2, 3) or "one" (all 1.) (default: "ascending") */
+  /** How to number ordered lists: "ascending" (1, 2, 3), "one" (all 1.) or "preserve" (count up from the first number) (default: "ascending") */
   orderedList?: string
+  /** How to lay out tables: "padded" or "compact" (default: "padded") */
+  tableStyle?: string
+  /** Spacing before nested lists: "tight" or "loose" (default: "tight") */
+  nestedListSpacing?: string
+  /** Convert bold/italic-only paragraphs into headings of this level (default: off) */
+  pseudoHeadingLevel?: number
+  /** Blank lines after frontmatter: "normalize" (exactly one) or "preserve" (keep as-is) (default: "normalize") */
+  frontmatterSpacing?: string
+  /** Right-align table columns without explicit alignment that contain only numbers (default: false) */
+  alignNumericColumns?: boolean
+  /** How to write horizontal rules, given as an example rule such as "---", "***" or "* * *" (default: "---") */
+  horizontalRule?: string
+  /** With wrap "always", avoid a single word alone on the last line of a paragraph (default: false) */
+  noWidows?: boolean
+  /** End the output with a newline; set to false to embed the result inline (default: true) */
+  trailingNewline?: boolean
+  /** Turn lines like "#Heading" (no space after the hashes) into headings (default: false) */
+  fixHeadingSpacing?: boolean
+  /** Line endings to write: "lf", "crlf", or "preserve" to keep the input's (default: "lf") */
+  lineEnding?: string
+  /** How quotes and dashes in prose are written: "keep", "smart" (curly quotes, en/em dashes) or "straight" (default: "keep") */
+  punctuation?: string
+  /** Right-align ordered list numbers so the periods line up (default: false) */
+  alignOrderedMarkers?: boolean
+  /** Marker for unordered list items: "-", "*" or "+" (also "dash", "star", "plus"; default: "-") */
+  bullet?: string
+  /** Heading style: "atx" (default) or "setext-when-possible" (underlined level 1 and 2 headings) */
+  headingStyle?: string
+  /** Character after ordered list numbers: "period" (1.) or "paren" (1)) (default: "period") */
+  orderedDelimiter?: string
+  /** How to write links: "inline" ([text](url)), "reference" ([text][n] with definitions collected at the end) or "preserve-reference" (keep the source's reference labels) (default: "inline") */
+  linkStyle?: string
+  /** Where reference-style definitions go: "document" (end of the document) or "section" (end of each H1/H2 section) (default: "document") */
+  referencePlacement?: string
+  /** Strip trailing spaces and tabs from code block lines (default: false) */
+  trimCodeTrailingWhitespace?: boolean
+  /** Lowercase code block languages and expand aliases such as "js" to "javascript" (default: false) */
+  normalizeCodeLang?: boolean
+  /** Extra language aliases for normalizeCodeLang, e.g. { "tsx": "typescript" }, added to the built-in ones */
+  codeLangAliases?: Record<string, string>
+  /** Indentation of list item content: "tab", or 2 to 4 spaces such as "4" (default: "2") */
+  indent?: string
+  /** Most blank lines kept in a row between top-level blocks (default: 1) */
+  maxBlankLines?: number
+  /** With wrap "always", split words longer than the width; links, code and math are never split (default: false) */
+  breakLongWords?: boolean
+  /** Heading close: "open" (default) or "closed" (`# Title #`) */
+  headingClose?: string
+  /** Re-indent YAML frontmatter consistently, keeping values as written; frontmatter with comments is left alone (default: false) */
+  normalizeFrontmatter?: boolean
+  /** Sort the top-level keys of YAML frontmatter, normalizing it (default: false) */
+  sortFrontmatterKeys?: boolean
+  /** Replace each tab in prose (not code) with this many spaces before wrapping (default: off) */
+  expandTabs?: number
+  /** Emphasis marker: "asterisk" (default) or "underscore" (`_text_`; `*` is kept inside words) */
+  emphasisMarker?: string
+  /** Strong emphasis marker: "asterisk" (default) or "underscore" (`__text__`; `**` is kept inside words) */
+  strongMarker?: string
+  /** Case of the x in checked task list items: "lower" (default, `[x]`) or "upper" (`[X]`) */
+  taskMarkerCase?: string
+  /** How indented code blocks are written: "fenced" (default) or "preserve" */
+  codeBlockStyle?: string
+  /** Number each ordered list starts at with ascending numbering, nested lists too; at most 999999999 (default: 1) */
+  orderedStart?: number
 }
 /** Result of a format operation */
 export interface FormatResult {
@@ -18,6 +82,15 @@ export interface FormatResult {
   content: string
   /** Whether the content was changed */
   changed: boolean
+  /** Regions of the input that formatting changes */
+  ranges: Array<ChangeRange>
+}
+/** A region of the input that formatting changes (1-based, end exclusive) */
+export interface ChangeRange {
+  line: number
+  col: number
+  endLine: number
+  endCol: number
 }
 /**
  * Format a markdown string with the given options.
@@ -51,26 +124,96 @@ export interface FileResult {
   changed: boolean
   /** Error message if the file could not be processed */
   error?: string
+  /** Regions of the original file that formatting changes (empty on error) */
+  ranges: Array<ChangeRange>
 }
 /** Options for file operations */
 export interface FileOptions {
-  /** Target line width for wrapping (default: 80) */
+  /** Target line width for wrapping (default: 80); 0 puts each paragraph on one line */
   width?: number
   /** How to wrap prose: "always", "never", or "preserve" (default: "preserve") */
   wrap?: string
-  /** How to number ordered lists: "ascending" (1, 2, 3) or "one" (all 1.) (default: "ascending") */
+  /** How to number ordered lists: "ascending" (1, 2, 3), "one" (all 1.) or "preserve" (count up from the first number) (default: "ascending") */
   orderedList?: string
+  /** How to lay out tables: "padded" or "compact" (default: "padded") */
+  tableStyle?: string
+  /** Spacing before nested lists: "tight" or "loose" (default: "tight") */
+  nestedListSpacing?: string
+  /** Convert bold/italic-only paragraphs into headings of this level (default: off) */
+  pseudoHeadingLevel?: number
+  /** Blank lines after frontmatter: "normalize" (exactly one) or "preserve" (keep as-is) (default: "normalize") */
+  frontmatterSpacing?: string
+  /** Right-align table columns without explicit alignment that contain only numbers (default: false) */
+  alignNumericColumns?: boolean
+  /** How to write horizontal rules, given as an example rule such as "---", "***" or "* * *" (default: "---") */
+  horizontalRule?: string
+  /** With wrap "always", avoid a single word alone on the last line of a paragraph (default: false) */
+  noWidows?: boolean
+  /** End the output with a newline; set to false to embed the result inline (default: true) */
+  trailingNewline?: boolean
+  /** Turn lines like "#Heading" (no space after the hashes) into headings (default: false) */
+  fixHeadingSpacing?: boolean
+  /** Line endings to write: "lf", "crlf", or "preserve" to keep the input's (default: "lf") */
+  lineEnding?: string
+  /** How quotes and dashes in prose are written: "keep", "smart" (curly quotes, en/em dashes) or "straight" (default: "keep") */
+  punctuation?: string
+  /** Right-align ordered list numbers so the periods line up (default: false) */
+  alignOrderedMarkers?: boolean
+  /** Marker for unordered list items: "-", "*" or "+" (also "dash", "star", "plus"; default: "-") */
+  bullet?: string
+  /** Heading style: "atx" (default) or "setext-when-possible" (underlined level 1 and 2 headings) */
+  headingStyle?: string
+  /** Character after ordered list numbers: "period" (1.) or "paren" (1)) (default: "period") */
+  orderedDelimiter?: string
+  /** How to write links: "inline" ([text](url)), "reference" ([text][n] with definitions collected at the end) or "preserve-reference" (keep the source's reference labels) (default: "inline") */
+  linkStyle?: string
+  /** Where reference-style definitions go: "document" (end of the document) or "section" (end of each H1/H2 section) (default: "document") */
+  referencePlacement?: string
+  /** Strip trailing spaces and tabs from code block lines (default: false) */
+  trimCodeTrailingWhitespace?: boolean
+  /** Lowercase code block languages and expand aliases such as "js" to "javascript" (default: false) */
+  normalizeCodeLang?: boolean
+  /** Extra language aliases for normalizeCodeLang, e.g. { "tsx": "typescript" }, added to the built-in ones */
+  codeLangAliases?: Record<string, string>
+  /** Indentation of list item content: "tab", or 2 to 4 spaces such as "4" (default: "2") */
+  indent?: string
+  /** Most blank lines kept in a row between top-level blocks (default: 1) */
+  maxBlankLines?: number
+  /** With wrap "always", split words longer than the width; links, code and math are never split (default: false) */
+  breakLongWords?: boolean
+  /** Heading close: "open" (default) or "closed" (`# Title #`) */
+  headingClose?: string
+  /** Re-indent YAML frontmatter consistently, keeping values as written; frontmatter with comments is left alone (default: false) */
+  normalizeFrontmatter?: boolean
+  /** Sort the top-level keys of YAML frontmatter, normalizing it (default: false) */
+  sortFrontmatterKeys?: boolean
+  /** Replace each tab in prose (not code) with this many spaces before wrapping (default: off) */
+  expandTabs?: number
+  /** Emphasis marker: "asterisk" (default) or "underscore" (`_text_`; `*` is kept inside words) */
+  emphasisMarker?: string
+  /** Strong emphasis marker: "asterisk" (default) or "underscore" (`__text__`; `**` is kept inside words) */
+  strongMarker?: string
+  /** Case of the x in checked task list items: "lower" (default, `[x]`) or "upper" (`[X]`) */
+  taskMarkerCase?: string
+  /** How indented code blocks are written: "fenced" (default) or "preserve" */
+  codeBlockStyle?: string
+  /** Number each ordered list starts at with ascending numbering, nested lists too; at most 999999999 (default: 1) */
+  orderedStart?: number
   /** Additional directories to exclude */
   exclude?: Array<string>
   /** Don't exclude any directories by default */
   noDefaultExcludes?: boolean
+  /** Skip files larger than this many bytes (reported as an error) */
+  maxFileSize?: number
+  /** File extensions treated as Markdown, e.g. ["md", "mdx"] (default: ["md", "markdown"]) */
+  extensions?: Array<string>
 }
 /**
  * Format files matching the given patterns and write changes to disk.
  *
  * @param patterns - File paths, directories, or glob patterns
  * @param options - Optional formatting and file options
- * @returns Array of results for each file processed
+ * @returns Array of results for each file processed, sorted by path
  */
 export declare function formatFiles(patterns: Array<string>, options?: FileOptions | undefined | null): Array<FileResult>
 /**
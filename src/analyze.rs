@@ -0,0 +1,300 @@
+//! Read-only document analysis
+//!
+//! Walks the same event stream the formatter uses and collects document
+//! metadata (headings, links) plus diagnostics. Nothing here rewrites input.
+
+use pulldown_cmark::{BrokenLink, Event, LinkType, Parser, Tag, TagEnd};
+use std::path::{Path, PathBuf};
+
+use crate::parser::{
+    extract_frontmatter, find_lone_carriage_return, heading_level, parser_options,
+};
+use crate::slug::Slugger;
+
+/// What a diagnostic is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A relative link points to a file that doesn't exist
+    MissingLinkTarget,
+    /// A construct the formatter passes through without normalizing (strict mode)
+    Unsupported,
+    /// Carriage-return-only or mixed (CRLF and LF) line endings
+    LineEndings,
+    /// A heading more than one level below the previous one (h1 → h3)
+    HeadingLevelSkip,
+    /// A `[text][label]` reference whose label has no definition (kept as text)
+    UndefinedReference,
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found while analyzing a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// What kind of problem this is
+    pub kind: DiagnosticKind,
+    /// Whether this should fail the run
+    pub severity: Severity,
+    /// Human-readable description
+    pub message: String,
+    /// Byte offset into the original input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column (in characters)
+    pub column: usize,
+}
+
+/// A heading found in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadingInfo {
+    /// Heading level (1-6)
+    pub level: u32,
+    /// Plain text of the heading
+    pub text: String,
+    /// Anchor id (`#anchor`) the heading gets, unique within the document
+    pub anchor: String,
+    /// Byte offset into the original input
+    pub offset: usize,
+}
+
+/// A link found in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// Link destination as written
+    pub url: String,
+    /// Byte offset into the original input
+    pub offset: usize,
+}
+
+/// Options controlling which checks `analyze` runs
+#[derive(Debug, Clone, Default)]
+pub struct AnalyzeOptions {
+    /// Directory that relative link targets are resolved against.
+    /// Link targets are only checked when this is set.
+    pub base_path: Option<PathBuf>,
+    /// Report constructs the formatter can't normalize as errors
+    pub strict: bool,
+}
+
+/// Result of analyzing a document
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    pub headings: Vec<HeadingInfo>,
+    pub links: Vec<LinkInfo>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    /// Whether any diagnostic has error severity
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+
+    fn push(
+        &mut self,
+        input: &str,
+        kind: DiagnosticKind,
+        severity: Severity,
+        offset: usize,
+        message: String,
+    ) {
+        let (line, column) = line_col(input, offset);
+        self.diagnostics.push(Diagnostic {
+            kind,
+            severity,
+            message,
+            offset,
+            line,
+            column,
+        });
+    }
+}
+
+/// Analyze a markdown document (frontmatter is skipped) without formatting it
+pub fn analyze(input: &str, options: &AnalyzeOptions) -> Analysis {
+    let (_, content) = extract_frontmatter(input);
+    let base_offset = input.len() - content.len();
+
+    let mut analysis = Analysis::default();
+    if let Some(offset) = find_lone_carriage_return(input) {
+        analysis.push(
+            input,
+            DiagnosticKind::LineEndings,
+            Severity::Warning,
+            offset,
+            "Carriage-return line endings are converted to LF".to_string(),
+        );
+    }
+    if let Some(offset) = find_mixed_line_ending(input) {
+        analysis.push(
+            input,
+            DiagnosticKind::LineEndings,
+            Severity::Warning,
+            offset,
+            "Line ending differs from the first line's (mixed CRLF and LF)".to_string(),
+        );
+    }
+
+    let mut heading: Option<(u32, String, usize)> = None;
+    let mut slugger = Slugger::new();
+    // Inside an HTML comment spanning several lines of raw HTML
+    let mut in_comment = false;
+
+    // Full and collapsed references to missing labels. Shortcut ones (`[text]`)
+    // aren't reported: brackets in prose are usually just brackets.
+    let mut undefined_references = Vec::new();
+    let mut on_broken_link = |link: BrokenLink| {
+        if matches!(link.link_type, LinkType::Reference | LinkType::Collapsed) {
+            undefined_references.push((link.span.start, link.reference.to_string()));
+        }
+        None
+    };
+    let parser =
+        Parser::new_with_broken_link_callback(content, parser_options(), Some(&mut on_broken_link));
+
+    for (event, range) in parser.into_offset_iter() {
+        let offset = base_offset + range.start;
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((heading_level(level), String::new(), offset));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, text, offset)) = heading.take() {
+                    if let Some(previous) = analysis.headings.last().map(|h| h.level) {
+                        if level > previous + 1 {
+                            analysis.push(
+                                input,
+                                DiagnosticKind::HeadingLevelSkip,
+                                Severity::Warning,
+                                offset,
+                                format!("Heading level jumps from h{} to h{}", previous, level),
+                            );
+                        }
+                    }
+                    analysis.headings.push(HeadingInfo {
+                        level,
+                        anchor: slugger.slug(&text),
+                        text,
+                        offset,
+                    });
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading_text, _)) = heading.as_mut() {
+                    heading_text.push_str(&text);
+                }
+            }
+            Event::Start(Tag::Link { dest_url: url, .. }) => {
+                analysis.links.push(LinkInfo {
+                    url: url.to_string(),
+                    offset,
+                });
+            }
+            Event::Html(html) | Event::InlineHtml(html) if options.strict => {
+                // Comments aren't rendered, so nothing is lost by passing them
+                // through; this covers the formatter's own ignore directives
+                let comment = in_comment || html.trim_start().starts_with("<!--");
+                in_comment = comment && !html.contains("-->");
+                if comment {
+                    continue;
+                }
+                analysis.push(
+                    input,
+                    DiagnosticKind::Unsupported,
+                    Severity::Error,
+                    offset,
+                    "Raw HTML is passed through without formatting".to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    for (offset, label) in undefined_references {
+        analysis.push(
+            input,
+            DiagnosticKind::UndefinedReference,
+            Severity::Warning,
+            base_offset + offset,
+            format!(
+                "Reference '[{}]' has no definition and is kept as text",
+                label
+            ),
+        );
+    }
+
+    if let Some(base) = &options.base_path {
+        let mut diagnostics = Vec::new();
+        for link in &analysis.links {
+            if let Some(target) = local_link_target(&link.url) {
+                if !base.join(target).exists() {
+                    diagnostics.push((
+                        link.offset,
+                        format!("Link target '{}' does not exist", target),
+                    ));
+                }
+            }
+        }
+        for (offset, message) in diagnostics {
+            analysis.push(
+                input,
+                DiagnosticKind::MissingLinkTarget,
+                Severity::Warning,
+                offset,
+                message,
+            );
+        }
+    }
+
+    analysis.diagnostics.sort_by_key(|d| d.offset);
+    analysis
+}
+
+/// Check a document without formatting it: the diagnostics of `analyze`
+/// (heading level skips, line ending problems, undefined references, and
+/// unsupported constructs in strict mode), sorted by position
+pub fn validate(input: &str, options: &AnalyzeOptions) -> Vec<Diagnostic> {
+    analyze(input, options).diagnostics
+}
+
+/// Byte offset of the first line ending that doesn't match the first line's
+/// (CRLF vs LF)
+fn find_mixed_line_ending(input: &str) -> Option<usize> {
+    let mut newlines = input.match_indices('\n').map(|(i, _)| i);
+    let crlf = |i: usize| input[..i].ends_with('\r');
+    let first = crlf(newlines.next()?);
+    newlines
+        .find(|&i| crlf(i) != first)
+        .map(|i| input[..i].rfind('\n').map_or(0, |start| start + 1))
+}
+
+/// Return the file part of a relative link, or None for URLs, anchors and absolute paths
+fn local_link_target(url: &str) -> Option<&str> {
+    if url.contains("://") || url.starts_with("mailto:") || url.starts_with('/') {
+        return None;
+    }
+    let end = url.find(['#', '?']).unwrap_or(url.len());
+    let path = &url[..end];
+    if path.is_empty() || Path::new(path).is_absolute() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Convert a byte offset into a 1-based (line, column) pair
+pub fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset.min(input.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
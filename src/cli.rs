@@ -1,52 +1,208 @@
-use clap::{Parser, ValueEnum};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Parser};
 use glob::glob;
-use std::path::PathBuf;
-
-/// How to handle prose wrapping
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
-pub enum WrapMode {
-    /// Wrap prose if it exceeds the print width
-    Always,
-    /// Un-wrap each block of prose into one line
-    Never,
-    /// Do nothing, leave prose as-is (default)
+use ignore::WalkBuilder;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::embedded::EmbeddedLanguage;
+use crate::formatter::{
+    BulletStyle, CodeBlockStyle, EmphasisMarker, FrontmatterSpacing, HeadingClose, HeadingStyle,
+    HorizontalRule, IndentStyle, LineEnding, LinkStyle, NestedListSpacing, OrderedListDelimiter,
+    OrderedListMode, ReferencePlacement, TableStyle, TaskMarkerCase, WrapMode, MAX_LIST_NUMBER,
+};
+
+/// How the CLI reports its results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Formatted output on stdout, messages on stderr
     #[default]
-    Preserve,
+    Text,
+    /// A JSON array of `FileReport`s on stdout
+    Json,
 }
 
-impl From<WrapMode> for crate::formatter::WrapMode {
-    fn from(mode: WrapMode) -> Self {
-        match mode {
-            WrapMode::Always => Self::Always,
-            WrapMode::Never => Self::Never,
-            WrapMode::Preserve => Self::Preserve,
+/// Result for one input, as reported by `--format json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReport {
+    /// The file path (or the stdin label)
+    pub path: String,
+    /// Whether the file was changed (or would be changed in check mode)
+    pub changed: bool,
+    /// Error message if the file could not be processed
+    pub error: Option<String>,
+}
+
+/// Why the input files couldn't be resolved from the arguments
+#[derive(Debug)]
+pub enum CliError {
+    /// No paths given and not reading from stdin
+    NoInput,
+    /// `--stdin-filepath` given without reading from stdin
+    StdinFilepathWithoutStdin,
+    /// A path that is neither a file nor a directory, and not a valid glob
+    InvalidGlob {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    /// A file named explicitly doesn't have one of the extensions
+    NotMarkdown {
+        path: PathBuf,
+        /// The accepted extensions, e.g. `.md, .markdown`
+        extensions: String,
+        /// Whether source files with embedded Markdown were expected
+        embedded: bool,
+    },
+    /// The paths matched no files with one of the extensions
+    NoFilesFound { extensions: String, embedded: bool },
+    /// Running git for `--since` failed
+    Git(String),
+}
+
+impl CliError {
+    /// Process exit code: 2 for invalid arguments (as for clap's own usage
+    /// errors), 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NoInput | Self::StdinFilepathWithoutStdin | Self::InvalidGlob { .. } => 2,
+            Self::NotMarkdown { .. } | Self::NoFilesFound { .. } | Self::Git(_) => 1,
         }
     }
 }
 
-/// How to handle ordered list numbering
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
-pub enum OrderedListMode {
-    /// Renumber items sequentially (1, 2, 3, ...) - default
-    #[default]
-    Ascending,
-    /// Use 1. for all items
-    One,
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInput => write!(f, "No input provided. Use --stdin or specify file paths."),
+            Self::StdinFilepathWithoutStdin => {
+                write!(
+                    f,
+                    "--stdin-filepath can only be used when reading from stdin"
+                )
+            }
+            Self::InvalidGlob { pattern, source } => {
+                write!(f, "Invalid glob pattern '{}': {}", pattern, source)
+            }
+            Self::NotMarkdown {
+                path,
+                extensions,
+                embedded: true,
+            } => write!(f, "File '{}' is not a {} file", path.display(), extensions),
+            Self::NotMarkdown {
+                path, extensions, ..
+            } => write!(
+                f,
+                "File '{}' is not a markdown file ({})",
+                path.display(),
+                extensions
+            ),
+            Self::NoFilesFound {
+                extensions,
+                embedded: true,
+            } => write!(f, "No {} files found.", extensions),
+            Self::NoFilesFound { .. } => write!(f, "No markdown files found."),
+            Self::Git(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidGlob { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Name of the config file looked up from each input's directory
+pub const CONFIG_FILE_NAME: &str = ".mdfmt.toml";
+
+/// Settings from a `.mdfmt.toml` file.
+///
+/// Each setting replaces the built-in default of the flag with the same
+/// name (`exclude` is `--exclude`); a flag given on the command line always
+/// wins over the file. Only the nearest config file applies, see
+/// [`ConfigCache::find`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub width: Option<usize>,
+    #[serde(deserialize_with = "parse_setting")]
+    pub wrap: Option<WrapMode>,
+    #[serde(deserialize_with = "parse_setting")]
+    pub ordered_list: Option<OrderedListMode>,
+    #[serde(deserialize_with = "parse_setting")]
+    pub bullet: Option<BulletStyle>,
+    pub exclude: Option<Vec<String>>,
+}
+
+/// Read a setting written the same way as its command line value
+fn parse_setting<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl Config {
+    /// Read and parse a config file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config '{}': {}", path.display(), e))
+    }
+}
+
+/// Config files found so far, by the directory they apply to
+#[derive(Debug, Default)]
+pub struct ConfigCache {
+    configs: HashMap<PathBuf, Option<Config>>,
 }
 
-impl From<OrderedListMode> for crate::formatter::OrderedListMode {
-    fn from(mode: OrderedListMode) -> Self {
-        match mode {
-            OrderedListMode::Ascending => Self::Ascending,
-            OrderedListMode::One => Self::One,
+impl ConfigCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The config that applies to files in `dir`: the first `.mdfmt.toml`
+    /// found in `dir` or one of its parents. The search stops at the root of
+    /// the git repository (a directory containing `.git`), so config files
+    /// above the repository are never used.
+    pub fn find(&mut self, dir: &Path) -> Result<Option<Config>, String> {
+        let dir = std::path::absolute(dir).map_err(|e| e.to_string())?;
+        if let Some(config) = self.configs.get(&dir) {
+            return Ok(config.clone());
         }
+
+        let path = dir.join(CONFIG_FILE_NAME);
+        let config = if path.is_file() {
+            Some(Config::load(&path)?)
+        } else if dir.join(".git").exists() {
+            None
+        } else {
+            match dir.parent() {
+                Some(parent) => self.find(parent)?,
+                None => None,
+            }
+        };
+        self.configs.insert(dir, config.clone());
+        Ok(config)
     }
 }
 
 /// Default directories to exclude when searching
 const DEFAULT_EXCLUDES: &[&str] = &["node_modules", "target", ".git", "vendor", "dist", "build"];
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(name = "mdfmt")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "Fast, opinionated Markdown formatter", long_about = None)]
@@ -63,11 +219,45 @@ pub struct Args {
     #[arg(long)]
     pub check: bool,
 
+    /// With --check, print the number of lines each file would gain and lose ("doc.md: +3 -2")
+    #[arg(long, requires = "check")]
+    pub diff_stat: bool,
+
+    /// With --write, also list the files that were already formatted
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Print a unified diff of the changes instead of the formatted output (exit with 1 if any)
+    #[arg(long, conflicts_with_all = ["write", "check"])]
+    pub diff: bool,
+
+    /// How to report results: text (messages on stderr), json (one array of
+    /// {path, changed, error} objects on stdout instead of any other output)
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Re-format the output and fail (without writing) if it changes again
+    // `--self-check` is kept as a hidden alias for corpus runs in CI
+    #[arg(long, alias = "self-check")]
+    pub verify: bool,
+
+    /// Print counts of headings, list items, links, images, code blocks and words of each formatted file to stderr
+    #[arg(long, conflicts_with = "embedded")]
+    pub stats: bool,
+
+    /// Number of files to process in parallel (output is still in file order)
+    #[arg(short, long, value_name = "N", default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: u32,
+
     /// Read from stdin
     #[arg(long)]
     pub stdin: bool,
 
-    /// Line width for wrapping (default: 80)
+    /// Path of the file being read from stdin, used in messages (the file itself isn't read)
+    #[arg(long, value_name = "PATH")]
+    pub stdin_filepath: Option<PathBuf>,
+
+    /// Line width for wrapping (default: 80); 0 puts each paragraph on one line, whatever --wrap says
     #[arg(long, default_value = "80")]
     pub width: usize,
 
@@ -75,10 +265,157 @@ pub struct Args {
     #[arg(long, value_enum, default_value = "preserve")]
     pub wrap: WrapMode,
 
-    /// How to number ordered lists: ascending (1, 2, 3), one (all 1.)
+    /// With --wrap always, avoid a single word alone on the last line of a paragraph
+    #[arg(long)]
+    pub no_widows: bool,
+
+    /// With --wrap always, split words longer than the line width (never links, code or math)
+    #[arg(long)]
+    pub break_long_words: bool,
+
+    /// Replace each tab in prose (not code) with N spaces before wrapping
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub expand_tabs: Option<u32>,
+
+    /// Marker for unordered list items: dash (-), star (*), plus (+)
+    #[arg(long = "bullet", value_enum, default_value = "dash")]
+    pub bullet: BulletStyle,
+
+    /// Indentation of list item content: tab, or 2 to 4 spaces (content column after a bullet)
+    #[arg(long, value_name = "STYLE", default_value = "2")]
+    pub indent: IndentStyle,
+
+    /// How to number ordered lists: ascending (1, 2, 3), one (all 1.), preserve (count up from the first number)
     #[arg(long = "ordered-list", value_enum, default_value = "ascending")]
     pub ordered_list: OrderedListMode,
 
+    /// Number each ordered list starts at with --ordered-list ascending (nested lists too), at most 999999999
+    #[arg(long, value_name = "N", default_value = "1", value_parser = clap::value_parser!(u64).range(0..=MAX_LIST_NUMBER))]
+    pub ordered_start: u64,
+
+    /// Character after ordered list numbers: period (1.), paren (1))
+    #[arg(long = "ordered-delimiter", value_enum, default_value = "period")]
+    pub ordered_delimiter: OrderedListDelimiter,
+
+    /// Right-align ordered list numbers so the periods line up (" 9.", "10.")
+    #[arg(long)]
+    pub align_ordered_markers: bool,
+
+    /// How to lay out tables: padded (align columns), compact (no padding)
+    #[arg(long = "table-style", value_enum, default_value = "padded")]
+    pub table_style: TableStyle,
+
+    /// Right-align table columns without explicit alignment that contain only numbers
+    #[arg(long)]
+    pub align_numeric_columns: bool,
+
+    /// Spacing before nested lists: tight (no blank line), loose (blank line)
+    #[arg(long = "nested-list-spacing", value_enum, default_value = "tight")]
+    pub nested_list_spacing: NestedListSpacing,
+
+    /// How to write horizontal rules, given as an example rule (e.g. "---", "***", "* * *")
+    #[arg(
+        long = "hr",
+        value_name = "RULE",
+        default_value = "---",
+        allow_hyphen_values = true
+    )]
+    pub horizontal_rule: HorizontalRule,
+
+    /// Blank lines after frontmatter: normalize (exactly one), preserve (keep as-is)
+    #[arg(long = "frontmatter-spacing", value_enum, default_value = "normalize")]
+    pub frontmatter_spacing: FrontmatterSpacing,
+
+    /// Re-indent YAML frontmatter consistently, keeping values as written (frontmatter with comments is left alone)
+    #[arg(long)]
+    pub normalize_frontmatter: bool,
+
+    /// Sort the top-level keys of YAML frontmatter alphabetically (implies --normalize-frontmatter)
+    #[arg(long)]
+    pub sort_frontmatter_keys: bool,
+
+    /// How to write headings: atx ("# Title"), setext-when-possible (underlined levels 1 and 2)
+    #[arg(long = "heading-style", value_enum, default_value = "atx")]
+    pub heading_style: HeadingStyle,
+
+    /// Whether ATX headings get closing hashes: open ("# Title"), closed ("# Title #")
+    #[arg(long = "heading-close", value_enum, default_value = "open")]
+    pub heading_close: HeadingClose,
+
+    /// Character for emphasis: asterisk ("*text*"), underscore ("_text_"; "*" inside words)
+    #[arg(long = "emphasis-marker", value_enum, default_value = "asterisk")]
+    pub emphasis_marker: EmphasisMarker,
+
+    /// Character for strong emphasis: asterisk ("**text**"), underscore ("__text__"; "**" inside words)
+    #[arg(long = "strong-marker", value_enum, default_value = "asterisk")]
+    pub strong_marker: EmphasisMarker,
+
+    /// Case of the x in checked task list items: lower ("[x]"), upper ("[X]")
+    #[arg(long = "task-marker-case", value_enum, default_value = "lower")]
+    pub task_marker_case: TaskMarkerCase,
+
+    /// Turn lines like "#Heading" (no space after the hashes) into headings
+    #[arg(long)]
+    pub fix_heading_spacing: bool,
+
+    /// Lowercase code block languages and expand aliases (js -> javascript, py -> python, sh -> bash)
+    #[arg(long)]
+    pub normalize_code_lang: bool,
+
+    /// How indented code blocks are written: fenced (converted to fences), preserve (kept indented)
+    #[arg(long = "code-block-style", value_enum, default_value = "fenced")]
+    pub code_block_style: CodeBlockStyle,
+
+    /// Strip trailing spaces and tabs from lines inside code blocks
+    #[arg(long)]
+    pub trim_code_trailing_whitespace: bool,
+
+    /// Write curly quotes and en/em dashes (for "--" and "---") in prose
+    #[arg(long, conflicts_with = "straight_punctuation")]
+    pub smart_punctuation: bool,
+
+    /// Write straight quotes and "--"/"---" for en/em dashes in prose
+    #[arg(long)]
+    pub straight_punctuation: bool,
+
+    /// How to write links: inline ([text](url)), reference ([text][1] with collected definitions),
+    /// preserve-reference (links written as references keep their labels)
+    #[arg(long = "link-style", value_enum, default_value = "inline")]
+    pub link_style: LinkStyle,
+
+    /// Where reference-style definitions go: document (at the end), section (before each H1/H2)
+    #[arg(long = "reference-placement", value_enum, default_value = "document")]
+    pub reference_placement: ReferencePlacement,
+
+    /// Convert paragraphs that are only bold/italic text into headings of this level
+    #[arg(long = "pseudo-headings", value_name = "LEVEL", value_parser = clap::value_parser!(u32).range(1..=6))]
+    pub pseudo_headings: Option<u32>,
+
+    /// Line endings to write: lf, crlf, preserve (keep each file's own)
+    #[arg(long = "line-ending", value_enum, default_value = "lf")]
+    pub line_ending: LineEnding,
+
+    /// Most blank lines kept in a row between top-level blocks (extra ones are removed)
+    #[arg(long, value_name = "N", default_value = "1")]
+    pub max_blank_lines: usize,
+
+    /// Don't end the output with a newline (for embedding into other files)
+    #[arg(long)]
+    pub no_trailing_newline: bool,
+
+    /// Format the Markdown in doc comments of source files in this language
+    /// (e.g. rust: `///` and `//!` in .rs files) instead of Markdown files
+    #[arg(long, value_enum, value_name = "LANG", conflicts_with_all = ["check_links", "strict"])]
+    pub embedded: Option<EmbeddedLanguage>,
+
+    /// Warn about relative links whose target file doesn't exist
+    #[arg(long)]
+    pub check_links: bool,
+
+    /// Fail on constructs the formatter can't normalize (raw HTML other than comments)
+    #[arg(long)]
+    pub strict: bool,
+
     /// Additional directories to exclude (node_modules, target, .git, vendor, dist, build are excluded by default)
     #[arg(long = "exclude", value_name = "DIR")]
     pub excludes: Vec<String>,
@@ -86,9 +423,63 @@ pub struct Args {
     /// Don't exclude any directories by default
     #[arg(long)]
     pub no_default_excludes: bool,
+
+    /// Also format files ignored by .gitignore, .ignore or the global git excludes
+    #[arg(long)]
+    pub no_gitignore: bool,
+
+    /// Print the files that would be formatted, one per line, without formatting them
+    #[arg(long, conflicts_with_all = ["write", "check", "diff"])]
+    pub list_files: bool,
+
+    /// File extensions treated as Markdown, comma-separated (e.g. md,markdown,mdx)
+    #[arg(
+        long,
+        value_name = "EXT",
+        value_delimiter = ',',
+        default_value = "md,markdown"
+    )]
+    pub extensions: Vec<String>,
+
+    /// Skip (and report) files larger than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Only process files changed since this git ref (per `git diff --name-only <REF>`)
+    #[arg(long, value_name = "REF")]
+    pub since: Option<String>,
 }
 
 impl Args {
+    /// These arguments with the settings of `config` in place of any option
+    /// that wasn't given on the command line (per `matches`)
+    pub fn with_config(&self, config: &Config, matches: &ArgMatches) -> Args {
+        let from_command_line =
+            |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let mut args = self.clone();
+        if let Some(width) = config.width.filter(|_| !from_command_line("width")) {
+            args.width = width;
+        }
+        if let Some(wrap) = config.wrap.filter(|_| !from_command_line("wrap")) {
+            args.wrap = wrap;
+        }
+        if let Some(mode) = config
+            .ordered_list
+            .filter(|_| !from_command_line("ordered_list"))
+        {
+            args.ordered_list = mode;
+        }
+        if let Some(bullet) = config.bullet.filter(|_| !from_command_line("bullet")) {
+            args.bullet = bullet;
+        }
+        if let Some(excludes) = config.exclude.as_ref() {
+            if !from_command_line("excludes") {
+                args.excludes = excludes.clone();
+            }
+        }
+        args
+    }
+
     /// Get the list of directories to exclude
     fn get_excludes(&self) -> Vec<String> {
         let mut excludes: Vec<String> = if self.no_default_excludes {
@@ -113,14 +504,40 @@ impl Args {
         false
     }
 
+    /// Lowercased extensions (without the dot) of the files to format: the
+    /// `--extensions`, or the source files of the `--embedded` language
+    fn extensions(&self) -> Vec<String> {
+        match self.embedded {
+            Some(language) => vec![language.extension().to_string()],
+            None => self
+                .extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// The extensions as listed in messages, e.g. `.md, .markdown`
+    fn extension_list(&self) -> String {
+        self.extensions()
+            .iter()
+            .map(|e| format!(".{}", e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     /// Resolve input paths to a list of markdown files or stdin
-    pub fn get_input_sources(&self) -> Result<Vec<InputSource>, String> {
+    pub fn get_input_sources(&self) -> Result<Vec<InputSource>, CliError> {
         if self.stdin || (self.paths.len() == 1 && self.paths[0] == "-") {
             return Ok(vec![InputSource::Stdin]);
         }
 
+        if self.stdin_filepath.is_some() {
+            return Err(CliError::StdinFilepathWithoutStdin);
+        }
+
         if self.paths.is_empty() {
-            return Err("No input provided. Use --stdin or specify file paths.".to_string());
+            return Err(CliError::NoInput);
         }
 
         let excludes = self.get_excludes();
@@ -129,28 +546,47 @@ impl Args {
         for pattern in &self.paths {
             let path = PathBuf::from(pattern);
 
-            if path.is_dir() {
-                // If it's a directory, find all .md files recursively
-                let glob_pattern = format!("{}/**/*.md", pattern);
+            if path.is_dir() && !self.no_gitignore {
+                // Walk the directory, skipping ignored files
+                self.walk_markdown_files(&path, &mut sources, &excludes);
+            } else if path.is_dir() {
+                // If it's a directory, find all matching files recursively
+                let glob_pattern = format!("{}/**/*", pattern);
                 self.collect_markdown_files(&glob_pattern, &mut sources, &excludes)?;
             } else if path.is_file() {
-                // Single file - must be .md
-                if Self::is_markdown_file(&path) {
-                    sources.push(InputSource::File(path));
-                } else {
-                    return Err(format!(
-                        "File '{}' is not a markdown file (.md)",
-                        path.display()
-                    ));
+                // Single file - must have the expected extension
+                if !self.is_markdown_file(&path) {
+                    return Err(CliError::NotMarkdown {
+                        path,
+                        extensions: self.extension_list(),
+                        embedded: self.embedded.is_some(),
+                    });
                 }
+                sources.push(InputSource::File(path));
             } else {
                 // Treat as glob pattern
                 self.collect_markdown_files(pattern, &mut sources, &excludes)?;
             }
         }
 
+        if let Some(git_ref) = &self.since {
+            // Nothing changed is a normal outcome here, not an error
+            let changed = changed_files_since(git_ref).map_err(CliError::Git)?;
+            sources.retain(|source| match source {
+                InputSource::File(path) => path
+                    .canonicalize()
+                    .map(|path| changed.contains(&path))
+                    .unwrap_or(false),
+                InputSource::Stdin => true,
+            });
+            return Ok(sources);
+        }
+
         if sources.is_empty() {
-            return Err("No markdown files found.".to_string());
+            return Err(CliError::NoFilesFound {
+                extensions: self.extension_list(),
+                embedded: self.embedded.is_some(),
+            });
         }
 
         Ok(sources)
@@ -161,15 +597,17 @@ impl Args {
         pattern: &str,
         sources: &mut Vec<InputSource>,
         excludes: &[String],
-    ) -> Result<(), String> {
-        let entries =
-            glob(pattern).map_err(|e| format!("Invalid glob pattern '{}': {}", pattern, e))?;
+    ) -> Result<(), CliError> {
+        let entries = glob(pattern).map_err(|source| CliError::InvalidGlob {
+            pattern: pattern.to_string(),
+            source,
+        })?;
 
         for entry in entries {
             match entry {
                 Ok(path) => {
                     if path.is_file()
-                        && Self::is_markdown_file(&path)
+                        && self.is_markdown_file(&path)
                         && !self.should_exclude(&path, excludes)
                     {
                         sources.push(InputSource::File(path));
@@ -184,13 +622,86 @@ impl Args {
         Ok(())
     }
 
-    fn is_markdown_file(path: &std::path::Path) -> bool {
+    /// Collect the files in `dir` that aren't ignored by `.gitignore`,
+    /// `.ignore` or the global git excludes, in the same order as a glob
+    fn walk_markdown_files(&self, dir: &Path, sources: &mut Vec<InputSource>, excludes: &[String]) {
+        let excluded = excludes.to_vec();
+        let walker = WalkBuilder::new(dir)
+            // Hidden files are only skipped when something ignores them
+            .hidden(false)
+            // Honor .gitignore files outside of git repositories too
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                let name = entry.file_name().to_string_lossy();
+                entry.depth() == 0 || !excluded.iter().any(|e| e == name.as_ref())
+            })
+            .build();
+
+        for entry in walker {
+            match entry {
+                Ok(entry) => {
+                    // Drop `./` so paths read the same as glob results
+                    let path: PathBuf = entry
+                        .path()
+                        .components()
+                        .filter(|c| *c != Component::CurDir)
+                        .collect();
+                    if path.is_file()
+                        && self.is_markdown_file(&path)
+                        && !self.should_exclude(&path, excludes)
+                    {
+                        sources.push(InputSource::File(path));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Warning: Could not read path: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Whether the file should be formatted (a Markdown file, or a source
+    /// file in `--embedded` mode)
+    fn is_markdown_file(&self, path: &std::path::Path) -> bool {
         path.extension()
-            .map(|ext| ext.to_string_lossy().to_lowercase() == "md")
+            .map(|ext| {
+                let ext = ext.to_string_lossy().to_lowercase();
+                self.extensions().contains(&ext)
+            })
             .unwrap_or(false)
     }
 }
 
+/// Absolute paths of files changed (and not deleted) since `git_ref`,
+/// according to git in the current directory
+fn changed_files_since(git_ref: &str) -> Result<HashSet<PathBuf>, String> {
+    let root = git(&["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(root.trim());
+    let changed = git(&["diff", "--name-only", "--diff-filter=d", git_ref, "--"])?;
+
+    Ok(changed
+        .lines()
+        .filter_map(|file| root.join(file).canonicalize().ok())
+        .collect())
+}
+
+/// Run a git command and return its stdout
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
 #[derive(Debug)]
 pub enum InputSource {
     File(PathBuf),
@@ -0,0 +1,69 @@
+//! Differences between a document and its formatted output
+//!
+//! Used by the CLI (`--verify` diffs and `--diff-stat` counts) and by
+//! editor integrations that want to highlight the parts of a file the
+//! formatter would change.
+
+use similar::{ChangeTag, DiffTag, TextDiff};
+
+/// A region of the original input that formatting changes.
+///
+/// Positions are 1-based; `end_line`/`end_column` point just past the last
+/// changed character. Pure insertions are empty ranges (start == end) at the
+/// point where the new lines go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeRange {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// Line ranges of `original` that differ in `formatted`
+pub fn changed_ranges(original: &str, formatted: &str) -> Vec<ChangeRange> {
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let diff = TextDiff::from_lines(original, formatted);
+
+    diff.ops()
+        .iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| {
+            let old = op.old_range();
+            let line = old.start + 1;
+            if old.is_empty() {
+                return ChangeRange {
+                    line,
+                    column: 1,
+                    end_line: line,
+                    end_column: 1,
+                };
+            }
+            let last = lines[old.end - 1].trim_end_matches(['\n', '\r']);
+            ChangeRange {
+                line,
+                column: 1,
+                end_line: old.end,
+                end_column: last.chars().count() + 1,
+            }
+        })
+        .collect()
+}
+
+/// Number of lines added and removed going from `old` to `new`
+pub fn diff_stat(old: &str, new: &str) -> (usize, usize) {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .fold((0, 0), |(added, removed), change| match change.tag() {
+            ChangeTag::Insert => (added + 1, removed),
+            ChangeTag::Delete => (added, removed + 1),
+            ChangeTag::Equal => (added, removed),
+        })
+}
+
+/// Render a unified line diff between two versions of a document
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_label, new_label)
+        .to_string()
+}
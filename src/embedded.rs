@@ -0,0 +1,143 @@
+//! Markdown embedded in other file types
+//!
+//! Finds runs of doc comments in source code, formats the Markdown inside
+//! them with the regular formatter and writes them back with the same
+//! comment markers and indentation. Everything outside those comments is
+//! left untouched.
+
+use std::str::FromStr;
+
+use crate::formatter::Formatter;
+use crate::parser::parse_markdown;
+
+/// Source language whose doc comments contain Markdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum EmbeddedLanguage {
+    /// Rust `///` and `//!` doc comments
+    Rust,
+}
+
+impl FromStr for EmbeddedLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rust" | "rs" => Ok(Self::Rust),
+            _ => Err(format!("Invalid embedded language: {}. Use 'rust'", s)),
+        }
+    }
+}
+
+impl EmbeddedLanguage {
+    /// File extension of source files in this language
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Rust => "rs",
+        }
+    }
+
+    /// Comment markers that introduce a Markdown line
+    fn markers(self) -> &'static [&'static str] {
+        match self {
+            Self::Rust => &["///", "//!"],
+        }
+    }
+
+    /// Split a line into (indentation, marker, text after the marker) if it
+    /// is a doc comment line
+    fn doc_comment(self, line: &str) -> Option<(&str, &'static str, &str)> {
+        let text = line.trim_start_matches([' ', '\t']);
+        let indent = &line[..line.len() - text.len()];
+        self.markers().iter().find_map(|&marker| {
+            let rest = text.strip_prefix(marker)?;
+            // `////...` is an ordinary comment, not a doc comment
+            if rest.starts_with('/') {
+                return None;
+            }
+            Some((indent, marker, rest))
+        })
+    }
+}
+
+/// Format the Markdown in the doc comments of `source`.
+///
+/// Each run of consecutive doc comment lines with the same indentation and
+/// marker is one Markdown document. Its common leading whitespace is removed
+/// before formatting, and lines are written back as `marker` + one space +
+/// text (just `marker` for blank lines).
+pub fn format_embedded(
+    formatter: &mut Formatter,
+    source: &str,
+    language: EmbeddedLanguage,
+) -> String {
+    let mut result = String::with_capacity(source.len());
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some((indent, marker, _)) = language.doc_comment(lines[i]) else {
+            result.push_str(lines[i]);
+            i += 1;
+            continue;
+        };
+
+        let mut block = Vec::new();
+        while let Some((line_indent, line_marker, text)) =
+            lines.get(i).and_then(|line| language.doc_comment(line))
+        {
+            if line_indent != indent || line_marker != marker {
+                break;
+            }
+            block.push(text.trim_end_matches(['\n', '\r']));
+            i += 1;
+        }
+
+        let ends_with_newline = lines[i - 1].ends_with('\n');
+        let line_ending = if lines[i - 1].ends_with("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        let formatted = formatter.format(parse_markdown(&unindent(&block)));
+        let formatted = formatted.trim_end_matches('\n');
+        let formatted_lines: Vec<&str> = if formatted.is_empty() {
+            vec![""]
+        } else {
+            formatted.split('\n').collect()
+        };
+
+        for (n, line) in formatted_lines.iter().enumerate() {
+            result.push_str(indent);
+            result.push_str(marker);
+            if !line.is_empty() {
+                result.push(' ');
+                result.push_str(line);
+            }
+            if n + 1 < formatted_lines.len() || ends_with_newline {
+                result.push_str(line_ending);
+            }
+        }
+    }
+
+    result
+}
+
+/// Join comment lines into one document, removing the leading whitespace
+/// shared by all non-blank lines
+fn unindent(lines: &[&str]) -> String {
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(line.get(common..).unwrap_or(""));
+        text.push('\n');
+    }
+    text
+}
@@ -1,14 +1,31 @@
-use pulldown_cmark::{CowStr, Event, Tag};
+use pulldown_cmark::{Alignment, BlockQuoteKind, CowStr, Event, LinkType, Parser, Tag, TagEnd};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::str::FromStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::parser::{
+    blank_lines_before_blocks, extract_frontmatter, fix_atx_heading_spacing,
+    has_ignore_file_directive, heading_level, ignored_blocks, normalize_line_endings,
+    normalize_yaml_frontmatter, parse_markdown, parser_options, uses_crlf, IGNORE_DIRECTIVE,
+};
+use crate::sink::{OutputSink, WriterSink};
 
 /// How to handle prose wrapping
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum WrapMode {
     /// Wrap prose if it exceeds the print width
     Always,
     /// Un-wrap each block of prose into one line
     Never,
-    /// Do nothing, leave prose as-is (default)
+    /// Keep the existing line breaks (default). Whitespace within a line is
+    /// still normalized: runs of spaces and tabs become one space, and
+    /// leading/trailing spaces are dropped (two or more trailing spaces are a
+    /// hard break and stay `  `). Files that must stay byte for byte can opt
+    /// out with `<!-- mdfmt-ignore-file -->`.
     #[default]
     Preserve,
 }
@@ -31,14 +48,16 @@ impl FromStr for WrapMode {
 
 /// How to handle ordered list numbering
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum OrderedListMode {
-    /// Renumber items sequentially (1, 2, 3, ...) - default
+    /// Renumber items sequentially (1, 2, 3, ..., or from
+    /// [`Formatter::ordered_start`]) - default
     #[default]
     Ascending,
     /// Use 1. for all items
     One,
-    // Note: Preserve mode is not currently possible because pulldown-cmark
-    // doesn't provide the original item numbers in the event stream
+    /// Count up from the list's original start number (5, 6, 7, ...)
+    Preserve,
 }
 
 impl FromStr for OrderedListMode {
@@ -48,14 +67,573 @@ impl FromStr for OrderedListMode {
         match s.to_lowercase().as_str() {
             "ascending" => Ok(Self::Ascending),
             "one" => Ok(Self::One),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "Invalid ordered list mode: '{}'. Expected: ascending, one, preserve",
+                s
+            )),
+        }
+    }
+}
+
+/// How to lay out table columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TableStyle {
+    /// Pad cells so every column has equal width (default)
+    #[default]
+    Padded,
+    /// Single spaces around cell content, no column padding
+    Compact,
+}
+
+impl FromStr for TableStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "padded" => Ok(Self::Padded),
+            "compact" => Ok(Self::Compact),
+            _ => Err(format!(
+                "Invalid table style: '{}'. Expected: padded, compact",
+                s
+            )),
+        }
+    }
+}
+
+/// Marker used for unordered list items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum BulletStyle {
+    /// `- item` (default)
+    #[default]
+    Dash,
+    /// `* item`
+    Star,
+    /// `+ item`
+    Plus,
+}
+
+impl BulletStyle {
+    /// The marker character
+    pub fn marker(self) -> char {
+        match self {
+            Self::Dash => '-',
+            Self::Star => '*',
+            Self::Plus => '+',
+        }
+    }
+}
+
+impl FromStr for BulletStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dash" | "-" => Ok(Self::Dash),
+            "star" | "*" => Ok(Self::Star),
+            "plus" | "+" => Ok(Self::Plus),
+            _ => Err(format!(
+                "Invalid bullet style: '{}'. Expected: dash, star, plus (or -, *, +)",
+                s
+            )),
+        }
+    }
+}
+
+/// Character after the number of ordered list items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OrderedListDelimiter {
+    /// `1. item` (default)
+    #[default]
+    Period,
+    /// `1) item`
+    Paren,
+}
+
+impl OrderedListDelimiter {
+    /// The delimiter character
+    pub fn delimiter(self) -> char {
+        match self {
+            Self::Period => '.',
+            Self::Paren => ')',
+        }
+    }
+}
+
+impl FromStr for OrderedListDelimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "period" | "." => Ok(Self::Period),
+            "paren" | ")" => Ok(Self::Paren),
+            _ => Err(format!(
+                "Invalid ordered list delimiter: '{}'. Expected: period, paren (or ., ))",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether a nested list is separated from its parent item's text by a blank
+/// line. Under CommonMark that blank line makes the parent list loose, so with
+/// `Loose` every list holding a sublist is written loose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum NestedListSpacing {
+    /// Sublist starts on the line right after the parent item (default);
+    /// in a loose list it's set off by a blank line like the item's other blocks
+    #[default]
+    Tight,
+    /// Blank line between the parent item's text and the sublist, and
+    /// between all items of the parent list
+    Loose,
+}
+
+impl FromStr for NestedListSpacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tight" => Ok(Self::Tight),
+            "loose" => Ok(Self::Loose),
+            _ => Err(format!(
+                "Invalid nested list spacing: '{}'. Expected: tight, loose",
+                s
+            )),
+        }
+    }
+}
+
+/// How links are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LinkStyle {
+    /// `[text](url)` (default)
+    #[default]
+    Inline,
+    /// `[text][n]` with collected `[n]: url` definitions
+    Reference,
+    /// Links written as references in the source keep their labels
+    /// (`[text][label]`, `[text][]`, `[text]`) and their definitions are
+    /// collected; inline links stay inline
+    PreserveReference,
+}
+
+impl FromStr for LinkStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "inline" => Ok(Self::Inline),
+            "reference" => Ok(Self::Reference),
+            "preserve-reference" => Ok(Self::PreserveReference),
+            _ => Err(format!(
+                "Invalid link style: '{}'. Expected: inline, reference, preserve-reference",
+                s
+            )),
+        }
+    }
+}
+
+/// Where collected reference definitions are written (reference link style only)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ReferencePlacement {
+    /// All definitions at the end of the document (default)
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "document"))]
+    DocumentEnd,
+    /// Definitions at the end of each top-level section (before the next H1/H2)
+    #[cfg_attr(feature = "cli", value(name = "section"))]
+    SectionEnd,
+}
+
+impl FromStr for ReferencePlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "document" => Ok(Self::DocumentEnd),
+            "section" => Ok(Self::SectionEnd),
+            _ => Err(format!(
+                "Invalid reference placement: '{}'. Expected: document, section",
+                s
+            )),
+        }
+    }
+}
+
+/// How the gap between frontmatter and the first block is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FrontmatterSpacing {
+    /// Exactly one blank line after the frontmatter (default)
+    #[default]
+    Normalize,
+    /// Keep the number of blank lines the source had, up to the
+    /// `max_blank_lines` limit
+    Preserve,
+}
+
+impl FromStr for FrontmatterSpacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "normalize" => Ok(Self::Normalize),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "Invalid frontmatter spacing: '{}'. Expected: normalize, preserve",
+                s
+            )),
+        }
+    }
+}
+
+/// How quotes and dashes in prose are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Punctuation {
+    /// Leave quotes and dashes as written (default)
+    #[default]
+    Keep,
+    /// Curly quotes, `--` as an en dash and `---` as an em dash
+    Smart,
+    /// Straight quotes, en/em dashes as `--`/`---`
+    Straight,
+}
+
+impl FromStr for Punctuation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(Self::Keep),
+            "smart" => Ok(Self::Smart),
+            "straight" => Ok(Self::Straight),
             _ => Err(format!(
-                "Invalid ordered list mode: '{}'. Expected: ascending, one",
+                "Invalid punctuation: '{}'. Expected: keep, smart, straight",
                 s
             )),
         }
     }
 }
 
+/// Writes a document's body to a sink, preceded by its frontmatter once
+/// there is any body, and with the chosen line endings
+struct DocumentSink<'s, S: ?Sized> {
+    sink: &'s mut S,
+    /// Frontmatter not written yet
+    frontmatter: Option<String>,
+    crlf: bool,
+}
+
+impl<S: OutputSink + ?Sized> DocumentSink<'_, S> {
+    fn write(&mut self, s: &str) {
+        if self.crlf {
+            self.sink.push_str(&s.replace('\n', "\r\n"));
+        } else {
+            self.sink.push_str(s);
+        }
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for DocumentSink<'_, S> {
+    fn push_str(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        if let Some(frontmatter) = self.frontmatter.take() {
+            self.write(&frontmatter);
+        }
+        self.write(s);
+    }
+}
+
+/// How headings are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum HeadingStyle {
+    /// `#` prefixed headings for every level (default)
+    #[default]
+    Atx,
+    /// Underlined (`===` / `---`) headings for levels 1 and 2 outside list
+    /// items and blockquotes, ATX for the rest
+    #[cfg_attr(feature = "cli", value(alias = "setext"))]
+    SetextWhenPossible,
+}
+
+impl FromStr for HeadingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "atx" => Ok(Self::Atx),
+            "setext" | "setext-when-possible" => Ok(Self::SetextWhenPossible),
+            _ => Err(format!(
+                "Invalid heading style: '{}'. Expected: atx, setext-when-possible",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether ATX headings get a closing sequence of `#`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum HeadingClose {
+    /// `# Title` (default)
+    #[default]
+    Open,
+    /// `# Title #`, with as many closing `#`s as opening ones
+    Closed,
+}
+
+impl FromStr for HeadingClose {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "open" => Ok(Self::Open),
+            "closed" => Ok(Self::Closed),
+            _ => Err(format!(
+                "Invalid heading close: '{}'. Expected: open, closed",
+                s
+            )),
+        }
+    }
+}
+
+/// Character used for emphasis (`*text*`) or strong emphasis (`**text**`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum EmphasisMarker {
+    /// `*text*` and `**text**` (default)
+    #[default]
+    Asterisk,
+    /// `_text_` and `__text__`, except inside words, where only `*` works
+    Underscore,
+}
+
+impl FromStr for EmphasisMarker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asterisk" | "*" => Ok(Self::Asterisk),
+            "underscore" | "_" => Ok(Self::Underscore),
+            _ => Err(format!(
+                "Invalid emphasis marker: '{}'. Expected: asterisk, underscore",
+                s
+            )),
+        }
+    }
+}
+
+/// Case of the `x` in checked task list items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TaskMarkerCase {
+    /// `- [x] task` (default)
+    #[default]
+    Lower,
+    /// `- [X] task`
+    Upper,
+}
+
+impl FromStr for TaskMarkerCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lower" => Ok(Self::Lower),
+            "upper" => Ok(Self::Upper),
+            _ => Err(format!(
+                "Invalid task marker case: '{}'. Expected: lower, upper",
+                s
+            )),
+        }
+    }
+}
+
+/// How indented code blocks are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CodeBlockStyle {
+    /// Convert indented code blocks to fenced ones (default)
+    #[default]
+    Fenced,
+    /// Keep indented code blocks indented by four spaces
+    Preserve,
+}
+
+impl FromStr for CodeBlockStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fenced" => Ok(Self::Fenced),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "Invalid code block style: '{}'. Expected: fenced, preserve",
+                s
+            )),
+        }
+    }
+}
+
+/// Line endings of the formatted output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LineEnding {
+    /// `\n` line endings (default)
+    #[default]
+    Lf,
+    /// `\r\n` line endings
+    Crlf,
+    /// Whatever the input uses (CRLF if its first line ends in `\r\n`)
+    Preserve,
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "Invalid line ending: '{}'. Expected: lf, crlf, preserve",
+                s
+            )),
+        }
+    }
+}
+
+/// How thematic breaks (horizontal rules) are written, e.g. `---`, `***`,
+/// `* * *` or `__________`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HorizontalRule {
+    /// Rule character: `-`, `*` or `_`
+    pub marker: char,
+    /// How many times the character is repeated (at least 3)
+    pub count: usize,
+    /// Whether the characters are separated by single spaces
+    pub spaced: bool,
+}
+
+impl Default for HorizontalRule {
+    fn default() -> Self {
+        Self {
+            marker: '-',
+            count: 3,
+            spaced: false,
+        }
+    }
+}
+
+impl std::fmt::Display for HorizontalRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = if self.spaced { " " } else { "" };
+        let marker = self.marker.to_string();
+        write!(f, "{}", vec![marker.as_str(); self.count].join(separator))
+    }
+}
+
+impl FromStr for HorizontalRule {
+    type Err = String;
+
+    /// Parse an example rule such as `---`, `* * *` or `_____`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid horizontal rule: '{}'. Expected 3 or more of the same '-', '*' or '_', optionally separated by single spaces",
+                s
+            )
+        };
+
+        let marker = s.chars().next().ok_or_else(invalid)?;
+        if !matches!(marker, '-' | '*' | '_') {
+            return Err(invalid());
+        }
+        let spaced = s.contains(' ');
+        let parts: Vec<&str> = if spaced {
+            s.split(' ').collect()
+        } else {
+            s.matches(marker).collect()
+        };
+        if parts.len() < 3
+            || parts.iter().any(|p| *p != marker.to_string())
+            || (!spaced && s.len() != parts.len())
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            marker,
+            count: parts.len(),
+            spaced,
+        })
+    }
+}
+
+/// How list item content and nested lists are indented
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    /// Content starts this many columns after the start of the marker, or
+    /// one space after the marker if that's wider (`- item`, `10. item`)
+    Spaces(usize),
+    /// A tab after the marker and one tab per level for nested content.
+    /// Inside blockquotes, where tab stops don't line up with `> `, items
+    /// fall back to a single space after the marker.
+    Tab,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        Self::Spaces(2)
+    }
+}
+
+impl std::fmt::Display for IndentStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spaces(width) => write!(f, "{}", width),
+            Self::Tab => write!(f, "tab"),
+        }
+    }
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    /// Parse `tab`, or a number of spaces from 2 to 4
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tab" => Ok(Self::Tab),
+            width => match width.parse() {
+                Ok(width @ 2..=4) => Ok(Self::Spaces(width)),
+                _ => Err(format!(
+                    "Invalid indent style: '{}'. Expected: tab, or 2 to 4 spaces",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Stands in for the spaces that wrapping must not break at (in math
+/// formulas, links and images) while their paragraph is wrapped
+const KEPT_SPACE: char = '\u{FDD0}';
+
+/// Largest ordered list number (CommonMark allows at most nine digits)
+pub(crate) const MAX_LIST_NUMBER: u64 = 999_999_999;
+
 /// Represents an inline element that can be buffered before wrapping
 #[derive(Debug, Clone)]
 enum InlineElement {
@@ -77,8 +655,21 @@ enum InlineElement {
     StrikethroughEnd,
     /// Start of link ([)
     LinkStart,
-    /// End of link with URL](url)
-    LinkEnd(String),
+    /// End of link with URL and optional title](url "title")
+    LinkEnd { url: String, title: String },
+    /// End of reference-style link or image: `][n]`, `][label]`, `][]` or
+    /// `]`. For `][]` and `]`, the label the text has to match: if
+    /// formatting changed the text, `][label]` is written instead.
+    ReferenceLinkEnd {
+        suffix: String,
+        label: Option<String>,
+    },
+    /// Footnote reference [^label]
+    FootnoteReference(String),
+    /// Task list checkbox (`[x]` or `[ ]`), kept on the line of the text after it
+    TaskMarker(bool),
+    /// Inline math ($...$) or display math ($$...$$)
+    Math { content: String, display: bool },
     /// Start of image (![)
     ImageStart,
     /// End of image with URL and optional title](url "title")
@@ -93,38 +684,387 @@ enum InlineElement {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Context {
     Paragraph,
-    Heading { level: u32 },
-    List { ordered: bool, item_count: usize },
+    Heading {
+        level: u32,
+    },
+    List {
+        ordered: bool,
+        item_count: usize,
+        /// Number of the first item as written in the source
+        start: u64,
+        /// Width the item numbers are right-aligned to (0 when not aligned)
+        number_width: usize,
+        /// Items are separated by blank lines
+        loose: bool,
+    },
     ListItem,
+    FootnoteDefinition,
+    DefinitionList {
+        /// Definitions hold paragraphs, so each is set off by a blank line
+        loose: bool,
+    },
+    /// Term of a definition list, written on one line
+    DefinitionTitle,
+    /// Definition of a term, after `: `
+    Definition,
     Blockquote,
     CodeBlock,
     Strong,
     Emphasis,
     Strikethrough,
-    Link { url: String },
-    Image { url: String, title: String },
+    Link {
+        url: String,
+        title: String,
+        /// What follows the link text when written as a reference (`][1]`),
+        /// and for `][]` and `]` the label the text has to match
+        reference: Option<(String, Option<String>)>,
+    },
+    /// `<url>` or `<address>` link, written as-is
+    Autolink,
+    Image {
+        url: String,
+        title: String,
+        /// What follows the description when written as a reference, as
+        /// for links
+        reference: Option<(String, Option<String>)>,
+    },
+    Table,
+    TableHead,
+    TableRow,
+    TableCell,
+}
+
+/// Rows and alignments buffered for a table until its end tag
+#[derive(Debug, Default)]
+struct TableState {
+    /// Column alignments from the separator row
+    alignments: Vec<Alignment>,
+    /// Rendered cells per row (the first row is the header)
+    rows: Vec<Vec<String>>,
+    /// Cells of the row currently being built
+    current_row: Vec<String>,
+}
+
+/// Info string and content buffered for a code block until its end tag,
+/// when the fence length can be chosen
+#[derive(Debug, Default)]
+struct CodeBlockState {
+    /// Language / info string after the opening fence
+    info: String,
+    /// Lines of code, unprefixed
+    content: String,
+    /// Write the block indented by four spaces instead of fenced
+    indented: bool,
 }
 
 /// Main formatter struct
 pub struct Formatter {
-    /// Final output
+    /// Output not yet handed to the sink. Trailing whitespace is held back
+    /// here so block handlers can look at how the output currently ends.
     output: String,
+    /// Whether anything has been handed to the sink for this document
+    flushed_output: bool,
+    /// Byte ranges of `output` holding code block and raw HTML lines (in
+    /// order), which are handed over as-is instead of having their blank
+    /// lines collapsed
+    verbatim: Vec<Range<usize>>,
+    /// Newlines at the end of what has been handed over so far
+    trailing_newlines: usize,
     /// Target line width
     line_width: usize,
     /// How to handle prose wrapping
     wrap_mode: WrapMode,
     /// How to handle ordered list numbering
     ordered_list_mode: OrderedListMode,
+    /// First number of each list in ascending numbering
+    ordered_start: u64,
+    /// How to lay out table columns
+    table_style: TableStyle,
+    /// Blank line (or not) before nested lists
+    nested_list_spacing: NestedListSpacing,
+    /// Convert bold/italic-only paragraphs into headings of this level
+    pseudo_heading_level: Option<u32>,
     /// Buffer for accumulating inline elements before wrapping
     inline_buffer: Vec<InlineElement>,
     /// Context stack for tracking nesting
     context_stack: Vec<Context>,
     /// Current list nesting depth
     list_depth: usize,
-    /// Current blockquote nesting depth
-    blockquote_depth: usize,
-    /// Are we inside a code block?
-    in_code_block: bool,
+    /// Content column of each open list item (innermost last)
+    item_indents: Vec<usize>,
+    /// Line prefix of each open blockquote (innermost last), from the line
+    /// start through its `> `, and how many item indents were open outside it
+    blockquotes: Vec<(String, usize)>,
+    /// A blockquote was just opened on a fresh line (or after its GFM alert
+    /// tag); its first block follows directly, without a blank line
+    at_blockquote_start: bool,
+    /// Code block being buffered, if inside one
+    code_block: Option<CodeBlockState>,
+    /// While a footnote definition is rendered into `output`, the document
+    /// output (and its verbatim ranges) set aside until it ends
+    document_output: Option<(String, Vec<Range<usize>>)>,
+    /// Rendered footnote definitions with their verbatim ranges, written at
+    /// the end of the document
+    footnotes: Vec<(String, Vec<Range<usize>>)>,
+    /// Table being buffered, if inside one
+    table: Option<TableState>,
+    /// How links are written
+    link_style: LinkStyle,
+    /// Where reference definitions are written
+    reference_placement: ReferencePlacement,
+    /// Reference definitions (label, url, title) in order of first use;
+    /// numbered labels are 1-based
+    references: Vec<(String, String, String)>,
+    /// Lookup from (url, title) to reference id, so duplicates share one
+    reference_ids: HashMap<(String, String), usize>,
+    /// Number of reference definitions already written
+    references_written: usize,
+    /// Blank lines between frontmatter and the body
+    frontmatter_spacing: FrontmatterSpacing,
+    /// Re-indent YAML frontmatter
+    normalize_frontmatter: bool,
+    /// Sort the top-level keys of YAML frontmatter (implies normalizing it)
+    sort_frontmatter_keys: bool,
+    /// Right-align table columns without explicit alignment whose cells are all numbers
+    align_numeric_columns: bool,
+    /// Set right after a list marker is written, until the item's first block starts
+    at_item_start: bool,
+    /// The previous event closed a list
+    after_list: bool,
+    /// How thematic breaks are written
+    horizontal_rule: HorizontalRule,
+    /// Avoid a single word on the last line of wrapped paragraphs (always mode)
+    no_widows: bool,
+    /// Split plain words longer than the line width (always mode)
+    break_long_words: bool,
+    /// Spaces each tab in prose text is replaced with
+    expand_tabs: Option<usize>,
+    /// End non-empty output with a newline (off when embedding the result inline)
+    trailing_newline: bool,
+    /// Turn `#Heading` lines into headings before parsing (`format_document` only)
+    fix_heading_spacing: bool,
+    /// Strip trailing spaces and tabs from code block lines
+    trim_code_trailing_whitespace: bool,
+    /// Fenced or indented code blocks
+    code_block_style: CodeBlockStyle,
+    /// Lowercase code block languages and replace aliases
+    normalize_code_lang: bool,
+    /// Language aliases and their canonical names, used with `normalize_code_lang`
+    code_lang_aliases: HashMap<String, String>,
+    /// Line endings written by `format_document`
+    line_ending: LineEnding,
+    /// How quotes and dashes in prose text are written
+    punctuation: Punctuation,
+    /// Marker for unordered list items
+    bullet_style: BulletStyle,
+    /// Spaces or tabs for list item content
+    indent_style: IndentStyle,
+    /// Character after ordered list item numbers
+    ordered_list_delimiter: OrderedListDelimiter,
+    /// ATX or setext headings
+    heading_style: HeadingStyle,
+    /// Open or closed ATX headings
+    heading_close: HeadingClose,
+    /// Character for emphasis
+    emphasis_marker: EmphasisMarker,
+    /// Character for strong emphasis
+    strong_marker: EmphasisMarker,
+    /// `[x]` or `[X]` for checked task list items
+    task_marker_case: TaskMarkerCase,
+    /// Column (in characters) where the text of the current heading starts
+    /// on its line
+    heading_column: usize,
+    /// Right-align ordered list numbers so the periods line up
+    align_ordered_markers: bool,
+    /// Item counts of the lists not started yet, in document order
+    list_item_counts: VecDeque<usize>,
+    /// Whether each list (or definition list) not started yet is loose, in
+    /// document order
+    loose_lists: VecDeque<bool>,
+    /// Most blank lines kept in a row between top-level blocks
+    max_blank_lines: usize,
+    /// Blank lines before each top-level block in the source, in document
+    /// order (only collected by `format_document` when more than one blank
+    /// line may be kept)
+    source_blank_lines: VecDeque<usize>,
+    /// Source text of the blocks after each `<!-- mdfmt-ignore -->`, in
+    /// document order (only collected by `format_document`)
+    ignored_blocks: VecDeque<String>,
+    /// A top-level `<!-- mdfmt-ignore -->` was just written
+    ignore_next_block: bool,
+    /// Nesting depth inside the ignored block whose events are being skipped
+    ignored_depth: usize,
+}
+
+/// Builds a [`Formatter`] one option at a time. Options that aren't set keep
+/// their defaults (80 columns, preserved wrapping, ascending numbering).
+///
+/// ```
+/// use md_formatter::{BulletStyle, Formatter, WrapMode};
+///
+/// let mut formatter = Formatter::builder()
+///     .width(60)
+///     .wrap_mode(WrapMode::Always)
+///     .bullet_style(BulletStyle::Star)
+///     .build();
+/// assert_eq!(formatter.format_document("- one\n- two"), "* one\n* two\n");
+/// ```
+pub struct FormatterBuilder {
+    formatter: Formatter,
+}
+
+impl Default for FormatterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FormatterBuilder {
+    /// A builder with every option at its default
+    pub fn new() -> Self {
+        Self {
+            formatter: Formatter {
+                output: String::new(),
+                flushed_output: false,
+                verbatim: Vec::new(),
+                trailing_newlines: 0,
+                line_width: 80,
+                wrap_mode: WrapMode::default(),
+                ordered_list_mode: OrderedListMode::default(),
+                ordered_start: 1,
+                table_style: TableStyle::default(),
+                nested_list_spacing: NestedListSpacing::default(),
+                pseudo_heading_level: None,
+                inline_buffer: Vec::new(),
+                context_stack: Vec::new(),
+                list_depth: 0,
+                item_indents: Vec::new(),
+                blockquotes: Vec::new(),
+                at_blockquote_start: false,
+                code_block: None,
+                document_output: None,
+                footnotes: Vec::new(),
+                table: None,
+                link_style: LinkStyle::default(),
+                reference_placement: ReferencePlacement::default(),
+                references: Vec::new(),
+                reference_ids: HashMap::new(),
+                references_written: 0,
+                frontmatter_spacing: FrontmatterSpacing::default(),
+                normalize_frontmatter: false,
+                sort_frontmatter_keys: false,
+                align_numeric_columns: false,
+                at_item_start: false,
+                after_list: false,
+                horizontal_rule: HorizontalRule::default(),
+                no_widows: false,
+                expand_tabs: None,
+                break_long_words: false,
+                trailing_newline: true,
+                fix_heading_spacing: false,
+                trim_code_trailing_whitespace: false,
+                normalize_code_lang: false,
+                code_lang_aliases: default_code_lang_aliases(),
+                line_ending: LineEnding::default(),
+                punctuation: Punctuation::default(),
+                bullet_style: BulletStyle::default(),
+                indent_style: IndentStyle::default(),
+                ordered_list_delimiter: OrderedListDelimiter::default(),
+                heading_style: HeadingStyle::default(),
+                heading_close: HeadingClose::default(),
+                emphasis_marker: EmphasisMarker::default(),
+                strong_marker: EmphasisMarker::default(),
+                task_marker_case: TaskMarkerCase::default(),
+                code_block_style: CodeBlockStyle::default(),
+                heading_column: 0,
+                align_ordered_markers: false,
+                list_item_counts: VecDeque::new(),
+                loose_lists: VecDeque::new(),
+                max_blank_lines: 1,
+                source_blank_lines: VecDeque::new(),
+                ignored_blocks: VecDeque::new(),
+                ignore_next_block: false,
+                ignored_depth: 0,
+            },
+        }
+    }
+
+    /// Set the target line width. A width of 0 means no wrapping at all:
+    /// every paragraph is written on one line, whatever the wrap mode.
+    pub fn width(mut self, line_width: usize) -> Self {
+        self.formatter.line_width = line_width;
+        self
+    }
+
+    /// Set how prose is wrapped
+    pub fn wrap_mode(mut self, wrap_mode: WrapMode) -> Self {
+        self.formatter.wrap_mode = wrap_mode;
+        self
+    }
+
+    /// Set how ordered lists are numbered
+    pub fn ordered_list_mode(mut self, ordered_list_mode: OrderedListMode) -> Self {
+        self.formatter.ordered_list_mode = ordered_list_mode;
+        self
+    }
+
+    /// Finish building
+    pub fn build(mut self) -> Formatter {
+        if self.formatter.line_width == 0 {
+            self.formatter.wrap_mode = WrapMode::Never;
+        }
+        self.formatter
+    }
+}
+
+/// Builder methods that set the formatter option of the same name
+macro_rules! builder_options {
+    ($($name:ident: $ty:ty,)*) => {
+        impl FormatterBuilder {
+            $(
+                #[doc = concat!("See [`Formatter::", stringify!($name), "`]")]
+                pub fn $name(mut self, value: $ty) -> Self {
+                    self.formatter = self.formatter.$name(value);
+                    self
+                }
+            )*
+        }
+    };
+}
+
+builder_options! {
+    table_style: TableStyle,
+    bullet_style: BulletStyle,
+    indent_style: IndentStyle,
+    nested_list_spacing: NestedListSpacing,
+    pseudo_heading_level: Option<u32>,
+    link_style: LinkStyle,
+    reference_placement: ReferencePlacement,
+    frontmatter_spacing: FrontmatterSpacing,
+    normalize_frontmatter: bool,
+    sort_frontmatter_keys: bool,
+    align_numeric_columns: bool,
+    horizontal_rule: HorizontalRule,
+    no_widows: bool,
+    break_long_words: bool,
+    expand_tabs: Option<usize>,
+    trailing_newline: bool,
+    fix_heading_spacing: bool,
+    ordered_list_delimiter: OrderedListDelimiter,
+    ordered_start: u64,
+    heading_style: HeadingStyle,
+    heading_close: HeadingClose,
+    emphasis_marker: EmphasisMarker,
+    strong_marker: EmphasisMarker,
+    task_marker_case: TaskMarkerCase,
+    code_block_style: CodeBlockStyle,
+    trim_code_trailing_whitespace: bool,
+    normalize_code_lang: bool,
+    code_lang_aliases: HashMap<String, String>,
+    punctuation: Punctuation,
+    align_ordered_markers: bool,
+    line_ending: LineEnding,
+    max_blank_lines: usize,
 }
 
 impl Formatter {
@@ -144,106 +1084,731 @@ impl Formatter {
         wrap_mode: WrapMode,
         ordered_list_mode: OrderedListMode,
     ) -> Self {
-        Self {
-            output: String::new(),
-            line_width,
-            wrap_mode,
-            ordered_list_mode,
-            inline_buffer: Vec::new(),
-            context_stack: Vec::new(),
-            list_depth: 0,
-            blockquote_depth: 0,
-            in_code_block: false,
+        FormatterBuilder::new()
+            .width(line_width)
+            .wrap_mode(wrap_mode)
+            .ordered_list_mode(ordered_list_mode)
+            .build()
+    }
+
+    /// Start building a formatter; see [`FormatterBuilder`]
+    pub fn builder() -> FormatterBuilder {
+        FormatterBuilder::new()
+    }
+
+    /// Set how table columns are laid out
+    pub fn table_style(mut self, table_style: TableStyle) -> Self {
+        self.table_style = table_style;
+        self
+    }
+
+    /// Set the marker used for unordered list items at every nesting level
+    pub fn bullet_style(mut self, bullet_style: BulletStyle) -> Self {
+        self.bullet_style = bullet_style;
+        self
+    }
+
+    /// Set whether nested lists are separated from their parent item by a
+    /// blank line (which also puts blank lines between the parent's items)
+    pub fn nested_list_spacing(mut self, spacing: NestedListSpacing) -> Self {
+        self.nested_list_spacing = spacing;
+        self
+    }
+
+    /// Convert paragraphs consisting solely of a strong (or emphasis) span,
+    /// such as `**Section Title**`, into real headings of the given level (1-6).
+    /// Off (`None`) by default since it changes document structure.
+    pub fn pseudo_heading_level(mut self, level: Option<u32>) -> Self {
+        self.pseudo_heading_level = level.map(|l| l.clamp(1, 6));
+        self
+    }
+
+    /// Set how links are written (inline or reference-style)
+    pub fn link_style(mut self, link_style: LinkStyle) -> Self {
+        self.link_style = link_style;
+        self
+    }
+
+    /// Set where reference definitions are written when using reference-style links
+    pub fn reference_placement(mut self, placement: ReferencePlacement) -> Self {
+        self.reference_placement = placement;
+        self
+    }
+
+    /// Set whether the blank lines after frontmatter are normalized to one or kept as written
+    pub fn frontmatter_spacing(mut self, spacing: FrontmatterSpacing) -> Self {
+        self.frontmatter_spacing = spacing;
+        self
+    }
+
+    /// Re-indent YAML frontmatter consistently, keeping every value as
+    /// written (see [`normalize_yaml_frontmatter`]). TOML frontmatter,
+    /// invalid YAML, YAML that isn't a mapping and YAML with comments are
+    /// kept as written. Needs the `yaml` feature. Off by default.
+    pub fn normalize_frontmatter(mut self, enabled: bool) -> Self {
+        self.normalize_frontmatter = enabled;
+        self
+    }
+
+    /// Sort the top-level keys of YAML frontmatter alphabetically. This
+    /// normalizes the frontmatter too. Off by default.
+    pub fn sort_frontmatter_keys(mut self, enabled: bool) -> Self {
+        self.sort_frontmatter_keys = enabled;
+        self
+    }
+
+    /// Right-align table columns that have no explicit alignment and only
+    /// numbers in their body cells. The alignment is written out (`--:`),
+    /// so the output keeps it when formatted again. Off by default.
+    pub fn align_numeric_columns(mut self, enabled: bool) -> Self {
+        self.align_numeric_columns = enabled;
+        self
+    }
+
+    /// Set how horizontal rules are written (default `---`). A `---` rule at
+    /// the very start of the output is written as `----` so it can't be read
+    /// as the opening of frontmatter.
+    pub fn horizontal_rule(mut self, rule: HorizontalRule) -> Self {
+        self.horizontal_rule = rule;
+        self
+    }
+
+    /// When reflowing prose (`WrapMode::Always`), avoid leaving a single word
+    /// alone on the last line of a paragraph by moving a word down from the
+    /// line before. Off by default.
+    pub fn no_widows(mut self, enabled: bool) -> Self {
+        self.no_widows = enabled;
+        self
+    }
+
+    /// Replace each tab in prose text (paragraphs, headings, table cells)
+    /// with this many spaces before wrapping, so tabs count like the spaces
+    /// they stand for. Code blocks keep their tabs. Off (`None`) by default;
+    /// a width of 0 counts as 1, so tabs never just disappear.
+    pub fn expand_tabs(mut self, width: Option<usize>) -> Self {
+        self.expand_tabs = width.map(|width| width.max(1));
+        self
+    }
+
+    /// Keep up to this many blank lines in a row between top-level blocks
+    /// where the source has them (default 1; values below 1 count as 1).
+    /// Only `format_document` sees the source; `format` always writes one.
+    pub fn max_blank_lines(mut self, max: usize) -> Self {
+        self.max_blank_lines = max.max(1);
+        self
+    }
+
+    /// When reflowing prose (`WrapMode::Always`), split words longer than the
+    /// line width into pieces that fit. Words with Markdown syntax in them
+    /// (links, code, emphasis, math, HTML) are never split. Off by default:
+    /// long words overflow on a line of their own.
+    pub fn break_long_words(mut self, enabled: bool) -> Self {
+        self.break_long_words = enabled;
+        self
+    }
+
+    /// Whether non-empty output ends with a single newline, as a document
+    /// should (default). Turn off to embed the formatted block inline in
+    /// other generated text.
+    pub fn trailing_newline(mut self, enabled: bool) -> Self {
+        self.trailing_newline = enabled;
+        self
+    }
+
+    /// Before parsing, insert the space missing in lines like `#Heading` so
+    /// they become real headings instead of text. Applies to `format_document`.
+    /// Off by default since `#hashtag` lines would be converted too.
+    pub fn fix_heading_spacing(mut self, enabled: bool) -> Self {
+        self.fix_heading_spacing = enabled;
+        self
+    }
+
+    /// Set how list item content and nested lists are indented: with
+    /// spaces (the default lines content up two columns after bullets and
+    /// one space after numbers) or with tabs
+    pub fn indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.indent_style = indent_style;
+        self
+    }
+
+    /// Set the character after ordered list item numbers (`1.` or `1)`).
+    /// Both are one character wide, so content and wrapped lines line up
+    /// the same way with either.
+    pub fn ordered_list_delimiter(mut self, delimiter: OrderedListDelimiter) -> Self {
+        self.ordered_list_delimiter = delimiter;
+        self
+    }
+
+    /// Set the number every list starts at with ascending numbering
+    /// (default 1), nested lists included. A nested list directly below
+    /// its item's text still starts at 1, since only such a list can
+    /// interrupt a paragraph, and a list whose numbers would pass nine
+    /// digits (999999999) isn't renumbered from it.
+    pub fn ordered_start(mut self, start: u64) -> Self {
+        self.ordered_start = start;
+        self
+    }
+
+    /// Set how headings are written. With setext, level 1 and 2 headings are
+    /// underlined to the width of their text; deeper levels stay ATX.
+    pub fn heading_style(mut self, heading_style: HeadingStyle) -> Self {
+        self.heading_style = heading_style;
+        self
+    }
+
+    /// Set whether ATX headings end with a closing sequence (`# Title #`).
+    /// Setext headings are never closed.
+    pub fn heading_close(mut self, heading_close: HeadingClose) -> Self {
+        self.heading_close = heading_close;
+        self
+    }
+
+    /// Set the character for emphasis (`*text*` or `_text_`). Underscores
+    /// can't open or close emphasis inside a word (`foo*bar*baz`) or next to
+    /// another underscore, so `*` is kept there.
+    pub fn emphasis_marker(mut self, marker: EmphasisMarker) -> Self {
+        self.emphasis_marker = marker;
+        self
+    }
+
+    /// Set the character for strong emphasis (`**text**` or `__text__`),
+    /// with the same fallback to `*` as [`Formatter::emphasis_marker`]
+    pub fn strong_marker(mut self, marker: EmphasisMarker) -> Self {
+        self.strong_marker = marker;
+        self
+    }
+
+    /// Set whether checked task list items are written `[x]` or `[X]`
+    pub fn task_marker_case(mut self, case: TaskMarkerCase) -> Self {
+        self.task_marker_case = case;
+        self
+    }
+
+    /// Set whether indented code blocks are converted to fenced ones or kept
+    /// indented. Indented blocks are still fenced where four spaces of
+    /// indentation would be read differently: right after a list (they'd
+    /// continue its last item) and after text in a tight list item.
+    pub fn code_block_style(mut self, style: CodeBlockStyle) -> Self {
+        self.code_block_style = style;
+        self
+    }
+
+    /// Strip trailing spaces and tabs from each line of code blocks.
+    /// Indentation and blank lines are kept. Off by default, since trailing
+    /// whitespace can be significant (e.g. Markdown or diff snippets).
+    pub fn trim_code_trailing_whitespace(mut self, enabled: bool) -> Self {
+        self.trim_code_trailing_whitespace = enabled;
+        self
+    }
+
+    /// Normalize the language of fenced code blocks: lowercase it and
+    /// replace aliases (`js` becomes `javascript`). Anything after the
+    /// language in the info string is kept.
+    pub fn normalize_code_lang(mut self, enabled: bool) -> Self {
+        self.normalize_code_lang = enabled;
+        self
+    }
+
+    /// Replace the alias table used by `normalize_code_lang` (lowercase alias
+    /// to canonical name). Starts as `default_code_lang_aliases()`.
+    pub fn code_lang_aliases(mut self, aliases: HashMap<String, String>) -> Self {
+        self.code_lang_aliases = aliases;
+        self
+    }
+
+    /// Set how quotes and dashes in prose are written. Only text is changed;
+    /// code, URLs and raw HTML are left alone.
+    pub fn punctuation(mut self, punctuation: Punctuation) -> Self {
+        self.punctuation = punctuation;
+        self
+    }
+
+    /// Right-align the numbers of ordered lists with 10 or more items so the
+    /// periods line up (` 9.`, `10.`) and every item's content starts at the
+    /// same column. Off by default.
+    pub fn align_ordered_markers(mut self, enabled: bool) -> Self {
+        self.align_ordered_markers = enabled;
+        self
+    }
+
+    /// Set the line endings `format_document` writes. `format` always
+    /// produces `\n`.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Clear per-document state so the formatter can be reused
+    fn reset(&mut self) {
+        self.output.clear();
+        self.flushed_output = false;
+        self.verbatim.clear();
+        self.trailing_newlines = 0;
+        self.inline_buffer.clear();
+        self.context_stack.clear();
+        self.list_depth = 0;
+        self.item_indents.clear();
+        self.blockquotes.clear();
+        self.code_block = None;
+        self.document_output = None;
+        self.footnotes.clear();
+        self.at_item_start = false;
+        self.after_list = false;
+        self.table = None;
+        self.references.clear();
+        self.reference_ids.clear();
+        self.references_written = 0;
+        self.list_item_counts.clear();
+        self.loose_lists.clear();
+        self.ignore_next_block = false;
+        self.ignored_depth = 0;
+    }
+
+    /// Format markdown from a list of events
+    pub fn format(&mut self, events: Vec<Event>) -> String {
+        let mut result = String::new();
+        self.format_to(events, &mut result);
+        result
+    }
+
+    /// Format markdown from a stream of events, writing the result into `sink`
+    ///
+    /// Events are consumed lazily, one top-level block at a time (lists need
+    /// to look ahead to their end for numbering and looseness), so only the
+    /// largest block is held in memory. Output is handed over as each event
+    /// is processed; only trailing whitespace is held back until the next
+    /// content (or the end).
+    pub fn format_to<'a, I, S>(&mut self, events: I, sink: &mut S)
+    where
+        I: IntoIterator<Item = Event<'a>>,
+        S: OutputSink + ?Sized,
+    {
+        self.reset();
+        let mut block = Vec::new();
+        let mut depth = 0usize;
+        for event in events {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            block.push(event);
+            if depth == 0 {
+                self.format_block(std::mem::take(&mut block), sink);
+            }
+        }
+        self.format_block(block, sink);
+
+        // Flush any remaining content
+        self.flush_inline_buffer();
+        self.write_footnote_definitions();
+        self.write_reference_definitions();
+        self.finish_output(sink);
+
+        // Ensure single trailing newline
+        if self.flushed_output && self.trailing_newline {
+            sink.push_char('\n');
+        }
+        self.output.clear();
+        self.source_blank_lines.clear();
+        self.ignored_blocks.clear();
+    }
+
+    /// Format the events of one top-level block
+    fn format_block<S: OutputSink + ?Sized>(&mut self, events: Vec<Event>, sink: &mut S) {
+        self.list_item_counts.extend(count_list_items(&events));
+        let sublists_loose = self.nested_list_spacing == NestedListSpacing::Loose;
+        self.loose_lists
+            .extend(loose_lists(&events, sublists_loose));
+        for event in events {
+            self.process_event(event);
+            self.flush_output(sink);
+        }
+    }
+
+    /// Hand the finished lines over to the sink. The line being written and
+    /// trailing whitespace are held back, so block handlers can still see
+    /// how the output ends and how wide the current line is.
+    fn flush_output<S: OutputSink + ?Sized>(&mut self, sink: &mut S) {
+        if self.document_output.is_some() {
+            // A footnote definition is being rendered; it's written at the end
+            return;
+        }
+        self.trim_leading_blank_lines();
+        // Blank lines inside blockquotes (just `>`) count as trailing whitespace
+        let content_end = self
+            .output
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '>')
+            .len();
+        let end = self.output[..content_end]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        self.hand_over(end, sink);
+    }
+
+    /// Hand everything up to the trailing whitespace over to the sink
+    fn finish_output<S: OutputSink + ?Sized>(&mut self, sink: &mut S) {
+        self.trim_leading_blank_lines();
+        let end = self.output.trim_end().len();
+        self.hand_over(end, sink);
+    }
+
+    /// Before anything is handed over: the output never starts with a blank
+    /// line, whatever block comes first
+    fn trim_leading_blank_lines(&mut self) {
+        if !self.flushed_output {
+            let content = self.output.trim_start();
+            let leading = &self.output[..self.output.len() - content.len()];
+            if let Some(newline) = leading.rfind('\n') {
+                self.drain_output(newline + 1);
+            }
+        }
+    }
+
+    /// Move the first `end` bytes of the output to the sink. Runs of blank
+    /// lines are collapsed to one, except in code blocks and raw HTML.
+    fn hand_over<S: OutputSink + ?Sized>(&mut self, mut end: usize, sink: &mut S) {
+        if end == 0 {
+            return;
+        }
+        while let Some(verbatim) = self
+            .verbatim
+            .first()
+            .filter(|range| range.start < end)
+            .map(|range| range.start..range.end.min(end))
+        {
+            self.push_collapsed(verbatim.start, sink);
+            sink.push_str(&self.output[..verbatim.len()]);
+            if !verbatim.is_empty() {
+                self.trailing_newlines = usize::from(self.output[..verbatim.len()].ends_with('\n'));
+            }
+            self.drain_output(verbatim.len());
+            end -= verbatim.end;
         }
+        self.push_collapsed(end, sink);
+        self.flushed_output = true;
+    }
+
+    /// Hand over the first `len` bytes of the output, dropping newlines that
+    /// would make more than `max_blank_lines` blank lines in a row
+    fn push_collapsed<S: OutputSink + ?Sized>(&mut self, len: usize, sink: &mut S) {
+        let mut start = 0;
+        for (i, c) in self.output[..len].char_indices() {
+            if c != '\n' {
+                self.trailing_newlines = 0;
+                continue;
+            }
+            self.trailing_newlines += 1;
+            if self.trailing_newlines > self.max_blank_lines + 1 {
+                sink.push_str(&self.output[start..i]);
+                start = i + 1;
+            }
+        }
+        sink.push_str(&self.output[start..len]);
+        self.drain_output(len);
+    }
+
+    /// Remove the first `len` bytes of the output, keeping the verbatim
+    /// ranges pointing at the same text
+    fn drain_output(&mut self, len: usize) {
+        self.output.drain(..len);
+        self.verbatim.retain(|range| range.end > len);
+        for range in &mut self.verbatim {
+            *range = range.start.saturating_sub(len)..range.end - len;
+        }
+    }
+
+    /// Hand the output from `start` on over as-is
+    fn mark_verbatim(&mut self, start: usize) {
+        let end = self.output.len();
+        match self.verbatim.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => self.verbatim.push(start..end),
+        }
+    }
+
+    /// Whether nothing has been written for the current document yet
+    fn output_is_empty(&self) -> bool {
+        !self.flushed_output && self.output.is_empty()
+    }
+
+    /// Format a whole document, keeping any frontmatter as-is. Documents
+    /// starting with `<!-- mdfmt-ignore-file -->` are returned unchanged.
+    ///
+    /// Blank lines before the first block are always dropped: the result
+    /// starts with the frontmatter or the first line of content.
+    ///
+    /// CRLF and lone CR line endings are read as LF; the output uses the
+    /// configured `LineEnding`.
+    pub fn format_document(&mut self, input: &str) -> String {
+        let mut output = String::new();
+        self.format_document_to(input, &mut output);
+        output
     }
 
-    /// Format markdown from a list of events
-    pub fn format(&mut self, events: Vec<Event>) -> String {
-        for event in events {
-            self.process_event(event);
+    /// Format a whole document like `format_document`, writing the result
+    /// into `sink` as it is produced
+    pub fn format_document_to<S: OutputSink + ?Sized>(&mut self, input: &str, sink: &mut S) {
+        if has_ignore_file_directive(input) {
+            sink.push_str(input);
+            return;
         }
 
-        // Flush any remaining content
-        self.flush_inline_buffer();
-
-        // Ensure single trailing newline
-        let result = self.output.trim_end().to_string();
-        if result.is_empty() {
-            result
+        let crlf = match self.line_ending {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Preserve => uses_crlf(input),
+        };
+        // Formatting works on LF line endings; the chosen ones are put back on output
+        let lf_input = normalize_line_endings(input).replace("\r\n", "\n");
+
+        let (frontmatter, content) = extract_frontmatter(&lf_input);
+        let source = if self.fix_heading_spacing {
+            fix_atx_heading_spacing(content)
         } else {
-            result + "\n"
+            Cow::Borrowed(content)
+        };
+        if self.max_blank_lines > 1 {
+            self.source_blank_lines = blank_lines_before_blocks(&source);
+        }
+        if source.contains(IGNORE_DIRECTIVE) {
+            self.ignored_blocks = ignored_blocks(&source);
+        }
+
+        let frontmatter = frontmatter.map(|fm| {
+            let fm = if self.normalize_frontmatter || self.sort_frontmatter_keys {
+                normalize_yaml_frontmatter(&fm, self.sort_frontmatter_keys).unwrap_or(fm)
+            } else {
+                fm
+            };
+            self.frontmatter_block(fm, content)
+        });
+        let mut document = DocumentSink {
+            sink,
+            frontmatter,
+            crlf,
+        };
+        self.format_to(Parser::new_ext(&source, parser_options()), &mut document);
+
+        // Nothing after the frontmatter: the document ends with its closing delimiter
+        if let Some(fm) = document.frontmatter.take() {
+            document.write(fm.trim_end_matches('\n'));
+            if self.trailing_newline {
+                document.write("\n");
+            }
+        }
+    }
+
+    /// Read a whole document from `reader` and write it formatted to
+    /// `writer`, as `format_document` would. Output is written as it is
+    /// produced rather than collected first.
+    pub fn format_reader<R: Read, W: Write>(&mut self, mut reader: R, writer: W) -> io::Result<()> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let mut sink = WriterSink::new(writer);
+        self.format_document_to(&input, &mut sink);
+        sink.finish()
+    }
+
+    /// The frontmatter as written before `body`: followed by one blank line,
+    /// or by as many blank lines as `body` starts with in preserve mode
+    fn frontmatter_block(&self, frontmatter: String, body: &str) -> String {
+        match self.frontmatter_spacing {
+            FrontmatterSpacing::Normalize => frontmatter,
+            FrontmatterSpacing::Preserve => {
+                let blank_lines = body
+                    .lines()
+                    .take_while(|l| l.trim().is_empty())
+                    .count()
+                    .min(self.max_blank_lines);
+                let mut block = frontmatter.trim_end_matches('\n').to_string();
+                block.push('\n');
+                block.push_str(&"\n".repeat(blank_lines));
+                block
+            }
         }
     }
 
     fn process_event(&mut self, event: Event) {
+        if self.skip_ignored_block(&event) {
+            return;
+        }
+        let ends_list = matches!(event, Event::End(TagEnd::List(_)));
         match event {
             Event::Start(tag) => self.handle_start_tag(tag),
             Event::End(tag) => self.handle_end_tag(tag),
             Event::Text(text) => self.handle_text(text),
             Event::Code(code) => self.handle_inline_code(code),
             Event::Html(html) => self.handle_html(html),
+            Event::InlineHtml(html) => self
+                .inline_buffer
+                .push(InlineElement::Text(html.to_string())),
+            Event::InlineMath(math) => self.inline_buffer.push(InlineElement::Math {
+                content: math.to_string(),
+                display: false,
+            }),
+            Event::DisplayMath(math) => self.inline_buffer.push(InlineElement::Math {
+                content: math.to_string(),
+                display: true,
+            }),
             Event::SoftBreak => self.handle_soft_break(),
             Event::HardBreak => self.handle_hard_break(),
             Event::Rule => self.handle_rule(),
-            Event::FootnoteReference(_) => {}
+            Event::FootnoteReference(label) => self
+                .inline_buffer
+                .push(InlineElement::FootnoteReference(label.to_string())),
             Event::TaskListMarker(checked) => self.handle_task_list_marker(checked),
         }
+        self.after_list = ends_list;
     }
 
-    /// Get the prefix for the current line (blockquote markers)
+    /// Get the prefix for the current line (through the innermost
+    /// blockquote's `> `, including the indent of items it's nested in)
     fn get_line_prefix(&self) -> String {
-        let mut prefix = String::new();
-        for _ in 0..self.blockquote_depth {
-            prefix.push_str("> ");
-        }
-        prefix
+        self.blockquotes
+            .last()
+            .map_or_else(String::new, |(prefix, _)| prefix.clone())
     }
 
     /// Get the continuation indent for wrapped lines
     fn get_continuation_indent(&self) -> String {
         let mut indent = self.get_line_prefix();
 
-        // Add list indentation for continuation lines
-        if self.list_depth > 0 {
-            // Each list level needs indentation, plus space for the marker
-            indent.push_str(&"  ".repeat(self.list_depth));
-        }
+        // Continuation lines line up with the content of the innermost list item
+        indent.push_str(&self.indent(self.item_content_indent()));
 
         indent
     }
 
+    /// Whether the innermost container is a list item, footnote definition or
+    /// definition, whose first block continues the marker line
+    fn in_item(&self) -> bool {
+        matches!(
+            self.context_stack.last(),
+            Some(Context::ListItem | Context::FootnoteDefinition | Context::Definition)
+        )
+    }
+
+    /// Whether the innermost container is an item of a tight list
+    fn in_tight_item(&self) -> bool {
+        matches!(
+            self.context_stack[..],
+            [.., Context::List { loose: false, .. }, Context::ListItem]
+                | [
+                    ..,
+                    Context::DefinitionList { loose: false },
+                    Context::Definition
+                ]
+        )
+    }
+
+    /// Whitespace reaching `columns`, as tabs (4 columns each) in tab mode
+    fn indent(&self, columns: usize) -> String {
+        if self.uses_tabs() {
+            format!("{}{}", "\t".repeat(columns / 4), " ".repeat(columns % 4))
+        } else {
+            " ".repeat(columns)
+        }
+    }
+
+    /// Whether indentation is written with tabs here; tab stops only line up
+    /// outside blockquotes
+    fn uses_tabs(&self) -> bool {
+        self.indent_style == IndentStyle::Tab && self.blockquotes.is_empty()
+    }
+
+    /// Column (after any blockquote prefix) where the innermost list item's
+    /// content starts; items outside the innermost blockquote don't count
+    fn item_content_indent(&self) -> usize {
+        let outer = self.blockquotes.last().map_or(0, |(_, items)| *items);
+        self.item_indents[outer..].last().copied().unwrap_or(0)
+    }
+
+    /// Escape `|` inside table cells, where the table syntax splits rows at
+    /// unescaped pipes before reading any inline syntax (code spans, links,
+    /// math)
+    fn escape_table_pipes(&self, s: &str) -> String {
+        if self.table.is_some() {
+            s.replace('|', "\\|")
+        } else {
+            s.to_string()
+        }
+    }
+
     /// Convert inline buffer to a flat string (for wrapping), preserving structure
     fn render_inline_buffer(&self) -> String {
+        let markers = self.emphasis_markers();
         let mut result = String::new();
-        for elem in &self.inline_buffer {
+        // Where the open links and images start in `result`
+        let mut links = Vec::new();
+        for (i, elem) in self.inline_buffer.iter().enumerate() {
+            if matches!(elem, InlineElement::LinkStart | InlineElement::ImageStart) {
+                links.push(result.len());
+            }
             match elem {
                 InlineElement::Text(s) => result.push_str(s),
-                InlineElement::Code(s) => {
-                    result.push('`');
-                    result.push_str(s);
-                    result.push('`');
-                }
-                InlineElement::EmphasisStart => result.push('*'),
-                InlineElement::EmphasisEnd => result.push('*'),
-                InlineElement::StrongStart => result.push_str("**"),
-                InlineElement::StrongEnd => result.push_str("**"),
+                // The table syntax reads `|` before code spans, so it stays escaped there
+                InlineElement::Code(s) => result.push_str(&code_span(&self.escape_table_pipes(s))),
+                InlineElement::EmphasisStart | InlineElement::EmphasisEnd => {
+                    result.push(markers[i])
+                }
+                InlineElement::StrongStart | InlineElement::StrongEnd => {
+                    result.push(markers[i]);
+                    result.push(markers[i]);
+                }
                 InlineElement::StrikethroughStart => result.push_str("~~"),
                 InlineElement::StrikethroughEnd => result.push_str("~~"),
                 InlineElement::LinkStart => result.push('['),
-                InlineElement::LinkEnd(url) => {
+                InlineElement::LinkEnd { url, title } => {
                     result.push_str("](");
-                    result.push_str(url);
+                    result.push_str(&self.escape_table_pipes(&link_destination(url, title)));
                     result.push(')');
                 }
+                InlineElement::ReferenceLinkEnd { suffix, label } => {
+                    // `[text]` and `[text][]` only link while the text still
+                    // matches the label of the definition
+                    let text = links.last().map(|&start| {
+                        let text = result[start..].trim_start_matches('!');
+                        &text[1..]
+                    });
+                    match (label, text) {
+                        (Some(label), Some(text)) if !labels_match(text, label) => {
+                            result.push_str("][");
+                            result.push_str(label);
+                            result.push(']');
+                        }
+                        _ => result.push_str(suffix),
+                    }
+                }
+                InlineElement::TaskMarker(checked) => {
+                    result.push('[');
+                    result.push(match (checked, self.task_marker_case) {
+                        (false, _) => KEPT_SPACE,
+                        (true, TaskMarkerCase::Lower) => 'x',
+                        (true, TaskMarkerCase::Upper) => 'X',
+                    });
+                    result.push(']');
+                    if i + 1 < self.inline_buffer.len() {
+                        result.push(KEPT_SPACE);
+                    }
+                }
+                InlineElement::FootnoteReference(label) => {
+                    result.push_str("[^");
+                    result.push_str(label);
+                    result.push(']');
+                }
+                InlineElement::Math { content, display } => {
+                    let delimiter = if *display { "$$" } else { "$" };
+                    result.push_str(delimiter);
+                    // Spaces are held as placeholders so wrapping can't break the formula
+                    let content = self.escape_table_pipes(content);
+                    result.push_str(&content.replace([' ', '\n'], &KEPT_SPACE.to_string()));
+                    result.push_str(delimiter);
+                }
                 InlineElement::ImageStart => result.push_str("!["),
                 InlineElement::ImageEnd { url, title } => {
                     result.push_str("](");
-                    result.push_str(url);
-                    if !title.is_empty() {
-                        result.push_str(" \"");
-                        result.push_str(title);
-                        result.push('"');
-                    }
+                    result.push_str(&self.escape_table_pipes(&link_destination(url, title)));
                     result.push(')');
                 }
                 InlineElement::HardBreak => result.push('\u{FFFF}'), // Placeholder for hard break
@@ -254,10 +1819,80 @@ impl Formatter {
                     }
                 }
             }
+            // A whole link or image is kept on one line, so that no line
+            // starts with a dangling `](url)`
+            if matches!(
+                elem,
+                InlineElement::LinkEnd { .. }
+                    | InlineElement::ImageEnd { .. }
+                    | InlineElement::ReferenceLinkEnd { .. }
+            ) {
+                if let (Some(start), true) = (links.pop(), links.is_empty()) {
+                    let link = result
+                        .split_off(start)
+                        .replace(' ', &KEPT_SPACE.to_string());
+                    result.push_str(&link);
+                }
+            }
         }
         result
     }
 
+    /// Marker character for each emphasis and strong delimiter in the inline
+    /// buffer (indexed like the buffer). A pair only gets `_` if the
+    /// characters around it are whitespace or punctuation other than `_`;
+    /// otherwise CommonMark wouldn't read it as emphasis, so it stays `*`.
+    fn emphasis_markers(&self) -> Vec<char> {
+        let buffer = &self.inline_buffer;
+        let mut markers = vec!['*'; buffer.len()];
+
+        let mut open = Vec::new();
+        let mut pairs = Vec::new();
+        for (i, elem) in buffer.iter().enumerate() {
+            match elem {
+                InlineElement::EmphasisStart | InlineElement::StrongStart => open.push(i),
+                InlineElement::EmphasisEnd | InlineElement::StrongEnd => {
+                    if let Some(start) = open.pop() {
+                        pairs.push((start, i));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Outer pairs first, so inner ones see the markers around them
+        pairs.sort_unstable();
+
+        for (start, end) in pairs {
+            let preferred = match buffer[start] {
+                InlineElement::EmphasisStart => self.emphasis_marker,
+                _ => self.strong_marker,
+            };
+            if preferred != EmphasisMarker::Underscore {
+                continue;
+            }
+            // Characters rendered just before the opening and just after the
+            // closing delimiter, skipping empty text
+            let before = buffer[..start]
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, elem)| edge_char(elem, markers[i], true));
+            let after = buffer[end + 1..]
+                .iter()
+                .enumerate()
+                .find_map(|(i, elem)| edge_char(elem, markers[end + 1 + i], false));
+            let is_boundary = |c: Option<char>| match c {
+                None => true,
+                Some(c) => c.is_whitespace() || (c.is_ascii_punctuation() && c != '_'),
+            };
+            if is_boundary(before) && is_boundary(after) {
+                markers[start] = '_';
+                markers[end] = '_';
+            }
+        }
+        markers
+    }
+
     /// Wrap text to fit within line_width
     /// Returns wrapped text with proper line prefixes
     fn wrap_text(&self, text: &str, first_line_prefix: &str, continuation_prefix: &str) -> String {
@@ -277,7 +1912,12 @@ impl Formatter {
             }
             WrapMode::Never => {
                 // Never mode: unwrap everything to single lines (per paragraph)
-                self.wrap_text_never(text, first_line_prefix, hard_break_placeholder)
+                self.wrap_text_never(
+                    text,
+                    first_line_prefix,
+                    continuation_prefix,
+                    hard_break_placeholder,
+                )
             }
             WrapMode::Always => {
                 // Always mode: reflow text to fit width
@@ -345,7 +1985,7 @@ impl Formatter {
             };
             result.push_str(prefix);
 
-            // Add the segment content (normalize internal whitespace but preserve words)
+            // Keep the line, but collapse its internal whitespace to single spaces
             let words: Vec<&str> = segment.split_whitespace().collect();
             result.push_str(&words.join(" "));
 
@@ -373,6 +2013,7 @@ impl Formatter {
         &self,
         text: &str,
         first_line_prefix: &str,
+        continuation_prefix: &str,
         hard_break_placeholder: &str,
     ) -> String {
         // Split on hard breaks - those we preserve
@@ -391,7 +2032,7 @@ impl Formatter {
             // Add hard break if not the last segment
             if seg_idx < segments.len() - 1 {
                 result.push_str("  \n");
-                result.push_str(first_line_prefix);
+                result.push_str(continuation_prefix);
             }
         }
 
@@ -415,69 +2056,161 @@ impl Formatter {
             // Normalize whitespace within this segment
             let words: Vec<&str> = segment.split_whitespace().collect();
 
-            if words.is_empty() {
-                if seg_idx < segments.len() - 1 {
-                    // There was a hard break here, add it
-                    if !result.is_empty() {
-                        result.push_str("  \n");
-                        result.push_str(continuation_prefix);
-                    }
-                }
-                continue;
-            }
-
-            let prefix = if seg_idx == 0 && result.is_empty() {
+            // Every segment starts on a fresh line: the first one after the
+            // block's own prefix, the rest after a hard break
+            let prefix = if seg_idx == 0 {
                 first_line_prefix
             } else {
                 continuation_prefix
             };
-
-            let mut current_line = if result.is_empty() || result.ends_with('\n') {
-                prefix.to_string()
+            let mut lines: Vec<String> = Vec::new();
+            let mut current_line = prefix.to_string();
+            // Display columns, so wide (e.g. CJK) characters count double
+            let mut current_width = display_width(prefix);
+            let mut first_word_on_line = true;
+
+            let max_piece = self
+                .line_width
+                .saturating_sub(display_width(continuation_prefix));
+            let pieces: Vec<&str> = if self.break_long_words {
+                words
+                    .iter()
+                    .flat_map(|word| split_long_word(word, max_piece))
+                    .collect()
             } else {
-                String::new()
+                words
             };
 
-            let mut first_word_on_line = result.is_empty() || result.ends_with('\n');
-
-            for word in &words {
+            for word in &pieces {
                 let space_needed = if first_word_on_line { 0 } else { 1 };
-                let would_be_length = current_line.len() + space_needed + word.len();
+                let would_be_width = current_width + space_needed + word.width();
 
-                if !first_word_on_line && would_be_length > self.line_width {
+                if !first_word_on_line && would_be_width > self.line_width {
                     // Wrap to new line (use plain \n - NOT hard break)
-                    result.push_str(&current_line);
-                    result.push('\n');
+                    lines.push(current_line);
                     current_line = continuation_prefix.to_string();
                     current_line.push_str(word);
-                    first_word_on_line = false;
+                    current_width = display_width(continuation_prefix) + word.width();
                 } else {
                     if !first_word_on_line {
                         current_line.push(' ');
                     }
                     current_line.push_str(word);
-                    first_word_on_line = false;
+                    current_width = would_be_width;
                 }
+                first_word_on_line = false;
             }
+            lines.push(current_line);
 
-            result.push_str(&current_line);
+            if self.no_widows {
+                avoid_widow(&mut lines, prefix, continuation_prefix, self.line_width);
+            }
+            result.push_str(&lines.join("\n"));
 
             // Add hard break if not the last segment
             if seg_idx < segments.len() - 1 {
                 result.push_str("  \n");
-                result.push_str(continuation_prefix);
             }
         }
 
         result
     }
 
+    /// If pseudo-heading conversion is enabled and the current paragraph
+    /// consists solely of one strong (or emphasis) span, return the heading
+    /// level to convert it to
+    fn pseudo_heading(&self) -> Option<u32> {
+        let level = self.pseudo_heading_level?;
+
+        // Paragraphs inside list items, footnotes and definitions stay as they are
+        let parent = self.context_stack.iter().rev().nth(1);
+        if matches!(
+            parent,
+            Some(Context::ListItem | Context::FootnoteDefinition | Context::Definition)
+        ) {
+            return None;
+        }
+
+        let (start, end) = match (self.inline_buffer.first(), self.inline_buffer.last()) {
+            (Some(InlineElement::StrongStart), Some(InlineElement::StrongEnd)) => {
+                (InlineElement::StrongStart, InlineElement::StrongEnd)
+            }
+            (Some(InlineElement::EmphasisStart), Some(InlineElement::EmphasisEnd)) => {
+                (InlineElement::EmphasisStart, InlineElement::EmphasisEnd)
+            }
+            _ => return None,
+        };
+
+        // The span must cover the whole paragraph: no other span of the same
+        // kind closes and reopens in between, and no line breaks
+        let inner = &self.inline_buffer[1..self.inline_buffer.len() - 1];
+        let single_span = inner.iter().all(|elem| {
+            std::mem::discriminant(elem) != std::mem::discriminant(&start)
+                && std::mem::discriminant(elem) != std::mem::discriminant(&end)
+                && !matches!(elem, InlineElement::SoftBreak | InlineElement::HardBreak)
+        });
+        let has_text = inner
+            .iter()
+            .any(|elem| matches!(elem, InlineElement::Text(t) if !t.trim().is_empty()));
+
+        (single_span && has_text).then_some(level)
+    }
+
+    /// Drop soft/hard breaks at the start or end of the inline buffer so a
+    /// block never begins or ends with a stray line break
+    fn trim_inline_breaks(&mut self) {
+        let is_break = |elem: &InlineElement| {
+            matches!(elem, InlineElement::SoftBreak | InlineElement::HardBreak)
+        };
+
+        while self.inline_buffer.last().is_some_and(is_break) {
+            self.inline_buffer.pop();
+        }
+        let leading = self
+            .inline_buffer
+            .iter()
+            .take_while(|elem| is_break(elem))
+            .count();
+        self.inline_buffer.drain(..leading);
+    }
+
     /// Flush the inline buffer, wrapping text appropriately
     fn flush_inline_buffer(&mut self) {
+        self.trim_inline_breaks();
         if self.inline_buffer.is_empty() {
             return;
         }
 
+        // Display math is a block of its own, split from the prose around it
+        // like other blocks (by blank lines, except in tight items). Headings
+        // and terms can't hold blocks, so there it stays inline.
+        let in_heading = matches!(
+            self.context_stack.last(),
+            Some(Context::Heading { .. } | Context::DefinitionTitle)
+        );
+        let display_math = self
+            .inline_buffer
+            .iter()
+            .position(|elem| matches!(elem, InlineElement::Math { display: true, .. }));
+        if let (Some(index), false) = (display_math, in_heading) {
+            let after = self.inline_buffer.split_off(index + 1);
+            if let Some(InlineElement::Math { content, .. }) = self.inline_buffer.pop() {
+                let length = self.output.len();
+                self.flush_inline_buffer();
+                if self.output.len() != length {
+                    self.separate_block();
+                }
+                self.write_display_math(&content);
+            }
+            self.inline_buffer = after;
+            self.trim_inline_breaks();
+            if !self.render_inline_buffer().trim().is_empty() {
+                self.separate_block();
+            }
+            self.flush_inline_buffer();
+            return;
+        }
+
         let rendered = self.render_inline_buffer();
 
         if rendered.trim().is_empty() {
@@ -485,47 +2218,313 @@ impl Formatter {
             return;
         }
 
-        let prefix = self.get_line_prefix();
-        let continuation = self.get_continuation_indent();
+        if in_heading {
+            // Headings and terms always stay on a single line (the prefix and
+            // hashes are already written), whatever the wrap mode. Runs of
+            // whitespace in their text become one space; code spans keep theirs.
+            let mut after_space = true;
+            for elem in self.inline_buffer.iter_mut() {
+                if matches!(elem, InlineElement::SoftBreak | InlineElement::HardBreak) {
+                    *elem = InlineElement::Text(" ".to_string());
+                }
+                let InlineElement::Text(text) = elem else {
+                    after_space = false;
+                    continue;
+                };
+                let mut collapsed = String::with_capacity(text.len());
+                for c in text.chars() {
+                    if !c.is_whitespace() {
+                        collapsed.push(c);
+                        after_space = false;
+                    } else if !after_space {
+                        collapsed.push(' ');
+                        after_space = true;
+                    }
+                }
+                *text = collapsed;
+            }
+            let rendered = self.render_inline_buffer().replace(KEPT_SPACE, " ");
+            self.output.push_str(rendered.trim_end());
+            self.inline_buffer.clear();
+            return;
+        }
 
-        let wrapped = self.wrap_text(&rendered, &prefix, &continuation);
-        self.output.push_str(&wrapped);
+        // Text starting a fresh line lines up with its container. Text that
+        // continues a line (right after a list marker) is wrapped as if that
+        // line's start were its prefix, so the marker counts toward the width.
+        let continuation = self.get_continuation_indent();
+        let current_line = if self.output_is_empty() || self.output.ends_with('\n') {
+            None
+        } else {
+            let start = self.output.rfind('\n').map_or(0, |newline| newline + 1);
+            Some(self.output[start..].to_string())
+        };
+
+        match current_line {
+            Some(line) => {
+                let wrapped = self
+                    .wrap_text(&rendered, &line, &continuation)
+                    .replace(KEPT_SPACE, " ");
+                self.output
+                    .push_str(wrapped.strip_prefix(line.as_str()).unwrap_or(&wrapped));
+            }
+            None => {
+                let wrapped = self
+                    .wrap_text(&rendered, &continuation, &continuation)
+                    .replace(KEPT_SPACE, " ");
+                self.output.push_str(&wrapped);
+            }
+        }
         self.inline_buffer.clear();
     }
 
-    /// Ensure there's a blank line before the next block element
-    fn ensure_blank_line(&mut self) {
-        if self.output.is_empty() {
+    /// Info string with its language lowercased and de-aliased
+    fn normalize_info(&self, info: &str) -> String {
+        let info = info.trim();
+        let split = info.find(char::is_whitespace).unwrap_or(info.len());
+        let (lang, rest) = info.split_at(split);
+        let lang = lang.to_lowercase();
+        match self.code_lang_aliases.get(&lang) {
+            Some(canonical) => format!("{}{}", canonical, rest),
+            None => format!("{}{}", lang, rest),
+        }
+    }
+
+    /// Write a code block with a fence its content can't close (or indented
+    /// by four spaces), indented to the enclosing container
+    fn write_code_block(&mut self, mut code_block: CodeBlockState) {
+        if self.trim_code_trailing_whitespace {
+            code_block.content = trim_line_ends(&code_block.content);
+        }
+        if code_block.indented {
+            self.write_indented_code_block(&code_block.content);
             return;
         }
-        if !self.output.ends_with("\n\n") {
-            if self.output.ends_with('\n') {
+        let fence = code_fence(&code_block.content, &code_block.info);
+
+        self.push_code_line_prefix();
+        self.output.push_str(&fence);
+        self.output.push_str(&code_block.info);
+        self.output.push('\n');
+
+        // Code lines are kept exactly; only the container prefix is added
+        let content_start = self.output.len();
+        for line in code_block.content.split_inclusive('\n') {
+            if line != "\n" {
+                self.push_code_line_prefix();
+            } else if self.output.ends_with('\n') {
+                let prefix = self.get_continuation_indent();
+                self.output.push_str(prefix.trim_end());
+            }
+            self.output.push_str(line);
+        }
+        // Content at the end of the input may lack its final newline
+        if !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+        self.mark_verbatim(content_start);
+
+        self.push_code_line_prefix();
+        self.output.push_str(&fence);
+        self.output.push('\n');
+    }
+
+    /// Write code lines indented by four spaces past the container prefix;
+    /// blank lines get only the prefix
+    fn write_indented_code_block(&mut self, content: &str) {
+        let content_start = self.output.len();
+        for line in content.split_inclusive('\n') {
+            if line == "\n" {
+                let prefix = self.get_continuation_indent();
+                self.output.push_str(prefix.trim_end());
                 self.output.push('\n');
+                continue;
+            }
+            self.push_code_line_prefix();
+            self.output.push_str("    ");
+            self.output.push_str(line);
+        }
+        if !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+        self.mark_verbatim(content_start);
+    }
+
+    /// At the start of a line inside a code block or raw HTML, write the
+    /// container prefix (blockquote markers, list item indent)
+    fn push_code_line_prefix(&mut self) {
+        if self.output_is_empty() || self.output.ends_with('\n') {
+            let prefix = self.get_continuation_indent();
+            self.output.push_str(&prefix);
+        }
+    }
+
+    /// Drop the whitespace after a list marker when nothing follows it on the line
+    fn trim_item_marker(&mut self) {
+        let marker_end = self.output.trim_end_matches([' ', '\t']).len();
+        self.output.truncate(marker_end);
+    }
+
+    /// Write the ATX marker of a heading (if not setext), remember where its
+    /// text starts and enter the heading context
+    fn start_heading(&mut self, level: u32) {
+        if !self.uses_setext(level) {
+            self.output.push_str(&"#".repeat(level as usize));
+            self.output.push(' ');
+        }
+        self.heading_column = self.current_line().chars().count();
+        self.context_stack.push(Context::Heading { level });
+    }
+
+    /// End the heading line, underlining it for setext or adding the closing
+    /// `#`s for closed ATX. A setext heading can't be empty or start like
+    /// another block (its text line would be read as that block), so such a
+    /// heading falls back to ATX; an empty ATX heading never gets closed.
+    fn end_heading(&mut self) {
+        let Some(&Context::Heading { level }) = self.context_stack.last() else {
+            return;
+        };
+        let line_start = self.output.rfind('\n').map_or(0, |newline| newline + 1);
+        let text_start = self.output[line_start..]
+            .char_indices()
+            .nth(self.heading_column)
+            .map_or(self.output.len(), |(i, _)| line_start + i);
+        let width = display_width(&self.output[text_start..]);
+        if self.uses_setext(level) {
+            if width == 0 {
+                self.output.push_str(&"#".repeat(level as usize));
+            } else if starts_block(&self.output[text_start..]) {
+                let marker = format!("{} ", "#".repeat(level as usize));
+                self.output.insert_str(text_start, &marker);
+                if self.heading_close == HeadingClose::Closed {
+                    self.output.push(' ');
+                    self.output.push_str(&"#".repeat(level as usize));
+                }
             } else {
-                self.output.push_str("\n\n");
+                let underline = if level == 1 { "=" } else { "-" };
+                self.output.push('\n');
+                let indent = self.get_continuation_indent();
+                self.output.push_str(&indent);
+                self.output.push_str(&underline.repeat(width));
+            }
+        } else if self.heading_close == HeadingClose::Closed && width > 0 {
+            self.output.push(' ');
+            self.output.push_str(&"#".repeat(level as usize));
+        }
+        self.output.push('\n');
+    }
+
+    /// Whether a heading of this level is written setext style. Only
+    /// top-level headings are: in a list item or blockquote the text line
+    /// could continue the text before it, making the heading swallow it.
+    fn uses_setext(&self, level: u32) -> bool {
+        self.heading_style == HeadingStyle::SetextWhenPossible
+            && level <= 2
+            && self
+                .context_stack
+                .iter()
+                .all(|context| matches!(context, Context::Heading { .. }))
+    }
+
+    /// The line currently being written
+    fn current_line(&self) -> &str {
+        let start = self.output.rfind('\n').map_or(0, |newline| newline + 1);
+        &self.output[start..]
+    }
+
+    /// Ensure there's a blank line before the next block element. Inside
+    /// blockquotes the blank line carries the `>` markers, so the quote
+    /// continues across it.
+    fn ensure_blank_line(&mut self) {
+        if self.output_is_empty() || std::mem::take(&mut self.at_blockquote_start) {
+            return;
+        }
+        if let Some(rest) = self.output.strip_suffix('\n') {
+            let last_line = &rest[rest.rfind('\n').map_or(0, |newline| newline + 1)..];
+            if last_line.chars().all(|c| c == '>' || c.is_whitespace()) {
+                // Already blank (possibly just quote markers)
+                return;
             }
+        } else {
+            self.output.push('\n');
+        }
+        let prefix = self.get_line_prefix();
+        self.output.push_str(prefix.trim_end());
+        self.output.push('\n');
+    }
+
+    /// Start a new block after the previous one: after a blank line, except
+    /// in items of tight lists, where a blank line between two blocks would
+    /// make the list loose, so the block just starts on the next line
+    fn separate_block(&mut self) {
+        if !self.in_tight_item() {
+            self.ensure_blank_line();
+        } else if !self.output.ends_with('\n') && !self.output_is_empty() {
+            self.output.push('\n');
+        }
+    }
+
+    /// Write the blank lines the source has before a top-level block, beyond
+    /// the one every block gets (the collapsing pass caps the run)
+    fn keep_source_blank_lines(&mut self) {
+        let blank_lines = self.source_blank_lines.pop_front().unwrap_or(1);
+        if blank_lines > 1 && !self.output_is_empty() {
+            self.flush_inline_buffer();
+            self.ensure_blank_line();
+            self.output.push_str(&"\n".repeat(blank_lines - 1));
         }
     }
 
     fn handle_start_tag(&mut self, tag: Tag) {
+        if self.context_stack.is_empty() {
+            // Footnote definitions are moved to the end, so the gap before
+            // them isn't kept
+            if matches!(tag, Tag::FootnoteDefinition(_)) {
+                self.source_blank_lines.pop_front();
+            } else {
+                self.keep_source_blank_lines();
+            }
+        }
+
+        // Whether this block is the first thing in a list item (on the marker line)
+        let at_item_start =
+            std::mem::take(&mut self.at_item_start) && self.inline_buffer.is_empty();
+
         match tag {
-            Tag::Heading(level, _, _) => {
+            Tag::Heading { level, .. } => {
+                let level = heading_level(level);
                 self.flush_inline_buffer();
-                self.ensure_blank_line();
-                let level_num = level as usize;
-                self.output.push_str(&"#".repeat(level_num));
-                self.output.push(' ');
-                self.context_stack.push(Context::Heading {
-                    level: level_num as u32,
-                });
+                // A top-level H1/H2 closes the previous section
+                if self.reference_placement == ReferencePlacement::SectionEnd
+                    && level <= 2
+                    && self.context_stack.is_empty()
+                {
+                    self.write_reference_definitions();
+                }
+                if self.in_item() {
+                    // Inside a list item the heading stays on the marker line,
+                    // or goes on its own line indented under the marker
+                    if !at_item_start {
+                        if !self.output.ends_with('\n') {
+                            self.output.push('\n');
+                        }
+                        let indent = self.get_continuation_indent();
+                        self.output.push_str(&indent);
+                    }
+                } else {
+                    self.ensure_blank_line();
+                    let prefix = self.get_line_prefix();
+                    self.output.push_str(&prefix);
+                }
+                self.start_heading(level);
             }
 
             Tag::Paragraph => {
                 self.flush_inline_buffer();
                 // Don't add blank line if we're directly inside a list item
                 // (list items implicitly contain paragraphs)
-                let in_list_item = self.context_stack.last() == Some(&Context::ListItem);
-                if !in_list_item {
+                let in_list_item = self.in_item();
+                if !in_list_item || !at_item_start {
                     self.ensure_blank_line();
                 }
                 // Don't add prefix here - wrap_text will handle it
@@ -534,27 +2533,71 @@ impl Formatter {
 
             Tag::List(first_item_number) => {
                 self.flush_inline_buffer();
-                // Only add blank line before top-level lists, not nested ones
-                // A nested list is one that starts while we're inside a ListItem
-                let in_list_item = self.context_stack.last() == Some(&Context::ListItem);
-                if !in_list_item {
+                // Top-level lists always get a blank line before them. A nested
+                // list (one that starts while we're inside a ListItem) only does
+                // in a loose parent list, where the blank line may be what
+                // makes the parent loose (loose nested-list spacing makes every
+                // parent of a sublist loose).
+                let in_list_item = self.in_item();
+                let loose_parent = match self.context_stack[..] {
+                    [.., Context::FootnoteDefinition] => {
+                        self.nested_list_spacing == NestedListSpacing::Loose
+                    }
+                    _ => !self.in_tight_item(),
+                };
+                let mut interrupts_paragraph = false;
+                if at_item_start {
+                    // A sublist as the item's first block starts on the next
+                    // line (a blank line here would end the parent item)
+                    self.trim_item_marker();
+                } else if !in_list_item || loose_parent {
                     self.ensure_blank_line();
+                } else {
+                    interrupts_paragraph = true;
                 }
                 self.list_depth += 1;
+                let item_count = self.list_item_counts.pop_front().unwrap_or(0);
+                let start = match self.ordered_list_mode {
+                    // Only a list starting at 1 can interrupt a paragraph
+                    OrderedListMode::Ascending if interrupts_paragraph => 1,
+                    OrderedListMode::Ascending => self.ordered_start,
+                    _ => first_item_number.unwrap_or(1),
+                };
+                // Numbers past nine digits aren't list markers, so a list that
+                // would reach them keeps its own first number, or starts at 1
+                let fits = |start: u64| {
+                    start
+                        .checked_add(item_count.saturating_sub(1) as u64)
+                        .is_some_and(|last| last <= MAX_LIST_NUMBER)
+                };
+                let start = [start, first_item_number.unwrap_or(1), 1]
+                    .into_iter()
+                    .find(|&start| fits(start))
+                    .unwrap_or(1);
+                let number_width = match self.ordered_list_mode {
+                    OrderedListMode::Ascending | OrderedListMode::Preserve
+                        if self.align_ordered_markers =>
+                    {
+                        (start + item_count.saturating_sub(1) as u64)
+                            .to_string()
+                            .len()
+                    }
+                    _ => 0,
+                };
                 self.context_stack.push(Context::List {
                     ordered: first_item_number.is_some(),
                     item_count: 0,
+                    start,
+                    number_width,
+                    loose: self.loose_lists.pop_front().unwrap_or(false),
                 });
             }
 
             Tag::Item => {
                 self.flush_inline_buffer();
-                if !self.output.ends_with('\n') && !self.output.is_empty() {
-                    self.output.push('\n');
-                }
 
                 // Increment the item count for the current list
-                let (is_ordered, item_number) = self
+                let (is_ordered, item_number, start, number_width, loose) = self
                     .context_stack
                     .iter_mut()
                     .rev()
@@ -562,64 +2605,170 @@ impl Formatter {
                         Context::List {
                             ordered,
                             item_count,
+                            start,
+                            number_width,
+                            loose,
                         } => {
                             *item_count += 1;
-                            Some((*ordered, *item_count))
+                            Some((*ordered, *item_count, *start, *number_width, *loose))
                         }
                         _ => None,
                     })
-                    .unwrap_or((false, 1));
+                    .unwrap_or((false, 1, 1, 0, false));
+
+                // Items of a loose list are separated by blank lines
+                if loose && item_number > 1 {
+                    self.ensure_blank_line();
+                } else if !self.output.ends_with('\n') && !self.output_is_empty() {
+                    self.output.push('\n');
+                }
 
                 // Add blockquote prefix
                 let prefix = self.get_line_prefix();
                 self.output.push_str(&prefix);
 
-                // Add list indentation (for nested lists)
-                if self.list_depth > 1 {
-                    self.output.push_str(&"  ".repeat(self.list_depth - 1));
-                }
+                // Nested items start at their parent item's content column
+                let indent = self.item_content_indent();
+                self.output.push_str(&self.indent(indent));
 
                 // Add list marker
-                if is_ordered {
-                    match self.ordered_list_mode {
-                        OrderedListMode::One => self.output.push_str("1. "),
-                        OrderedListMode::Ascending => {
-                            self.output.push_str(&format!("{}. ", item_number));
+                let marker = if is_ordered {
+                    let number = match self.ordered_list_mode {
+                        OrderedListMode::One => 1,
+                        OrderedListMode::Ascending | OrderedListMode::Preserve => {
+                            start + item_number as u64 - 1
                         }
-                    }
+                    };
+                    format!(
+                        "{:>width$}{}",
+                        number,
+                        self.ordered_list_delimiter.delimiter(),
+                        width = number_width
+                    )
+                } else {
+                    self.bullet_style.marker().to_string()
+                };
+                // Content starts at the next tab stop, or after the padding
+                let content_indent = match self.indent_style {
+                    IndentStyle::Tab if self.uses_tabs() => (indent + marker.len()) / 4 * 4 + 4,
+                    IndentStyle::Spaces(width) => indent + width.max(marker.len() + 1),
+                    IndentStyle::Tab => indent + marker.len() + 1,
+                };
+                self.output.push_str(&marker);
+                if self.uses_tabs() {
+                    self.output.push('\t');
                 } else {
-                    self.output.push_str("- ");
+                    let padding = content_indent - indent - marker.len();
+                    self.output.push_str(&" ".repeat(padding));
                 }
+                self.item_indents.push(content_indent);
+                self.at_item_start = true;
 
                 self.context_stack.push(Context::ListItem);
             }
 
-            Tag::BlockQuote => {
+            Tag::FootnoteDefinition(label) => {
+                self.flush_inline_buffer();
+                // Rendered on its own and collected for the end of the document;
+                // content lines after the first are indented four spaces
+                self.document_output = Some((
+                    std::mem::take(&mut self.output),
+                    std::mem::take(&mut self.verbatim),
+                ));
+                self.output.push_str(&format!("[^{}]: ", label));
+                self.item_indents.push(4);
+                self.at_item_start = true;
+                self.context_stack.push(Context::FootnoteDefinition);
+            }
+
+            Tag::BlockQuote(kind) => {
                 self.flush_inline_buffer();
-                self.ensure_blank_line();
-                self.blockquote_depth += 1;
+                if at_item_start {
+                    // As an item's first block the quote starts on the next
+                    // line, like a sublist (`- > quote` would need the marker
+                    // line to hold two prefixes)
+                    self.trim_item_marker();
+                    self.output.push('\n');
+                    self.at_blockquote_start = true;
+                } else {
+                    self.separate_block();
+                    // In a tight item there's no blank line before the quote,
+                    // and its first block mustn't add one
+                    self.at_blockquote_start = self.in_tight_item();
+                }
+                let mut prefix = self.get_continuation_indent();
+                prefix.push_str("> ");
+                self.blockquotes.push((prefix, self.item_indents.len()));
                 self.context_stack.push(Context::Blockquote);
+                if let Some(kind) = kind {
+                    self.push_code_line_prefix();
+                    self.output.push_str(blockquote_tag(kind));
+                    self.output.push('\n');
+                    self.at_blockquote_start = true;
+                }
             }
 
             Tag::CodeBlock(kind) => {
                 self.flush_inline_buffer();
-                self.ensure_blank_line();
-                self.in_code_block = true;
+                // As an item's first block, the opening fence goes on the marker line
+                if !at_item_start {
+                    self.separate_block();
+                }
 
                 // Extract language if specified
-                let lang = match kind {
-                    pulldown_cmark::CodeBlockKind::Fenced(lang) if !lang.is_empty() => {
-                        lang.to_string()
+                let info = match &kind {
+                    pulldown_cmark::CodeBlockKind::Fenced(lang) if self.normalize_code_lang => {
+                        self.normalize_info(lang)
                     }
-                    _ => String::new(),
+                    pulldown_cmark::CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    pulldown_cmark::CodeBlockKind::Indented => String::new(),
                 };
-
-                self.output.push_str("```");
-                self.output.push_str(&lang);
-                self.output.push('\n');
+                let indented = matches!(kind, pulldown_cmark::CodeBlockKind::Indented)
+                    && self.code_block_style == CodeBlockStyle::Preserve
+                    && !self.after_list
+                    && (at_item_start || !self.in_tight_item());
+                self.code_block = Some(CodeBlockState {
+                    info,
+                    content: String::new(),
+                    indented,
+                });
                 self.context_stack.push(Context::CodeBlock);
             }
 
+            Tag::HtmlBlock => {
+                self.flush_inline_buffer();
+                // As an item's first block, the HTML starts on the marker line
+                if !at_item_start {
+                    self.separate_block();
+                }
+            }
+
+            Tag::Table(alignments) => {
+                self.flush_inline_buffer();
+                // As an item's first block, the header row goes on the marker line
+                if !at_item_start {
+                    self.separate_block();
+                }
+                self.table = Some(TableState {
+                    alignments,
+                    ..TableState::default()
+                });
+                self.context_stack.push(Context::Table);
+            }
+
+            Tag::TableHead => {
+                self.context_stack.push(Context::TableHead);
+            }
+
+            Tag::TableRow => {
+                self.context_stack.push(Context::TableRow);
+            }
+
+            Tag::TableCell => {
+                self.inline_buffer.clear();
+                self.context_stack.push(Context::TableCell);
+            }
+
             Tag::Strong => {
                 self.inline_buffer.push(InlineElement::StrongStart);
                 self.context_stack.push(Context::Strong);
@@ -635,40 +2784,134 @@ impl Formatter {
                 self.context_stack.push(Context::Strikethrough);
             }
 
-            Tag::Link(_, url, _) => {
+            Tag::Link {
+                link_type,
+                dest_url: url,
+                title,
+                id,
+            } => {
+                // Autolinks are written whole here; their text is the URL itself
+                if matches!(link_type, LinkType::Autolink | LinkType::Email) {
+                    self.inline_buffer
+                        .push(InlineElement::Text(format!("<{}>", url)));
+                    self.context_stack.push(Context::Autolink);
+                    return;
+                }
                 self.inline_buffer.push(InlineElement::LinkStart);
+                let reference = match self.link_style {
+                    LinkStyle::Inline => None,
+                    LinkStyle::Reference => {
+                        Some((format!("][{}]", self.reference_id(&url, &title)), None))
+                    }
+                    LinkStyle::PreserveReference => {
+                        self.source_reference(link_type, &id, &url, &title)
+                    }
+                };
                 self.context_stack.push(Context::Link {
                     url: url.to_string(),
+                    title: title.to_string(),
+                    reference,
+                });
+            }
+
+            Tag::Image {
+                link_type,
+                dest_url: url,
+                title,
+                id,
+            } => {
+                self.inline_buffer.push(InlineElement::ImageStart);
+                let reference = match self.link_style {
+                    LinkStyle::PreserveReference => {
+                        self.source_reference(link_type, &id, &url, &title)
+                    }
+                    _ => None,
+                };
+                self.context_stack.push(Context::Image {
+                    url: url.to_string(),
+                    title: title.to_string(),
+                    reference,
+                });
+            }
+
+            Tag::DefinitionList => {
+                self.flush_inline_buffer();
+                if !self.in_item() || !at_item_start {
+                    self.ensure_blank_line();
+                }
+                // The first term of an item's first block goes on the marker line
+                self.at_item_start = at_item_start && self.in_item();
+                self.context_stack.push(Context::DefinitionList {
+                    loose: self.loose_lists.pop_front().unwrap_or(false),
                 });
             }
 
-            Tag::Image(_, url, title) => {
-                self.inline_buffer.push(InlineElement::ImageStart);
-                self.context_stack.push(Context::Image {
-                    url: url.to_string(),
-                    title: title.to_string(),
-                });
+            Tag::DefinitionListTitle => {
+                self.flush_inline_buffer();
+                // Terms are separated by blank lines, so a term can't be read
+                // as a lazy continuation of the previous definition
+                if !at_item_start {
+                    self.ensure_blank_line();
+                    if self.output_is_empty() || self.output.ends_with('\n') {
+                        let indent = self.get_continuation_indent();
+                        self.output.push_str(&indent);
+                    }
+                }
+                self.context_stack.push(Context::DefinitionTitle);
+            }
+
+            Tag::DefinitionListDefinition => {
+                self.flush_inline_buffer();
+                if matches!(
+                    self.context_stack.last(),
+                    Some(Context::DefinitionList { loose: true })
+                ) {
+                    self.ensure_blank_line();
+                } else if !self.output.ends_with('\n') && !self.output_is_empty() {
+                    self.output.push('\n');
+                }
+                // Content lines after the first line up after the `: `
+                let indent = self.get_continuation_indent();
+                self.output.push_str(&indent);
+                self.output.push_str(": ");
+                self.item_indents.push(self.item_content_indent() + 2);
+                self.at_item_start = true;
+                self.context_stack.push(Context::Definition);
             }
 
-            _ => {}
+            // Extensions that `parser_options` doesn't enable
+            Tag::Superscript | Tag::Subscript | Tag::MetadataBlock(_) => {}
         }
     }
 
-    fn handle_end_tag(&mut self, tag: Tag) {
+    fn handle_end_tag(&mut self, tag: TagEnd) {
         match tag {
-            Tag::Heading { .. } => {
+            TagEnd::Heading(_) => {
                 self.flush_inline_buffer();
-                self.output.push('\n');
+                self.end_heading();
                 self.context_stack.pop();
             }
 
-            Tag::Paragraph => {
-                self.flush_inline_buffer();
-                self.output.push('\n');
+            TagEnd::Paragraph => {
+                if let Some(level) = self.pseudo_heading() {
+                    // Drop the surrounding strong/emphasis markers and emit
+                    // the remaining content as a real heading
+                    self.inline_buffer.remove(0);
+                    self.inline_buffer.pop();
+                    let prefix = self.get_line_prefix();
+                    self.output.push_str(&prefix);
+                    self.context_stack.pop();
+                    self.start_heading(level);
+                    self.flush_inline_buffer();
+                    self.end_heading();
+                } else {
+                    self.flush_inline_buffer();
+                    self.output.push('\n');
+                }
                 self.context_stack.pop();
             }
 
-            Tag::List(_) => {
+            TagEnd::List(_) => {
                 self.flush_inline_buffer();
                 if !self.output.ends_with('\n') {
                     self.output.push('\n');
@@ -677,69 +2920,211 @@ impl Formatter {
                 self.context_stack.pop();
             }
 
-            Tag::Item => {
+            TagEnd::Item => {
+                let empty_item =
+                    std::mem::take(&mut self.at_item_start) && self.inline_buffer.is_empty();
+                self.flush_inline_buffer();
+                if empty_item {
+                    self.trim_item_marker();
+                }
+                self.item_indents.pop();
+                self.context_stack.pop();
+            }
+
+            TagEnd::FootnoteDefinition => {
+                let empty =
+                    std::mem::take(&mut self.at_item_start) && self.inline_buffer.is_empty();
                 self.flush_inline_buffer();
+                if empty {
+                    self.trim_item_marker();
+                }
+                self.item_indents.pop();
                 self.context_stack.pop();
+                if let Some((document, verbatim)) = self.document_output.take() {
+                    let mut definition = std::mem::replace(&mut self.output, document);
+                    let definition_verbatim = std::mem::replace(&mut self.verbatim, verbatim);
+                    definition.truncate(definition.trim_end().len());
+                    self.footnotes.push((definition, definition_verbatim));
+                }
             }
 
-            Tag::BlockQuote => {
+            TagEnd::BlockQuote(_) => {
                 self.flush_inline_buffer();
+                self.at_blockquote_start = false;
                 if !self.output.ends_with('\n') {
                     self.output.push('\n');
                 }
-                self.blockquote_depth = self.blockquote_depth.saturating_sub(1);
+                self.blockquotes.pop();
                 self.context_stack.pop();
             }
 
-            Tag::CodeBlock(_) => {
-                self.output.push_str("```\n");
-                self.in_code_block = false;
+            TagEnd::CodeBlock => {
+                if let Some(code_block) = self.code_block.take() {
+                    self.write_code_block(code_block);
+                }
                 self.context_stack.pop();
             }
 
-            Tag::Strong => {
+            TagEnd::HtmlBlock => {}
+
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.write_table(table);
+                }
+                self.context_stack.pop();
+            }
+
+            TagEnd::TableHead | TagEnd::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    let row = std::mem::take(&mut table.current_row);
+                    table.rows.push(row);
+                }
+                self.context_stack.pop();
+            }
+
+            TagEnd::TableCell => {
+                let cell = self.render_inline_buffer().trim().replace(KEPT_SPACE, " ");
+                self.inline_buffer.clear();
+                if let Some(table) = self.table.as_mut() {
+                    table.current_row.push(cell);
+                }
+                self.context_stack.pop();
+            }
+
+            TagEnd::Strong => {
                 self.inline_buffer.push(InlineElement::StrongEnd);
                 self.context_stack.pop();
             }
 
-            Tag::Emphasis => {
+            TagEnd::Emphasis => {
                 self.inline_buffer.push(InlineElement::EmphasisEnd);
                 self.context_stack.pop();
             }
 
-            Tag::Strikethrough => {
+            TagEnd::Strikethrough => {
                 self.inline_buffer.push(InlineElement::StrikethroughEnd);
                 self.context_stack.pop();
             }
 
-            Tag::Link(_, _, _) => {
-                // Get the URL from context
-                if let Some(Context::Link { url }) = self.context_stack.pop() {
-                    self.inline_buffer.push(InlineElement::LinkEnd(url));
+            TagEnd::Link => {
+                // Get the URL and title from context
+                match self.context_stack.pop() {
+                    Some(Context::Link {
+                        reference: Some((suffix, label)),
+                        ..
+                    }) => {
+                        self.inline_buffer
+                            .push(InlineElement::ReferenceLinkEnd { suffix, label });
+                    }
+                    Some(Context::Link { url, title, .. }) => {
+                        self.inline_buffer
+                            .push(InlineElement::LinkEnd { url, title });
+                    }
+                    // Written whole at its start tag
+                    Some(Context::Autolink) => {}
+                    _ => {}
                 }
             }
 
-            Tag::Image(_, _, _) => {
+            TagEnd::Image => {
                 // Get the URL and title from context
-                if let Some(Context::Image { url, title }) = self.context_stack.pop() {
-                    self.inline_buffer
-                        .push(InlineElement::ImageEnd { url, title });
+                match self.context_stack.pop() {
+                    Some(Context::Image {
+                        reference: Some((suffix, label)),
+                        ..
+                    }) => {
+                        self.inline_buffer
+                            .push(InlineElement::ReferenceLinkEnd { suffix, label });
+                    }
+                    Some(Context::Image { url, title, .. }) => {
+                        self.inline_buffer
+                            .push(InlineElement::ImageEnd { url, title });
+                    }
+                    _ => {}
                 }
             }
 
-            _ => {}
+            TagEnd::DefinitionList => {
+                self.flush_inline_buffer();
+                if !self.output.ends_with('\n') {
+                    self.output.push('\n');
+                }
+                self.context_stack.pop();
+            }
+
+            TagEnd::DefinitionListTitle => {
+                self.flush_inline_buffer();
+                self.output.push('\n');
+                self.context_stack.pop();
+            }
+
+            TagEnd::DefinitionListDefinition => {
+                let empty =
+                    std::mem::take(&mut self.at_item_start) && self.inline_buffer.is_empty();
+                self.flush_inline_buffer();
+                if empty {
+                    self.trim_item_marker();
+                }
+                self.item_indents.pop();
+                self.context_stack.pop();
+            }
+
+            TagEnd::Superscript | TagEnd::Subscript | TagEnd::MetadataBlock(_) => {}
         }
     }
 
     fn handle_text(&mut self, text: CowStr) {
-        if self.in_code_block {
-            // Code blocks: preserve exactly
-            self.output.push_str(&text);
-        } else {
-            // Regular text: add to inline buffer
-            self.inline_buffer
-                .push(InlineElement::Text(text.to_string()));
+        if let Some(code_block) = &mut self.code_block {
+            // Code blocks: buffered as-is and written at their end tag
+            code_block.content.push_str(&text);
+            return;
+        }
+        if self.context_stack.last() == Some(&Context::Autolink) {
+            return;
+        }
+
+        let mut text = self.convert_punctuation(&text);
+        if let Some(width) = self.expand_tabs {
+            text = text.replace('\t', &" ".repeat(width));
+        }
+        let text = self.escape_table_pipes(&text);
+        self.inline_buffer.push(InlineElement::Text(text));
+    }
+
+    /// Apply the punctuation style to a run of prose text
+    fn convert_punctuation(&self, text: &str) -> String {
+        // A link whose text is its URL must keep it as written
+        let is_url = matches!(
+            self.context_stack.last(),
+            Some(Context::Link { url, .. }) if url.ends_with(text)
+        );
+        match self.punctuation {
+            Punctuation::Keep => text.to_string(),
+            _ if is_url => text.to_string(),
+            Punctuation::Smart => smart_punctuation(text, self.previous_inline_char()),
+            Punctuation::Straight => straight_punctuation(text),
+        }
+    }
+
+    /// The character written just before the next inline text, looking
+    /// through emphasis and link openers (`None` at the start of a block)
+    fn previous_inline_char(&self) -> Option<char> {
+        for elem in self.inline_buffer.iter().rev() {
+            match elem {
+                InlineElement::Text(s) if s.is_empty() => {}
+                InlineElement::Text(s) => return s.chars().last(),
+                InlineElement::Code(_) => return Some('`'),
+                InlineElement::HardBreak | InlineElement::SoftBreak => return Some(' '),
+                InlineElement::LinkEnd { .. }
+                | InlineElement::ImageEnd { .. }
+                | InlineElement::ReferenceLinkEnd { .. } => return Some(')'),
+                InlineElement::FootnoteReference(_) => return Some(']'),
+                InlineElement::TaskMarker(_) => return Some(' '),
+                InlineElement::Math { .. } => return Some('$'),
+                _ => {}
+            }
         }
+        None
     }
 
     fn handle_inline_code(&mut self, code: CowStr) {
@@ -747,17 +3132,82 @@ impl Formatter {
             .push(InlineElement::Code(code.to_string()));
     }
 
-    fn handle_html(&mut self, html: CowStr) {
+    /// Write the block after a top-level `<!-- mdfmt-ignore -->` from its
+    /// source text, and skip its events. Returns whether `event` was skipped.
+    fn skip_ignored_block(&mut self, event: &Event) -> bool {
+        if self.ignored_depth > 0 {
+            match event {
+                Event::Start(_) => self.ignored_depth += 1,
+                Event::End(_) => self.ignored_depth -= 1,
+                _ => {}
+            }
+            return true;
+        }
+        if !matches!(event, Event::Start(_) | Event::Rule)
+            || !std::mem::take(&mut self.ignore_next_block)
+        {
+            return false;
+        }
+        let Some(source) = self.ignored_blocks.pop_front() else {
+            return false;
+        };
+
+        if matches!(event, Event::Start(_)) {
+            self.ignored_depth = 1;
+        }
+        self.keep_source_blank_lines();
         self.flush_inline_buffer();
         self.ensure_blank_line();
-        self.output.push_str(&html);
+        let start = self.output.len();
+        self.output.push_str(&source);
+        self.output.push('\n');
+        self.mark_verbatim(start);
+        true
+    }
+
+    fn handle_html(&mut self, html: CowStr) {
+        if self.context_stack.is_empty() && html.trim() == IGNORE_DIRECTIVE {
+            self.ignore_next_block = true;
+        }
+        // Raw HTML lines keep the container prefix, like code lines, and are
+        // handed over as they are
+        let start = self.output.len();
+        for line in html.split_inclusive('\n') {
+            if line.trim().is_empty() {
+                if !self.output.ends_with('\n') {
+                    self.output.push('\n');
+                }
+                let prefix = self.get_continuation_indent();
+                self.output.push_str(prefix.trim_end());
+                self.output.push('\n');
+            } else {
+                self.push_code_line_prefix();
+                self.output.push_str(line);
+            }
+        }
         if !html.ends_with('\n') {
             self.output.push('\n');
         }
+        self.mark_verbatim(start);
+    }
+
+    /// Write display math as a `$$...$$` block, keeping the formula's lines
+    /// as written
+    fn write_display_math(&mut self, math: &str) {
+        self.push_code_line_prefix();
+        self.output.push_str("$$");
+        for (n, line) in math.split('\n').enumerate() {
+            if n > 0 {
+                self.output.push('\n');
+                self.push_code_line_prefix();
+            }
+            self.output.push_str(line);
+        }
+        self.output.push_str("$$");
     }
 
     fn handle_soft_break(&mut self) {
-        if !self.in_code_block {
+        if self.code_block.is_none() {
             // Soft break = space (will be normalized during flush)
             self.inline_buffer.push(InlineElement::SoftBreak);
         }
@@ -768,19 +3218,656 @@ impl Formatter {
         self.inline_buffer.push(InlineElement::HardBreak);
     }
 
+    /// Get the reference id for a link target, registering a new one if needed
+    fn reference_id(&mut self, url: &str, title: &str) -> usize {
+        let key = (url.to_string(), title.to_string());
+        if let Some(&id) = self.reference_ids.get(&key) {
+            return id;
+        }
+        let id = self.references.len() + 1;
+        self.references
+            .push((id.to_string(), url.to_string(), title.to_string()));
+        self.reference_ids.insert(key, id);
+        id
+    }
+
+    /// For a link or image written as a reference in the source, register
+    /// its definition under the source label and return what follows its
+    /// text (`][label]`, `][]` or `]`), with the label the text has to match
+    /// for the last two. `None` for inline links.
+    fn source_reference(
+        &mut self,
+        link_type: LinkType,
+        label: &str,
+        url: &str,
+        title: &str,
+    ) -> Option<(String, Option<String>)> {
+        let (suffix, implicit) = match link_type {
+            LinkType::Reference => (format!("][{}]", label), false),
+            LinkType::Collapsed => ("][]".to_string(), true),
+            LinkType::Shortcut => ("]".to_string(), true),
+            _ => return None,
+        };
+        // Labels match case-insensitively; the first spelling is kept
+        let defined = self
+            .references
+            .iter()
+            .any(|(defined, ..)| defined.to_lowercase() == label.to_lowercase());
+        if !defined {
+            self.references
+                .push((label.to_string(), url.to_string(), title.to_string()));
+        }
+        Some((suffix, implicit.then(|| label.to_string())))
+    }
+
+    /// Write the footnote definitions collected so far, separated by blank lines
+    fn write_footnote_definitions(&mut self) {
+        if self.footnotes.is_empty() {
+            return;
+        }
+        self.ensure_blank_line();
+        for (n, (definition, verbatim)) in
+            std::mem::take(&mut self.footnotes).into_iter().enumerate()
+        {
+            if n > 0 {
+                self.output.push_str("\n\n");
+            }
+            let start = self.output.len();
+            self.output.push_str(&definition);
+            for range in verbatim {
+                let end = range.end.min(definition.len());
+                self.verbatim
+                    .push(start + range.start.min(end)..start + end);
+            }
+        }
+        self.output.push('\n');
+    }
+
+    /// Write any reference definitions collected since the last block of definitions
+    fn write_reference_definitions(&mut self) {
+        if self.references_written == self.references.len() {
+            return;
+        }
+        self.ensure_blank_line();
+        for (label, url, title) in self.references.iter().skip(self.references_written) {
+            self.output
+                .push_str(&format!("[{}]: {}\n", label, link_destination(url, title)));
+        }
+        self.references_written = self.references.len();
+    }
+
+    /// Emit a buffered table, padding columns according to the table style
+    fn write_table(&mut self, mut table: TableState) {
+        let column_count = table
+            .rows
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .unwrap_or(0)
+            .max(table.alignments.len());
+        if column_count == 0 {
+            return;
+        }
+
+        if self.align_numeric_columns {
+            table.alignments.resize(column_count, Alignment::None);
+            for (i, alignment) in table.alignments.iter_mut().enumerate() {
+                let mut cells = table
+                    .rows
+                    .iter()
+                    .skip(1)
+                    .filter_map(|row| row.get(i))
+                    .filter(|cell| !cell.is_empty())
+                    .peekable();
+                if *alignment == Alignment::None
+                    && cells.peek().is_some()
+                    && cells.all(|cell| is_numeric_cell(cell))
+                {
+                    *alignment = Alignment::Right;
+                }
+            }
+        }
+
+        // Column widths (only used for padded tables); separators need at least 3 dashes
+        let mut widths = vec![3; column_count];
+        if self.table_style == TableStyle::Padded {
+            for row in &table.rows {
+                for (i, cell) in row.iter().enumerate() {
+                    widths[i] = widths[i].max(display_width(cell));
+                }
+            }
+        }
+
+        let empty = String::new();
+
+        // Rows are indented to the enclosing container, like code lines
+        for (row_idx, row) in table.rows.iter().enumerate() {
+            self.push_code_line_prefix();
+            self.output.push('|');
+            for (i, width) in widths.iter().enumerate() {
+                let cell = row.get(i).unwrap_or(&empty);
+                let alignment = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                self.output.push(' ');
+                match self.table_style {
+                    TableStyle::Padded => {
+                        self.output.push_str(&pad_cell(cell, *width, alignment));
+                    }
+                    TableStyle::Compact => self.output.push_str(cell),
+                }
+                self.output.push_str(" |");
+            }
+            self.output.push('\n');
+
+            // Separator row goes right after the header
+            if row_idx == 0 {
+                self.push_code_line_prefix();
+                self.output.push('|');
+                for (i, width) in widths.iter().enumerate() {
+                    let alignment = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                    self.output.push(' ');
+                    self.output.push_str(&separator_cell(*width, alignment));
+                    self.output.push_str(" |");
+                }
+                self.output.push('\n');
+            }
+        }
+    }
+
     fn handle_rule(&mut self) {
+        if self.context_stack.is_empty() {
+            self.keep_source_blank_lines();
+        }
+        // As an item's first block, the rule goes on the marker line
+        let at_item_start =
+            std::mem::take(&mut self.at_item_start) && self.inline_buffer.is_empty();
         self.flush_inline_buffer();
-        self.ensure_blank_line();
-        self.output.push_str("---\n");
+        let mut style = self.horizontal_rule;
+        if at_item_start {
+            // After `- ` markers, a `---` rule would make the whole line one
+            // rule instead of an item holding it
+            let markers: String = self
+                .current_line()
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '\t' | '>'))
+                .collect();
+            if !markers.is_empty() && markers.chars().all(|c| c == style.marker) {
+                style.marker = if style.marker == '-' { '*' } else { '-' };
+            }
+        } else {
+            // Right below the text of a tight list item, `---` would
+            // underline it as a setext heading
+            if self.in_tight_item() && !self.output.ends_with('\n') && style.marker == '-' {
+                style.marker = '*';
+            }
+            self.separate_block();
+        }
+        let mut rule = style.to_string();
+        if self.output_is_empty() {
+            // A document starting with `---` would open frontmatter
+            if rule == "---" {
+                rule.push('-');
+            }
+        } else if self.output.ends_with('\n') {
+            let indent = self.get_continuation_indent();
+            self.output.push_str(&indent);
+        }
+        self.output.push_str(&rule);
+        self.output.push('\n');
     }
 
     fn handle_task_list_marker(&mut self, checked: bool) {
-        if checked {
-            self.inline_buffer
-                .push(InlineElement::Text("[x] ".to_string()));
+        self.inline_buffer.push(InlineElement::TaskMarker(checked));
+    }
+}
+
+/// Format several markdown fragments and join them into one well-formed document.
+///
+/// Fragments are separated by a blank line. When one fragment ends with a list
+/// and the next starts with one, an empty HTML comment is placed between them
+/// so the two lists don't merge when the result is parsed again. Only the first
+/// fragment may carry frontmatter.
+pub fn concat_documents(docs: &[&str], formatter: &mut Formatter) -> String {
+    let mut result = String::new();
+    let mut has_body = false;
+    let mut previous_ends_with_list = false;
+
+    for (i, doc) in docs.iter().enumerate() {
+        let (frontmatter, content) = if i == 0 {
+            extract_frontmatter(doc)
+        } else {
+            (None, *doc)
+        };
+        if let Some(fm) = frontmatter {
+            result.push_str(&formatter.frontmatter_block(fm, content));
+        }
+
+        let events = parse_markdown(content);
+        let starts_with_list = matches!(events.first(), Some(Event::Start(Tag::List(_))));
+        let ends_with_list = matches!(events.last(), Some(Event::End(TagEnd::List(_))));
+        let formatted = formatter.format(events);
+        if formatted.is_empty() {
+            continue;
+        }
+
+        if has_body {
+            // Fragments may come without a trailing newline (embedding mode)
+            if !result.ends_with('\n') {
+                result.push('\n');
+            }
+            result.push('\n');
+            if previous_ends_with_list && starts_with_list {
+                result.push_str("<!-- -->\n\n");
+            }
+        }
+        result.push_str(&formatted);
+        has_body = true;
+        previous_ends_with_list = ends_with_list;
+    }
+
+    result
+}
+
+/// If the last wrapped line holds a single word, pull the last word of the
+/// line before it down, as long as that line keeps at least one word, the
+/// new last line still fits and doesn't start like a block
+fn avoid_widow(lines: &mut [String], first_prefix: &str, continuation_prefix: &str, width: usize) {
+    let previous_prefix = if lines.len() == 2 {
+        first_prefix
+    } else {
+        continuation_prefix
+    };
+    let [.., previous, last] = lines else {
+        return;
+    };
+    let last_word = &last[continuation_prefix.len()..];
+    if last_word.contains(' ') {
+        return;
+    }
+    let Some(split) = previous[previous_prefix.len()..].rfind(' ') else {
+        return;
+    };
+    let split = previous_prefix.len() + split;
+    let moved = &previous[split + 1..];
+    if display_width(continuation_prefix) + moved.width() + 1 + last_word.width() > width
+        || starts_block(moved)
+    {
+        return;
+    }
+
+    *last = format!("{}{} {}", continuation_prefix, moved, last_word);
+    previous.truncate(split);
+}
+
+/// The built-in code block language aliases: `js`, `ts`, `py`, `rb`, `sh`,
+/// `shell`, `yml` and `md` mapped to their full names
+pub fn default_code_lang_aliases() -> HashMap<String, String> {
+    [
+        ("js", "javascript"),
+        ("ts", "typescript"),
+        ("py", "python"),
+        ("rb", "ruby"),
+        ("sh", "bash"),
+        ("shell", "bash"),
+        ("yml", "yaml"),
+        ("md", "markdown"),
+    ]
+    .into_iter()
+    .map(|(alias, name)| (alias.to_string(), name.to_string()))
+    .collect()
+}
+
+/// Columns taken up by `text`, with wide characters counting double and
+/// tabs advancing to the next multiple of 4
+fn display_width(text: &str) -> usize {
+    text.chars().fold(0, |column, c| match c {
+        '\t' => column / 4 * 4 + 4,
+        c => column + c.width().unwrap_or(0),
+    })
+}
+
+/// Whether a line starting with `text` would open a block (an ATX heading,
+/// blockquote, list item, code fence, thematic break or setext underline)
+/// instead of holding prose
+fn starts_block(text: &str) -> bool {
+    let text = text.trim_start();
+    let marker_ends = |rest: &str| rest.is_empty() || rest.starts_with([' ', '\t']);
+    let hashes = text.len() - text.trim_start_matches('#').len();
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rule = |marker: char| {
+        text.chars().all(|c| c == marker || c == ' ' || c == '\t')
+            && text.matches(marker).count() >= 3
+    };
+    (1..=6).contains(&hashes) && marker_ends(&text[hashes..])
+        || text.starts_with('>')
+        || text.starts_with(['-', '+', '*']) && marker_ends(&text[1..])
+        || (1..=9).contains(&digits)
+            && text[digits..].starts_with(['.', ')'])
+            && marker_ends(&text[digits + 1..])
+        || text.starts_with("```")
+        || text.starts_with("~~~")
+        || rule('-')
+        || rule('*')
+        || rule('_')
+        || !text.is_empty() && text.trim_end().chars().all(|c| c == '=' || c == '-')
+}
+
+/// Pieces of `word` no wider than `width`, or the whole word if it fits,
+/// is a bare URL, contains Markdown syntax that splitting would break or
+/// has a piece that would start a block at the start of its line
+fn split_long_word(word: &str, width: usize) -> Vec<&str> {
+    let syntax = [
+        '[', ']', '(', ')', '<', '>', '`', '$', '*', '_', '~', '\\', KEPT_SPACE,
+    ];
+    if width == 0 || word.width() <= width || word.contains(syntax) || word.contains("://") {
+        return vec![word];
+    }
+
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut piece_width = 0;
+    for (i, c) in word.char_indices() {
+        let char_width = c.width().unwrap_or(0);
+        if piece_width + char_width > width && i > start {
+            pieces.push(&word[start..i]);
+            start = i;
+            piece_width = 0;
+        }
+        piece_width += char_width;
+    }
+    pieces.push(&word[start..]);
+    if pieces[1..].iter().any(|piece| starts_block(piece)) {
+        return vec![word];
+    }
+    pieces
+}
+
+/// Remove trailing spaces and tabs from every line
+fn trim_line_ends(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let content = line.strip_suffix('\n').unwrap_or(line);
+        result.push_str(content.trim_end_matches([' ', '\t']));
+        result.push_str(&line[content.len()..]);
+    }
+    result
+}
+
+/// Whether each list or definition list of the event stream is loose (its
+/// items or definitions hold their text in paragraphs, or with
+/// `sublists_loose`, hold a sublist), in the order the lists start
+fn loose_lists(events: &[Event], sublists_loose: bool) -> VecDeque<bool> {
+    let mut loose = VecDeque::new();
+    // Open blocks: the index of each open list, `None` for other tags
+    let mut open: Vec<Option<usize>> = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(Tag::List(_) | Tag::DefinitionList) => {
+                if let (true, [.., Some(list), None]) = (sublists_loose, &open[..]) {
+                    loose[*list] = true;
+                }
+                open.push(Some(loose.len()));
+                loose.push_back(false);
+            }
+            Event::Start(Tag::Paragraph) => {
+                // A paragraph directly inside an item or definition:
+                // [.., list, item]
+                if let [.., Some(list), None] = open[..] {
+                    loose[list] = true;
+                }
+                open.push(None);
+            }
+            Event::Start(_) => open.push(None),
+            Event::End(_) => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    loose
+}
+
+/// Number of items in each list of the event stream, in the order the
+/// lists start
+fn count_list_items(events: &[Event]) -> VecDeque<usize> {
+    let mut counts = VecDeque::new();
+    let mut open = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(Tag::List(_)) => {
+                open.push(counts.len());
+                counts.push_back(0);
+            }
+            Event::End(TagEnd::List(_)) => {
+                open.pop();
+            }
+            Event::Start(Tag::Item) => {
+                if let Some(&list) = open.last() {
+                    counts[list] += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    counts
+}
+
+/// Fence for a code block: three backticks, or one more than the longest
+/// fence-like run in the content so the block can't be closed early. Info
+/// strings containing backticks need a tilde fence.
+fn code_fence(content: &str, info: &str) -> String {
+    let marker = if info.contains('`') { '~' } else { '`' };
+    let longest = content
+        .lines()
+        .filter_map(|line| {
+            let trimmed = line.trim_start_matches(' ');
+            if line.len() - trimmed.len() > 3 {
+                return None;
+            }
+            let run = trimmed.chars().take_while(|&c| c == marker).count();
+            (run >= 3).then_some(run)
+        })
+        .max()
+        .unwrap_or(0);
+    marker.to_string().repeat(longest.max(2) + 1)
+}
+
+/// Whether rendered link text matches a reference label the way CommonMark
+/// matches labels: ignoring case and runs of whitespace (including the
+/// placeholders for line breaks and kept spaces)
+fn labels_match(text: &str, label: &str) -> bool {
+    let normalize = |s: &str| {
+        s.replace(['\u{FFFE}', '\u{FFFF}', KEPT_SPACE], " ")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    normalize(text) == normalize(label)
+}
+
+/// First (or, with `last`, last) character an inline element renders as;
+/// `None` for empty text. Emphasis delimiters render as `marker`.
+fn edge_char(elem: &InlineElement, marker: char, last: bool) -> Option<char> {
+    let edge = |s: &str| {
+        if last {
+            s.chars().last()
         } else {
-            self.inline_buffer
-                .push(InlineElement::Text("[ ] ".to_string()));
+            s.chars().next()
+        }
+    };
+    match elem {
+        InlineElement::Text(s) => edge(s),
+        InlineElement::Code(_) => Some('`'),
+        InlineElement::EmphasisStart
+        | InlineElement::EmphasisEnd
+        | InlineElement::StrongStart
+        | InlineElement::StrongEnd => Some(marker),
+        InlineElement::StrikethroughStart | InlineElement::StrikethroughEnd => Some('~'),
+        InlineElement::LinkStart => Some('['),
+        InlineElement::ImageStart => Some(if last { '[' } else { '!' }),
+        InlineElement::LinkEnd { .. } | InlineElement::ImageEnd { .. } => {
+            Some(if last { ')' } else { ']' })
+        }
+        InlineElement::ReferenceLinkEnd { suffix, .. } => edge(suffix),
+        InlineElement::FootnoteReference(_) => Some(if last { ']' } else { '[' }),
+        InlineElement::TaskMarker(_) => Some(if last { ' ' } else { '[' }),
+        InlineElement::Math { .. } => Some('$'),
+        InlineElement::HardBreak | InlineElement::SoftBreak => Some('\n'),
+    }
+}
+
+/// Render an inline code span, choosing a backtick fence that doesn't occur
+/// in the content and padding with spaces where the parser would otherwise
+/// strip or misread the content's edges
+fn code_span(content: &str) -> String {
+    let mut runs = Vec::new();
+    let mut run = 0;
+    for c in content.chars().chain(std::iter::once(' ')) {
+        if c == '`' {
+            run += 1;
+        } else if run > 0 {
+            runs.push(run);
+            run = 0;
         }
     }
+    let fence_len = (1..).find(|n| !runs.contains(n)).unwrap_or(1);
+    let fence = "`".repeat(fence_len);
+
+    let needs_padding = content.starts_with('`')
+        || content.ends_with('`')
+        || (content.starts_with(' ')
+            && content.ends_with(' ')
+            && !content.chars().all(|c| c == ' '));
+    let padding = if needs_padding { " " } else { "" };
+
+    format!("{fence}{padding}{content}{padding}{fence}")
+}
+
+/// The tag line of a GFM alert blockquote
+fn blockquote_tag(kind: BlockQuoteKind) -> &'static str {
+    match kind {
+        BlockQuoteKind::Note => "[!NOTE]",
+        BlockQuoteKind::Tip => "[!TIP]",
+        BlockQuoteKind::Important => "[!IMPORTANT]",
+        BlockQuoteKind::Warning => "[!WARNING]",
+        BlockQuoteKind::Caution => "[!CAUTION]",
+    }
+}
+
+/// Curly quotes and en/em dashes for straight ones. `prev` is the character
+/// before `text`; a quote after whitespace or an opening bracket opens,
+/// anything else (including an apostrophe in a contraction) closes.
+fn smart_punctuation(text: &str, mut prev: Option<char>) -> String {
+    let opens = |prev: Option<char>| {
+        prev.is_none_or(|c| c.is_whitespace() || "([{<\u{2013}\u{2014}\u{201C}\u{2018}".contains(c))
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let converted = match c {
+            '-' if chars.next_if_eq(&'-').is_some() => {
+                if chars.next_if_eq(&'-').is_some() {
+                    '\u{2014}'
+                } else {
+                    '\u{2013}'
+                }
+            }
+            '"' if opens(prev) => '\u{201C}',
+            '"' => '\u{201D}',
+            '\'' if opens(prev) => '\u{2018}',
+            '\'' => '\u{2019}',
+            c => c,
+        };
+        result.push(converted);
+        prev = Some(converted);
+    }
+    result
+}
+
+/// Straight quotes and `--`/`---` for typographic quotes and dashes
+fn straight_punctuation(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{201C}' | '\u{201D}' | '\u{201E}' => result.push('"'),
+            '\u{2018}' | '\u{2019}' | '\u{201A}' => result.push('\''),
+            '\u{2013}' => result.push_str("--"),
+            '\u{2014}' => result.push_str("---"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Render a link destination with its optional title in canonical form: `url "title"`
+///
+/// Titles are always emitted double-quoted regardless of the source syntax
+/// (`"..."`, `'...'`, `(...)`, or on the following line of a definition).
+fn link_destination(url: &str, title: &str) -> String {
+    // An empty destination must be spelled `<>`: a bare `[1]:` isn't a
+    // definition, and in `( "title")` the title would be read as the URL.
+    // Whitespace, unbalanced parentheses or a leading `<` also need the
+    // angle brackets, or the destination would end early or not parse.
+    let url = if url.is_empty()
+        || !balanced_parens(url)
+        || url.starts_with('<')
+        || url.contains(|c: char| c.is_whitespace() || c.is_control())
+    {
+        format!("<{}>", url.replace('<', "\\<").replace('>', "\\>"))
+    } else {
+        url.to_string()
+    };
+    if title.is_empty() {
+        url
+    } else {
+        format!("{} \"{}\"", url, title.replace('"', "\\\""))
+    }
+}
+
+/// Whether every `)` in `text` closes an earlier `(` and none is left open
+fn balanced_parens(text: &str) -> bool {
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// Pad a table cell to the given width, honoring column alignment
+fn pad_cell(cell: &str, width: usize, alignment: Alignment) -> String {
+    let padding = width.saturating_sub(display_width(cell));
+    match alignment {
+        Alignment::Right => format!("{}{}", " ".repeat(padding), cell),
+        Alignment::Center => {
+            let left = padding / 2;
+            format!("{}{}{}", " ".repeat(left), cell, " ".repeat(padding - left))
+        }
+        Alignment::Left | Alignment::None => format!("{}{}", cell, " ".repeat(padding)),
+    }
+}
+
+/// Whether a table cell holds a plain number such as `42`, `-3.5`, `1,024` or `12%`
+fn is_numeric_cell(cell: &str) -> bool {
+    let number = cell.strip_suffix('%').unwrap_or(cell).replace(',', "");
+    number.chars().any(|c| c.is_ascii_digit())
+        && number
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+'))
+        && number.parse::<f64>().is_ok()
+}
+
+/// Build the separator cell (`---`, `:--`, `--:`, `:-:`) for a column
+fn separator_cell(width: usize, alignment: Alignment) -> String {
+    match alignment {
+        Alignment::None => "-".repeat(width),
+        Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+        Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+        Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+    }
 }
@@ -1,18 +1,41 @@
+pub mod analyze;
 #[cfg(feature = "cli")]
 pub mod cli;
+pub mod diff;
+pub mod embedded;
 pub mod formatter;
 pub mod parser;
+pub mod sink;
+pub mod slug;
 
 // Only include NAPI bindings when the napi feature is enabled
 #[cfg(feature = "napi")]
 pub mod napi;
 
-pub use formatter::{Formatter, OrderedListMode, WrapMode};
-pub use parser::{extract_frontmatter, parse_markdown};
+pub use analyze::{analyze, validate, AnalyzeOptions, Diagnostic, DiagnosticKind, Severity};
+pub use diff::{changed_ranges, diff_stat, unified_diff, ChangeRange};
+pub use embedded::{format_embedded, EmbeddedLanguage};
+pub use formatter::{
+    concat_documents, default_code_lang_aliases, BulletStyle, CodeBlockStyle, EmphasisMarker,
+    Formatter, FormatterBuilder, FrontmatterSpacing, HeadingClose, HeadingStyle, HorizontalRule,
+    IndentStyle, LineEnding, LinkStyle, NestedListSpacing, OrderedListDelimiter, OrderedListMode,
+    Punctuation, ReferencePlacement, TableStyle, TaskMarkerCase, WrapMode,
+};
+pub use parser::{
+    extract_frontmatter, fix_atx_heading_spacing, has_ignore_file_directive,
+    has_lone_carriage_returns, normalize_line_endings, normalize_yaml_frontmatter, parse_markdown,
+    uses_crlf, DocumentStats, IGNORE_DIRECTIVE, IGNORE_FILE_DIRECTIVE,
+};
+pub use sink::{OutputSink, WriterSink};
+pub use slug::{slugify, Slugger};
 
 #[cfg(test)]
 mod tests {
-    use crate::{extract_frontmatter, parse_markdown, Formatter, OrderedListMode, WrapMode};
+    use crate::{
+        changed_ranges, extract_frontmatter, has_ignore_file_directive, parse_markdown,
+        ChangeRange, Formatter, FrontmatterSpacing, HorizontalRule, NestedListSpacing,
+        OrderedListMode, OutputSink, TableStyle, WrapMode,
+    };
 
     fn format_markdown(input: &str) -> String {
         let events = parse_markdown(input);
@@ -34,22 +57,204 @@ mod tests {
 
     /// Format markdown with frontmatter support
     fn format_markdown_full(input: &str) -> String {
-        let (frontmatter, content) = extract_frontmatter(input);
-        let events = parse_markdown(content);
-        let mut formatter = Formatter::with_wrap_mode(80, WrapMode::Always);
-        let formatted = formatter.format(events);
-
-        if let Some(fm) = frontmatter {
-            fm + &formatted
-        } else {
-            formatted
-        }
+        Formatter::with_wrap_mode(80, WrapMode::Always).format_document(input)
     }
 
     // ==========================================================
     // Unit Tests
     // ==========================================================
 
+    #[test]
+    fn test_no_trailing_newline_for_embedding() {
+        let input = "Some   *inline*   text.\n\n\n";
+        let mut formatter = Formatter::new(80).trailing_newline(false);
+        assert_eq!(
+            formatter.format(parse_markdown(input)),
+            "Some *inline* text."
+        );
+        assert_eq!(format_markdown(input), "Some *inline* text.\n");
+        assert_eq!(formatter.format(parse_markdown("")), "");
+
+        // Whole documents follow the same policy, also when they are only frontmatter
+        let input = "---\ntitle: x\n---\n\n# Heading\n\n";
+        assert_eq!(
+            formatter.format_document(input),
+            "---\ntitle: x\n---\n\n# Heading"
+        );
+        assert_eq!(
+            formatter.format_document("---\ntitle: x\n---\n\n"),
+            "---\ntitle: x\n---"
+        );
+        // ...which is still read as frontmatter on the next run
+        assert_eq!(
+            formatter.format_document("---\ntitle: x\n---"),
+            "---\ntitle: x\n---"
+        );
+        assert_eq!(
+            extract_frontmatter("+++\r\ntitle = 1\r\n+++"),
+            (Some("+++\r\ntitle = 1\r\n+++\r\n\r\n".to_string()), "")
+        );
+        assert_eq!(formatter.format_document(""), "");
+        let mut formatter = Formatter::new(80);
+        assert_eq!(
+            formatter.format_document("---\ntitle: x\n---\n\n"),
+            "---\ntitle: x\n---\n"
+        );
+        assert_eq!(formatter.format_document(""), "");
+    }
+
+    #[test]
+    fn test_custom_output_sink() {
+        /// Sink that only counts what it is given
+        #[derive(Default)]
+        struct ByteCounter {
+            bytes: usize,
+            writes: usize,
+        }
+
+        impl OutputSink for ByteCounter {
+            fn push_str(&mut self, s: &str) {
+                self.bytes += s.len();
+                self.writes += 1;
+            }
+        }
+
+        let input = "# Title\n\nSome *text*   \n\n- one\n- two\n\n\n";
+        let mut counter = ByteCounter::default();
+        let mut formatter = Formatter::new(80);
+        formatter.format_to(parse_markdown(input), &mut counter);
+
+        let expected = formatter.format(parse_markdown(input));
+        assert_eq!(counter.bytes, expected.len());
+        assert!(
+            counter.writes > 1,
+            "output should be handed over incrementally"
+        );
+    }
+
+    #[test]
+    fn test_format_reader_matches_buffered_output() {
+        let body = [COMPLEX_BAD, TABLES, LOOSE_LIST, TIGHT_LIST, HEADING_IN_LIST].join("\n\n");
+        let documents = [
+            format!("---\ntitle: Streamed\n---\n\n{}", body),
+            body.replace('\n', "\r\n"),
+            "---\ntitle: Only frontmatter\n---\n".to_string(),
+        ];
+
+        let mut formatter = Formatter::with_wrap_mode(40, WrapMode::Always)
+            .align_ordered_markers(true)
+            .line_ending(crate::LineEnding::Preserve);
+        for input in &documents {
+            let mut streamed = Vec::new();
+            formatter
+                .format_reader(input.as_bytes(), &mut streamed)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                formatter.format_document(input)
+            );
+        }
+
+        // Lazily parsed events give the same result as a collected list
+        let mut streamed = String::new();
+        formatter.format_to(pulldown_cmark::Parser::new(&body), &mut streamed);
+        assert_eq!(
+            streamed,
+            formatter.format(pulldown_cmark::Parser::new(&body).collect())
+        );
+    }
+
+    /// Every variant of the formatter's option enums can be selected from
+    /// the command line, under the same name the NAPI options accept
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_cli_reaches_every_option_variant() {
+        use crate::cli::Args;
+        use crate::{EmbeddedLanguage, FrontmatterSpacing, LineEnding};
+        use clap::{Parser, ValueEnum};
+        use std::fmt::Debug;
+        use std::str::FromStr;
+
+        fn check<T>(flag: &str, field: impl Fn(&Args) -> T)
+        where
+            T: ValueEnum + FromStr + PartialEq + Debug,
+            <T as FromStr>::Err: Debug,
+        {
+            for variant in T::value_variants() {
+                let name = variant.to_possible_value().unwrap().get_name().to_string();
+                let args = Args::try_parse_from(["mdfmt", flag, &name]).unwrap();
+                assert_eq!(&field(&args), variant, "{} {}", flag, name);
+                assert_eq!(&name.parse::<T>().unwrap(), variant, "{}", name);
+            }
+        }
+
+        check::<WrapMode>("--wrap", |a| a.wrap);
+        check::<OrderedListMode>("--ordered-list", |a| a.ordered_list);
+        check::<TableStyle>("--table-style", |a| a.table_style);
+        check::<NestedListSpacing>("--nested-list-spacing", |a| a.nested_list_spacing);
+        check::<FrontmatterSpacing>("--frontmatter-spacing", |a| a.frontmatter_spacing);
+        check::<LineEnding>("--line-ending", |a| a.line_ending);
+        check::<crate::LinkStyle>("--link-style", |a| a.link_style);
+        check::<crate::ReferencePlacement>("--reference-placement", |a| a.reference_placement);
+        check::<crate::BulletStyle>("--bullet", |a| a.bullet);
+        check::<crate::OrderedListDelimiter>("--ordered-delimiter", |a| a.ordered_delimiter);
+        check::<crate::HeadingStyle>("--heading-style", |a| a.heading_style);
+        check::<crate::HeadingClose>("--heading-close", |a| a.heading_close);
+        check::<crate::EmphasisMarker>("--emphasis-marker", |a| a.emphasis_marker);
+        check::<crate::EmphasisMarker>("--strong-marker", |a| a.strong_marker);
+        check::<crate::TaskMarkerCase>("--task-marker-case", |a| a.task_marker_case);
+        check::<crate::CodeBlockStyle>("--code-block-style", |a| a.code_block_style);
+        check::<EmbeddedLanguage>("--embedded", |a| a.embedded.unwrap());
+    }
+
+    #[test]
+    fn test_formatter_builder() {
+        use crate::{BulletStyle, FormatterBuilder};
+
+        let input = "* one\n* two\n\n1. first item of a longer list\n2. second\n";
+
+        let mut built = Formatter::builder()
+            .width(20)
+            .wrap_mode(WrapMode::Always)
+            .ordered_list_mode(OrderedListMode::One)
+            .bullet_style(BulletStyle::Plus)
+            .build();
+        let mut chained = Formatter::with_options(20, WrapMode::Always, OrderedListMode::One)
+            .bullet_style(BulletStyle::Plus);
+        let output = built.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "+ one\n+ two\n\n1. first item of a\n   longer list\n1. second\n"
+        );
+        assert_eq!(chained.format(parse_markdown(input)), output);
+
+        // An untouched builder matches the default constructor
+        let mut default = FormatterBuilder::default().build();
+        assert_eq!(
+            default.format(parse_markdown(input)),
+            Formatter::new(80).format(parse_markdown(input))
+        );
+    }
+
+    #[test]
+    fn test_blockquote_markers_on_every_line() {
+        let input = "> Intro\n>\n> ```rust\n> fn a() {}\n>\n> fn b() {}\n> ```\n>\n> - item\n>\n>   second paragraph\n> - code:\n>   ```\n>   x\n>   ```\n>\n> > nested\n> >\n> > ```\n> > deep\n> > ```\n>\n> <!-- note -->\n";
+        let output = format_markdown(input);
+        assert!(
+            output.lines().all(|line| line.starts_with('>')),
+            "{}",
+            output
+        );
+        assert_eq!(
+            output,
+            "> Intro\n>\n> ```rust\n> fn a() {}\n>\n> fn b() {}\n> ```\n>\n> - item\n>\n>   second paragraph\n>\n> - code:\n>\n>   ```\n>   x\n>   ```\n>\n> > nested\n> >\n> > ```\n> > deep\n> > ```\n>\n> <!-- note -->\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        // After the quote ends, the blank line has no marker
+        assert_eq!(format_markdown("> a\n\nb"), "> a\n\nb\n");
+    }
+
     #[test]
     fn test_heading_normalization() {
         let input = "# Heading 1\n## Heading 2";
@@ -58,6 +263,318 @@ mod tests {
         assert_eq!(output, expected);
     }
 
+    #[test]
+    fn test_setext_headings() {
+        let format = |input: &str| {
+            Formatter::new(80)
+                .heading_style(crate::HeadingStyle::SetextWhenPossible)
+                .format_document(input)
+        };
+
+        let input = "Title\n=====\n\n## Sub *heading*\n\n### Deeper\n";
+        let expected = "Title\n=====\n\nSub *heading*\n-------------\n\n### Deeper\n";
+        assert_eq!(format(input), expected);
+        assert_eq!(format(expected), expected);
+
+        // The underline matches the title's display width, not its bytes
+        assert_eq!(format("# Überblick\n"), "Überblick\n=========\n");
+        assert_eq!(format("# 概要\n"), "概要\n====\n");
+
+        // In containers, where the text line could join the text before it,
+        // headings stay ATX; so do empty headings
+        let input = "> # Quote\n\n- # Item\n\n- Plain item\n  ## Second\n\n#\n";
+        assert_eq!(format(input), input);
+
+        // Text that would start another block on its own line stays ATX
+        let input = "## - item\n\n# > quote\n\n# 1. one\n\n## ---\n\n# -x\n";
+        let expected = "## - item\n\n# > quote\n\n# 1. one\n\n## ---\n\n-x\n==\n";
+        assert_eq!(format(input), expected);
+        assert_eq!(format(expected), expected);
+
+        // The default turns setext headings into ATX
+        assert_eq!(
+            format_markdown("Title\n=====\n\nSub\n---\n"),
+            "# Title\n\n## Sub\n"
+        );
+    }
+
+    #[test]
+    fn test_closed_atx_headings() {
+        let format = |input: &str, style| {
+            Formatter::new(80)
+                .heading_style(style)
+                .heading_close(crate::HeadingClose::Closed)
+                .format_document(input)
+        };
+        let atx = crate::HeadingStyle::Atx;
+
+        assert_eq!(format("# Title", atx), "# Title #\n");
+        let expected = "# Title #\n\n### C# ###\n\n#\n";
+        assert_eq!(format("# Title\n\n### C# ##\n\n#\n", atx), expected);
+        assert_eq!(format(expected, atx), expected);
+
+        // Setext headings have no closing sequence; deeper levels still do
+        assert_eq!(
+            format(
+                "# Title\n\n### Deeper\n",
+                crate::HeadingStyle::SetextWhenPossible
+            ),
+            "Title\n=====\n\n### Deeper ###\n"
+        );
+
+        // The default drops closing hashes
+        assert_eq!(format_markdown("## Sub ##\n"), "## Sub\n");
+    }
+
+    #[test]
+    fn test_wrapping_keeps_links_whole() {
+        let mut formatter = Formatter::with_wrap_mode(30, WrapMode::Always)
+            .link_style(crate::LinkStyle::PreserveReference);
+        let input = "Read the [guide to formatting](https://example.com/guide \"The guide\") and [the FAQ][faq] first.\n\n[faq]: https://example.com/faq\n";
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "Read the\n[guide to formatting](https://example.com/guide \"The guide\")\nand [the FAQ][faq] first.\n\n[faq]: https://example.com/faq\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+
+        // Soft breaks inside the link text are joined too
+        let output = formatter.format_document("Some [link\ntext](/a) here.\n");
+        assert_eq!(output, "Some [link text](/a) here.\n");
+    }
+
+    #[test]
+    fn test_width_zero_disables_wrapping() {
+        let input =
+            "A paragraph that is\nwrapped over\nthree lines.\n\n> Quoted text\n> on two lines.\n";
+        let expected =
+            "A paragraph that is wrapped over three lines.\n\n> Quoted text on two lines.\n";
+        for wrap_mode in [WrapMode::Always, WrapMode::Never, WrapMode::Preserve] {
+            let mut formatter = Formatter::with_wrap_mode(0, wrap_mode);
+            assert_eq!(
+                formatter.format_document(input),
+                expected,
+                "{:?}",
+                wrap_mode
+            );
+        }
+    }
+
+    #[test]
+    fn test_image_alt_text() {
+        for input in [
+            "![plain alt](image.png)\n",
+            "![*emphasized* and **strong** `code`](image.png \"Title\")\n",
+            "![](image.png)\n",
+            "[![*badge*](badge.svg)](https://example.com)\n",
+        ] {
+            assert_eq!(format_markdown(input), input);
+        }
+
+        // Images are kept on one line like links
+        let output = Formatter::with_wrap_mode(20, WrapMode::Always)
+            .format_document("See ![*a long* alt text](image.png) here.\n");
+        assert_eq!(output, "See\n![*a long* alt text](image.png)\nhere.\n");
+    }
+
+    #[test]
+    fn test_emphasis_markers() {
+        use crate::EmphasisMarker;
+
+        let format = |input: &str| {
+            Formatter::new(80)
+                .emphasis_marker(EmphasisMarker::Underscore)
+                .strong_marker(EmphasisMarker::Underscore)
+                .format_document(input)
+        };
+
+        // At word boundaries, including next to punctuation
+        let expected = "Some _emphasis_ and __strong__ text, (_here_).\n";
+        assert_eq!(
+            format("Some *emphasis* and **strong** text, (*here*).\n"),
+            expected
+        );
+        assert_eq!(format(expected), expected);
+        assert_eq!(format("___both___\n"), "_**both**_\n");
+
+        // Inside words only `*` is emphasis
+        let expected = "foo*bar*baz and un**frigging**believable, *a*b\n";
+        assert_eq!(
+            format("foo_bar_baz and un__frigging__believable, _a_b\n"),
+            "foo_bar_baz and un__frigging__believable, _a_b\n"
+        );
+        assert_eq!(
+            format("foo*bar*baz and un**frigging**believable, *a*b\n"),
+            expected
+        );
+        assert_eq!(format(expected), expected);
+
+        // Next to code, links and other emphasis
+        let input = "*`code`* **[link](https://example.com)** ***mixed** text*\n";
+        assert_eq!(
+            format(input),
+            "_`code`_ __[link](https://example.com)__ _**mixed** text_\n"
+        );
+
+        // Only the chosen kind changes
+        assert_eq!(
+            Formatter::new(80)
+                .strong_marker(EmphasisMarker::Underscore)
+                .format_document("*a* **b**\n"),
+            "*a* __b__\n"
+        );
+    }
+
+    #[test]
+    fn test_ordered_list_preserve_start() {
+        let format = |input: &str, mode| {
+            let events = parse_markdown(input);
+            Formatter::with_options(80, WrapMode::default(), mode).format(events)
+        };
+
+        assert_eq!(
+            format("5. a\n6. b", OrderedListMode::Preserve),
+            "5. a\n6. b\n"
+        );
+        assert_eq!(
+            format("5. a\n5. b\n5. c", OrderedListMode::Preserve),
+            "5. a\n6. b\n7. c\n"
+        );
+        assert_eq!(
+            format("5. a\n6. b", OrderedListMode::Ascending),
+            "1. a\n2. b\n"
+        );
+        assert_eq!(format("5. a\n6. b", OrderedListMode::One), "1. a\n1. b\n");
+
+        // Nested lists keep their own start numbers
+        assert_eq!(
+            format("3. a\n   1. x\n   1. y\n4. b", OrderedListMode::Preserve),
+            "3. a\n   1. x\n   2. y\n4. b\n"
+        );
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        let input = "# Hello, World!\n\n## What's new in v2.0?\n\n## Hello, World!\n\n### Hello World 1\n\n## Hello, World!\n\n## `code` and _emphasis_\n\n## Überblick & Ärger\n\n## 日本語 見出し\n\n## Émoji 🎉 party\n";
+        let analysis = crate::analyze(input, &Default::default());
+        let anchors: Vec<&str> = analysis
+            .headings
+            .iter()
+            .map(|h| h.anchor.as_str())
+            .collect();
+        assert_eq!(
+            anchors,
+            [
+                "hello-world",
+                "whats-new-in-v20",
+                "hello-world-1",
+                "hello-world-1-1",
+                "hello-world-2",
+                "code-and-emphasis",
+                "überblick--ärger",
+                "日本語-見出し",
+                "émoji--party",
+            ]
+        );
+
+        assert_eq!(
+            crate::slugify("Snake_case -- dashes"),
+            "snake_case----dashes"
+        );
+    }
+
+    #[test]
+    fn test_fix_heading_spacing() {
+        let input = "#Heading\n\n###Third ##\n\n####### seven\n\n```\n#comment\n```\n\n#\n";
+
+        // Off by default: `#Heading` is just text
+        let mut formatter = Formatter::new(80);
+        let output = formatter.format_document(input);
+        assert!(output.starts_with("#Heading\n"), "{:?}", output);
+
+        let mut formatter = Formatter::new(80).fix_heading_spacing(true);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "# Heading\n\n### Third\n\n####### seven\n\n```\n#comment\n```\n\n#\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+    }
+
+    #[test]
+    fn test_format_rust_doc_comments() {
+        use crate::{format_embedded, EmbeddedLanguage};
+        let input = "//! Crate   docs\n\n/// Adds   *two*  numbers:\n///\n///   * a\n///   * b\n///\n/// ```\n/// let x  =  add(1,2);\n/// ```\n////  not   docs\nfn add(a: i32,   b: i32) -> i32 {\n    a + b\n}\n\nimpl Foo {\n    ///Does   things\n    fn bar() {}\n}\n";
+        let expected = "//! Crate docs\n\n/// Adds *two* numbers:\n///\n/// - a\n/// - b\n///\n/// ```\n/// let x  =  add(1,2);\n/// ```\n////  not   docs\nfn add(a: i32,   b: i32) -> i32 {\n    a + b\n}\n\nimpl Foo {\n    /// Does things\n    fn bar() {}\n}\n";
+
+        let mut formatter = Formatter::new(80);
+        let output = format_embedded(&mut formatter, input, EmbeddedLanguage::Rust);
+        assert_eq!(output, expected);
+        assert_eq!(
+            format_embedded(&mut formatter, &output, EmbeddedLanguage::Rust),
+            output
+        );
+    }
+
+    #[test]
+    fn test_all_heading_levels() {
+        for level in 1..=6 {
+            let hashes = "#".repeat(level);
+            let expected = format!("{} Level {}\n", hashes, level);
+
+            // ATX with extra spacing and closing hashes
+            let output = format_markdown(&format!("{}   Level {} {}", hashes, level, hashes));
+            assert_eq!(output, expected);
+            assert_eq!(format_markdown(&output), output);
+
+            let analysis = crate::analyze(&output, &Default::default());
+            assert_eq!(analysis.headings[0].level, level as u32);
+        }
+
+        // Setext headings map to levels 1 and 2
+        assert_eq!(format_markdown("Title\n====="), "# Title\n");
+        assert_eq!(format_markdown("Title\n-----"), "## Title\n");
+    }
+
+    #[test]
+    fn test_long_heading_stays_on_one_line() {
+        let input = "## This heading is far too long to fit within the configured line width of the formatter";
+        for output in [format_markdown_always(input), format_markdown(input)] {
+            assert_eq!(
+                output.lines().count(),
+                1,
+                "Heading should not wrap: {:?}",
+                output
+            );
+            assert!(output.starts_with("## This heading"));
+        }
+
+        // Setext headings with a soft break are joined onto one line too
+        let output = format_markdown_always("Split\nheading\n=====");
+        assert_eq!(output, "# Split heading\n");
+
+        // Whitespace in the text collapses, but code spans keep theirs
+        let output = format_markdown("# Use   `a  b`  here\n\nTerm  `x   y`\n: def\n");
+        assert_eq!(output, "# Use `a  b` here\n\nTerm `x   y`\n: def\n");
+        assert_eq!(format_markdown(&output), output);
+    }
+
+    #[test]
+    fn test_pseudo_heading_conversion() {
+        let input = "**Section Title**\n\nThis has **bold** and more.\n\n*Note*\n\n- **Item**";
+
+        // Off by default
+        assert_eq!(format_markdown(input), format!("{}\n", input));
+
+        let mut formatter = Formatter::new(80).pseudo_heading_level(Some(3));
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "### Section Title\n\nThis has **bold** and more.\n\n### Note\n\n- **Item**\n"
+        );
+        assert_eq!(output, formatter.format(parse_markdown(&output)));
+    }
+
     #[test]
     fn test_list_normalization() {
         let input = "- Item 1\n- Item 2\n- Item 3";
@@ -76,6 +593,58 @@ mod tests {
         assert!(output.contains("3. Third"));
     }
 
+    #[test]
+    fn test_ordered_start() {
+        let input = "1. First\n1. Second\n\nText\n\n5. Another\n5. List\n";
+        let format = |start: u64, mode: OrderedListMode| {
+            Formatter::with_options(80, WrapMode::default(), mode)
+                .ordered_start(start)
+                .format(parse_markdown(input))
+        };
+        assert_eq!(
+            format(0, OrderedListMode::Ascending),
+            "0. First\n1. Second\n\nText\n\n0. Another\n1. List\n"
+        );
+        assert_eq!(
+            format(10, OrderedListMode::Ascending),
+            "10. First\n11. Second\n\nText\n\n10. Another\n11. List\n"
+        );
+
+        // Other modes ignore it
+        assert_eq!(
+            format(10, OrderedListMode::One),
+            format_markdown(input).replace("2.", "1.")
+        );
+        assert!(format(10, OrderedListMode::Preserve).contains("5. Another\n6. List"));
+
+        // Numbers can't pass nine digits, so such a list isn't renumbered
+        assert_eq!(
+            format(999_999_999, OrderedListMode::Ascending),
+            "1. First\n2. Second\n\nText\n\n5. Another\n6. List\n"
+        );
+
+        // Nested lists start at the base too, except right below their
+        // item's text, where only a list starting at 1 is a list
+        let nested = "1. Parent\n   1. Child\n   1. Child\n2. Next\n   - x\n     1. Deep\n";
+        for (spacing, expected) in [
+            (
+                NestedListSpacing::Tight,
+                "10. Parent\n    1. Child\n    2. Child\n11. Next\n    - x\n      1. Deep\n",
+            ),
+            (
+                NestedListSpacing::Loose,
+                "10. Parent\n\n    10. Child\n    11. Child\n\n11. Next\n\n    - x\n\n      10. Deep\n",
+            ),
+        ] {
+            let mut formatter = Formatter::new(80)
+                .ordered_start(10)
+                .nested_list_spacing(spacing);
+            let output = formatter.format(parse_markdown(nested));
+            assert_eq!(output, expected, "{:?}", spacing);
+            assert_eq!(formatter.format(parse_markdown(&output)), output);
+        }
+    }
+
     #[test]
     fn test_multiple_ordered_list_ascending_mode() {
         // Default mode: items are numbered 1, 2, 3, ...
@@ -95,6 +664,72 @@ mod tests {
         assert!(!output.contains("\n\n  - Subitem one"));
     }
 
+    fn format_markdown_nested_spacing(input: &str, spacing: NestedListSpacing) -> String {
+        let events = parse_markdown(input);
+        let mut formatter = Formatter::new(80).nested_list_spacing(spacing);
+        formatter.format(events)
+    }
+
+    #[test]
+    fn test_nested_list_spacing_tight_and_loose() {
+        for (input, parent) in [
+            (
+                "- Parent\n  - Child one\n  - Child two\n- Next",
+                "- Parent\n  - Child one",
+            ),
+            (
+                "1. Parent\n   - Child one\n   - Child two\n2. Next",
+                "1. Parent\n   - Child one",
+            ),
+        ] {
+            let tight = format_markdown_nested_spacing(input, NestedListSpacing::Tight);
+            assert!(
+                tight.contains(parent),
+                "Tight spacing should have no blank line: {:?}",
+                tight
+            );
+            assert_eq!(
+                tight,
+                format_markdown_nested_spacing(&tight, NestedListSpacing::Tight)
+            );
+
+            let loose = format_markdown_nested_spacing(input, NestedListSpacing::Loose);
+            assert!(
+                loose.contains(&parent.replacen('\n', "\n\n", 1)),
+                "Loose spacing should have a blank line: {:?}",
+                loose
+            );
+            assert_eq!(
+                loose,
+                format_markdown_nested_spacing(&loose, NestedListSpacing::Loose)
+            );
+        }
+
+        // In a loose list the blank line stays, even with tight spacing: it
+        // may be all that makes the list loose
+        for input in ["* a\n\n  * b\n", "- a\n\n  - b\n- c\n"] {
+            let tight = format_markdown_nested_spacing(input, NestedListSpacing::Tight);
+            assert_eq!(tight, input.replace('*', "-").replace("b\n-", "b\n\n-"));
+            assert_eq!(
+                tight,
+                format_markdown_nested_spacing(&tight, NestedListSpacing::Tight)
+            );
+        }
+
+        // The blank line before a sublist makes its parent list loose, so
+        // loose spacing separates all of the parent's items
+        let input = "- One\n- Two\n  - Child one\n  - Child two\n- Three\n\n1. a\n2. b\n";
+        let loose = format_markdown_nested_spacing(input, NestedListSpacing::Loose);
+        assert_eq!(
+            loose,
+            "- One\n\n- Two\n\n  - Child one\n  - Child two\n\n- Three\n\n1. a\n2. b\n"
+        );
+        assert_eq!(
+            loose,
+            format_markdown_nested_spacing(&loose, NestedListSpacing::Loose)
+        );
+    }
+
     #[test]
     fn test_ordered_list_one_mode() {
         // One mode: all items use "1."
@@ -113,6 +748,19 @@ mod tests {
         assert_eq!(output, expected);
     }
 
+    #[test]
+    fn test_code_only_heading_and_list_item() {
+        let input = "# `config`\n\n## `` `quoted` ``\n\n- `item`\n- ``a `tick` b``\n- ` `\n";
+
+        for output in [format_markdown(input), format_markdown_always(input)] {
+            assert_eq!(output, input);
+        }
+
+        // Fences are chosen to fit the content, whatever the source used
+        assert_eq!(format_markdown("- ```a `` b```"), "- `a `` b`\n");
+        assert_eq!(format_markdown("# ``` `` ```"), "# ` `` `\n");
+    }
+
     #[test]
     fn test_code_block() {
         let input = "```rust\nfn main() {\n    println!(\"Hello\");\n}\n```";
@@ -193,59 +841,1524 @@ mod tests {
     }
 
     #[test]
-    fn test_strikethrough_preservation() {
-        let input = "This has ~~strikethrough~~ text.";
-        let output = format_markdown(input);
-        // Strikethrough should be preserved
-        assert!(output.contains("~~strikethrough~~"));
-    }
+    fn test_format_document_matches_manual_pipeline() {
+        let input = "---\ntitle: Test\n---\n\n# Heading\n\n* one\n* two\n\nSome  text.";
 
-    #[test]
-    fn test_gfm_autolinks() {
-        let input = "Visit <https://example.com> for more info.";
-        let output = format_markdown(input);
-        // Should contain link reference
-        assert!(output.contains("example.com"));
-    }
+        // Extract, parse and format the content, then put the frontmatter back
+        let (frontmatter, content) = extract_frontmatter(input);
+        let mut formatter = Formatter::new(80);
+        let manual = frontmatter.unwrap() + &formatter.format(parse_markdown(content));
 
-    #[test]
-    fn test_hard_break_preservation() {
-        let input = "Line one  \nLine two";
-        let output = format_markdown(input);
-        // Hard break should be preserved (two spaces before newline)
-        assert!(output.contains("  \n"), "Hard break should be preserved");
+        assert_eq!(formatter.format_document(input), manual);
     }
 
     #[test]
-    fn test_no_spurious_hard_breaks() {
-        // A long line that gets wrapped should NOT have hard breaks (when using always mode)
-        let input = "This is a very long line that needs to be wrapped because it exceeds eighty characters.";
-        let output = format_markdown_always(input);
-        // Should not contain hard breaks (two spaces before newline)
-        assert!(
-            !output.contains("  \n"),
-            "Wrapped lines should not have hard breaks"
+    fn test_toml_frontmatter() {
+        let input = "+++\ntitle = \"Test\"\ndraft = false\n+++\n# Heading\n\nContent.";
+        let (frontmatter, content) = extract_frontmatter(input);
+        assert_eq!(
+            frontmatter.as_deref(),
+            Some("+++\ntitle = \"Test\"\ndraft = false\n+++\n\n")
         );
-    }
+        assert_eq!(content, "# Heading\n\nContent.");
 
-    // ==========================================================
-    // Fixture-Based Tests
-    // ==========================================================
+        let output = format_markdown_full(input);
+        assert_eq!(
+            output,
+            "+++\ntitle = \"Test\"\ndraft = false\n+++\n\n# Heading\n\nContent.\n"
+        );
+        assert_eq!(format_markdown_full(&output), output);
 
-    const SIMPLE_GOOD: &str = include_str!("../tests/fixtures/simple-good.md");
-    const SIMPLE_BAD: &str = include_str!("../tests/fixtures/simple-bad.md");
-    const COMPLEX_GOOD: &str = include_str!("../tests/fixtures/complex-good.md");
-    const COMPLEX_BAD: &str = include_str!("../tests/fixtures/complex-bad.md");
+        // Delimiters must match
+        let mixed = "+++\ntitle = \"Test\"\n---\n# Heading\n";
+        assert_eq!(extract_frontmatter(mixed), (None, mixed));
+    }
 
     #[test]
-    fn test_simple_good_is_idempotent() {
-        let formatted = format_markdown(SIMPLE_GOOD);
-        let reformatted = format_markdown(&formatted);
+    fn test_crlf_frontmatter() {
+        let input = "---\r\ntitle: Test\r\n---\r\n# Heading\r\nLine one  \r\nLine two\r\n";
+        let (frontmatter, content) = extract_frontmatter(input);
         assert_eq!(
-            formatted, reformatted,
-            "simple-good.md should be idempotent"
+            frontmatter.as_deref(),
+            Some("---\r\ntitle: Test\r\n---\r\n\r\n")
         );
-    }
+        assert_eq!(content, "# Heading\r\nLine one  \r\nLine two\r\n");
+
+        // The frontmatter isn't mistaken for a setext heading, and the hard
+        // break survives; CRLF is written back when preserving line endings
+        let expected = "---\ntitle: Test\n---\n\n# Heading\n\nLine one  \nLine two\n";
+        assert_eq!(Formatter::new(80).format_document(input), expected);
+        let mut formatter = Formatter::new(80).line_ending(crate::LineEnding::Preserve);
+        assert_eq!(
+            formatter.format_document(input),
+            expected.replace('\n', "\r\n")
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_with_multibyte_content() {
+        let input =
+            "---\ntitle: 日本語のタイトル\nemoji: 🎉✓\n---\n\n# Überschrift\n\nTexte accentué.";
+        let (frontmatter, content) = extract_frontmatter(input);
+        assert_eq!(
+            frontmatter.as_deref(),
+            Some("---\ntitle: 日本語のタイトル\nemoji: 🎉✓\n---\n\n")
+        );
+        assert_eq!(content, "\n# Überschrift\n\nTexte accentué.");
+
+        let output = format_markdown_full(input);
+        assert_eq!(
+            output,
+            "---\ntitle: 日本語のタイトル\nemoji: 🎉✓\n---\n\n# Überschrift\n\nTexte accentué.\n"
+        );
+        assert_eq!(format_markdown_full(&output), output);
+
+        // Multibyte text right against the markers, and an unclosed block
+        let (frontmatter, content) = extract_frontmatter("---\né\n---\né");
+        assert_eq!(frontmatter.as_deref(), Some("---\né\n---\n\n"));
+        assert_eq!(content, "é");
+        assert_eq!(extract_frontmatter("---\né---\n"), (None, "---\né---\n"));
+    }
+
+    #[test]
+    fn test_frontmatter_spacing() {
+        let document = |blank_lines: usize| {
+            format!(
+                "---\ntitle: Test\n---\n{}# Heading\n",
+                "\n".repeat(blank_lines)
+            )
+        };
+
+        // (blank lines in the source, kept with max_blank_lines 1, kept with 2)
+        for (blank_lines, one, two) in [(0, 0, 0), (1, 1, 1), (2, 1, 2), (3, 1, 2)] {
+            let input = document(blank_lines);
+
+            // Default: exactly one blank line
+            let mut formatter = Formatter::new(80).max_blank_lines(2);
+            assert_eq!(formatter.format_document(&input), document(1));
+
+            for (max, expected) in [(1, one), (2, two)] {
+                let mut formatter = Formatter::new(80)
+                    .frontmatter_spacing(FrontmatterSpacing::Preserve)
+                    .max_blank_lines(max);
+                let output = formatter.format_document(&input);
+                assert_eq!(output, document(expected), "{} blank lines", blank_lines);
+                assert_eq!(formatter.format_document(&output), output);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_normalize_frontmatter() {
+        let input = "---\ntitle:   'Test'\ndate: 2024-01-01\ntags:\n    - b\n    - a\nauthor: Me\n---\n\n# Heading\n";
+
+        // Keys keep their order; nested lists keep theirs
+        let mut formatter = Formatter::new(80).normalize_frontmatter(true);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "---\ntitle: 'Test'\ndate: 2024-01-01\ntags:\n  - b\n  - a\nauthor: Me\n---\n\n# Heading\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+
+        let mut formatter = Formatter::new(80).sort_frontmatter_keys(true);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "---\nauthor: Me\ndate: 2024-01-01\ntags:\n  - b\n  - a\ntitle: 'Test'\n---\n\n# Heading\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+
+        // Scalars keep their source text; nested blocks move as a whole
+        let input = "---\nversion: 1.10\nid: 0012\nempty: ~\nnested:\n    items:\n        - name: x\n          text: |\n              line\n                indented\n---\n";
+        assert_eq!(
+            formatter.format_document(input),
+            "---\nempty: ~\nid: 0012\nnested:\n  items:\n    - name: x\n      text: |\n          line\n            indented\nversion: 1.10\n---\n"
+        );
+        assert_eq!(
+            crate::normalize_yaml_frontmatter("---\nb: 1\na: [x,\n  y]\n---\n", true).as_deref(),
+            Some("---\na: [x,\n  y]\nb: 1\n---\n")
+        );
+
+        // Comments would be lost or misplaced, so such frontmatter is kept;
+        // a `#` inside a value is not a comment
+        for input in [
+            "---\nz: 1 # last\na: 2\n---\n",
+            "---\n# heading\nz: 1\na: 2\n---\n",
+        ] {
+            assert_eq!(formatter.format_document(input), input);
+        }
+        assert_eq!(
+            formatter.format_document("---\nz: \"a #b\"\na: c#d\n---\n"),
+            "---\na: c#d\nz: \"a #b\"\n---\n"
+        );
+
+        // Invalid YAML, non-mappings and TOML are kept as written
+        for input in [
+            "---\ntitle: [unclosed\nz:  1\n---\n\n# Heading\n",
+            "---\njust   text\n---\n\n# Heading\n",
+            "+++\nz  = 1\na = 2\n+++\n\n# Heading\n",
+        ] {
+            assert_eq!(formatter.format_document(input), input);
+        }
+    }
+
+    #[test]
+    fn test_horizontal_rule_style() {
+        let input = "One\n\n----------\n\nTwo\n\n* * *\n\nThree\n\n___\n\n- item\n\n  *****";
+
+        for (style, rule) in [
+            ("---", "---"),
+            ("***", "***"),
+            ("* * *", "* * *"),
+            ("_____", "_____"),
+            ("- - - -", "- - - -"),
+        ] {
+            let rule_style: HorizontalRule = style.parse().unwrap();
+            let mut formatter = Formatter::new(80).horizontal_rule(rule_style);
+            let output = formatter.format(parse_markdown(input));
+            assert_eq!(
+                output,
+                format!(
+                    "One\n\n{rule}\n\nTwo\n\n{rule}\n\nThree\n\n{rule}\n\n- item\n\n  {rule}\n"
+                )
+            );
+            assert_eq!(formatter.format(parse_markdown(&output)), output);
+        }
+
+        for invalid in ["", "--", "-*-", "===", "*  *  *", "- - *"] {
+            assert!(invalid.parse::<HorizontalRule>().is_err(), "{:?}", invalid);
+        }
+
+        // A leading `---` would turn the text up to the next rule into
+        // frontmatter, so the first rule is made one longer
+        let input = "***\n\ntitle: x\n\n***\n";
+        let expected = "----\n\ntitle: x\n\n---\n";
+        let mut formatter = Formatter::new(80);
+        assert_eq!(formatter.format_document(input), expected);
+        assert_eq!(formatter.format_document(expected), expected);
+        let mut formatter = Formatter::new(80).horizontal_rule("***".parse().unwrap());
+        assert_eq!(
+            formatter.format_document(expected),
+            "***\n\ntitle: x\n\n***\n"
+        );
+    }
+
+    #[test]
+    fn test_ignore_file_directive() {
+        let mut formatter = Formatter::with_wrap_mode(20, WrapMode::Always);
+        for input in [
+            "<!-- mdfmt-ignore-file -->\n#    Messy   heading\n* a\n+ b\n",
+            "---\ntitle: Generated\n---\n\n\n<!-- mdfmt-ignore-file -->\n\nA   long line that would be wrapped",
+            "\u{FEFF}<!-- mdfmt-ignore-file -->\n#    After a byte order mark\n",
+        ] {
+            assert!(has_ignore_file_directive(input));
+            assert_eq!(formatter.format_document(input), input);
+        }
+
+        // Only honored at the top of the document
+        let input = "# Title\n\n<!-- mdfmt-ignore-file -->\n* a";
+        assert!(!has_ignore_file_directive(input));
+        assert_eq!(
+            formatter.format_document(input),
+            "# Title\n\n<!-- mdfmt-ignore-file -->\n\n- a\n"
+        );
+    }
+
+    #[test]
+    fn test_ignore_directive() {
+        let format = |input: &str| Formatter::new(80).format_document(input);
+
+        // The table after the directive is kept as written; the rest is formatted
+        let input = "#  Scores\n<!-- mdfmt-ignore -->\n|Name|Score|\n|-|-:|\n|Ann  |  10|\n\n|A|B|\n|-|-|\n|1|2|\n";
+        let expected = "# Scores\n\n<!-- mdfmt-ignore -->\n\n|Name|Score|\n|-|-:|\n|Ann  |  10|\n\n| A   | B   |\n| --- | --- |\n| 1   | 2   |\n";
+        assert_eq!(format(input), expected);
+        assert_eq!(format(expected), expected);
+
+        // Whole lists, blank lines included, and thematic breaks
+        let input = "<!-- mdfmt-ignore -->\n* a\n\n\n* b\n\n* c\n\n<!-- mdfmt-ignore -->\n* * *\n";
+        assert_eq!(
+            format(input),
+            "<!-- mdfmt-ignore -->\n\n* a\n\n\n* b\n\n* c\n\n<!-- mdfmt-ignore -->\n\n* * *\n"
+        );
+
+        // Only the one block after the directive, and only at the top level
+        assert_eq!(
+            format("<!-- mdfmt-ignore -->\n\nSome   text\n\nMore   text\n"),
+            "<!-- mdfmt-ignore -->\n\nSome   text\n\nMore text\n"
+        );
+        assert_eq!(
+            format("> <!-- mdfmt-ignore -->\n> Some   text\n"),
+            "> <!-- mdfmt-ignore -->\n>\n> Some text\n"
+        );
+    }
+
+    #[test]
+    fn test_document_stats() {
+        use crate::DocumentStats;
+
+        let input = "---\ntitle: Not counted\n---\n\n# Guide\n\nRead **the**se [docs](https://example.com) `now`.\n\n## Steps\n\n- one\n- two ![logo](logo.png)\n\n```\nlet code = 1;\n```\n";
+        let stats = DocumentStats::collect(input);
+        assert_eq!(
+            stats,
+            DocumentStats {
+                headings: [1, 1, 0, 0, 0, 0],
+                list_items: 2,
+                links: 1,
+                images: 1,
+                code_blocks: 1,
+                words: 9,
+            }
+        );
+        assert_eq!(stats.heading_count(), 2);
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        let input = "# Heading\nText right under it.\n\n* one\n* two\n";
+        let formatted = format_markdown(input);
+        assert_eq!(
+            formatted,
+            "# Heading\n\nText right under it.\n\n- one\n- two\n"
+        );
+
+        assert_eq!(
+            changed_ranges(input, &formatted),
+            vec![
+                // Blank line inserted after the heading
+                ChangeRange {
+                    line: 2,
+                    column: 1,
+                    end_line: 2,
+                    end_column: 1,
+                },
+                // Both list markers rewritten
+                ChangeRange {
+                    line: 4,
+                    column: 1,
+                    end_line: 5,
+                    end_column: 6,
+                },
+            ]
+        );
+        assert!(changed_ranges(&formatted, &formatted).is_empty());
+    }
+
+    #[test]
+    fn test_strikethrough_preservation() {
+        let input = "This has ~~strikethrough~~ text.";
+        let output = format_markdown(input);
+        // Strikethrough should be preserved
+        assert!(output.contains("~~strikethrough~~"));
+    }
+
+    #[test]
+    fn test_gfm_autolinks() {
+        // Angle-bracket autolinks stay autolinks, even with reference links
+        let input = "Visit <https://example.com> or <me@example.com> for more info.";
+        let expected = "Visit <https://example.com> or <me@example.com> for more info.\n";
+        assert_eq!(format_markdown(input), expected);
+        let output = Formatter::new(80)
+            .link_style(crate::LinkStyle::Reference)
+            .format_document(input);
+        assert_eq!(output, expected);
+
+        // Bare URLs are written as they are, and never split
+        let input = "See https://example.com/a/very/long/path/to/a/page for more.\n";
+        assert_eq!(format_markdown(input), input);
+        let output = Formatter::with_wrap_mode(20, WrapMode::Always)
+            .break_long_words(true)
+            .format_document(input);
+        assert_eq!(
+            output,
+            "See\nhttps://example.com/a/very/long/path/to/a/page\nfor more.\n"
+        );
+
+        // GFM alerts keep their tag line
+        let input = "> [!NOTE]\n> Useful   information.\n\n> [!WARNING]\n> - Careful\n";
+        let expected = "> [!NOTE]\n> Useful information.\n\n> [!WARNING]\n> - Careful\n";
+        assert_eq!(format_markdown(input), expected);
+        assert_eq!(format_markdown(expected), expected);
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        let format = |input: &str, mode| {
+            Formatter::with_wrap_mode(24, mode)
+                .expand_tabs(Some(4))
+                .format_document(input)
+        };
+
+        // Wrapped as if the tab were spaces
+        let input = "Some\ttext with a tab in it, long enough to wrap.\n";
+        let spaces = "Some    text with a tab in it, long enough to wrap.\n";
+        assert_eq!(
+            format(input, WrapMode::Always),
+            format(spaces, WrapMode::Always)
+        );
+
+        // Table cells line up by the expanded width; code blocks keep tabs
+        let input = "| a\tb | c |\n|---|---|\n| 1 | 2 |\n\n```\nkeep\ttab\n```\n";
+        assert_eq!(
+            format(input, WrapMode::Preserve),
+            "| a    b | c   |\n| ------ | --- |\n| 1      | 2   |\n\n```\nkeep\ttab\n```\n"
+        );
+
+        // A width of 0 counts as 1 instead of deleting the tabs
+        let output = Formatter::new(80)
+            .expand_tabs(Some(0))
+            .format_document("| a\tb |\n|---|\n");
+        assert_eq!(output, "| a b |\n| --- |\n");
+    }
+
+    #[test]
+    fn test_break_long_words() {
+        let input = "See aaaaaaaaaabbbbbbbbbbccccccccccdddddddddd here.\n\nA [link](https://example.com/a/very/long/path) stays.\n";
+        let format = |break_long_words| {
+            Formatter::with_wrap_mode(20, WrapMode::Always)
+                .break_long_words(break_long_words)
+                .format(parse_markdown(input))
+        };
+
+        // Off by default: the long word overflows on its own line
+        assert_eq!(
+            format(false),
+            "See\naaaaaaaaaabbbbbbbbbbccccccccccdddddddddd\nhere.\n\nA\n[link](https://example.com/a/very/long/path)\nstays.\n"
+        );
+
+        // Split at the width boundary, but the link's URL is never broken
+        let output = format(true);
+        assert_eq!(
+            output,
+            "See\naaaaaaaaaabbbbbbbbbb\nccccccccccdddddddddd\nhere.\n\nA\n[link](https://example.com/a/very/long/path)\nstays.\n"
+        );
+        let mut formatter = Formatter::with_wrap_mode(20, WrapMode::Always).break_long_words(true);
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // A word isn't split where a piece would start a line as a setext
+        // underline or another block
+        let input = "Some text aaaaaaaaaaaaaaaaaaaa====================\n";
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "Some text\naaaaaaaaaaaaaaaaaaaa====================\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+    }
+
+    #[test]
+    fn test_no_widows() {
+        let input = "The quick brown fox jumps over the lazy dog and keeps running far away.";
+
+        // Greedy wrapping leaves "away." alone on the last line
+        let greedy = Formatter::with_wrap_mode(34, WrapMode::Always).format(parse_markdown(input));
+        assert_eq!(
+            greedy,
+            "The quick brown fox jumps over the\nlazy dog and keeps running far\naway.\n"
+        );
+
+        let mut formatter = Formatter::with_wrap_mode(34, WrapMode::Always).no_widows(true);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "The quick brown fox jumps over the\nlazy dog and keeps running\nfar away.\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // The moved word can't start the last line as a list marker
+        let output = Formatter::with_wrap_mode(16, WrapMode::Always)
+            .no_widows(true)
+            .format_document("aaaa bbbb cccc - dddd\n");
+        assert_eq!(output, "aaaa bbbb cccc -\ndddd\n");
+
+        // A paragraph that fits on one line is left alone
+        assert_eq!(
+            formatter.format(parse_markdown("Short line.")),
+            "Short line.\n"
+        );
+    }
+
+    #[test]
+    fn test_hard_break_preservation() {
+        let input = "Line one  \nLine two";
+        let output = format_markdown(input);
+        // Hard break should be preserved (two spaces before newline)
+        assert!(output.contains("  \n"), "Hard break should be preserved");
+    }
+
+    #[test]
+    fn test_normalize_code_lang() {
+        let input = "```JS\nx\n```\n\n```Py {.numberLines}\nx\n```\n\n```Rust\nx\n```\n";
+
+        // Off by default
+        assert_eq!(format_markdown(input), input);
+
+        let mut formatter = Formatter::new(80).normalize_code_lang(true);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "```javascript\nx\n```\n\n```python {.numberLines}\nx\n```\n\n```rust\nx\n```\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // The alias table can be replaced
+        let aliases = [("rust".to_string(), "rs".to_string())]
+            .into_iter()
+            .collect();
+        let mut formatter = Formatter::new(80)
+            .normalize_code_lang(true)
+            .code_lang_aliases(aliases);
+        let output = formatter.format(parse_markdown(input));
+        assert!(output.starts_with("```js\n"), "{}", output);
+        assert!(output.ends_with("```rs\nx\n```\n"), "{}", output);
+    }
+
+    #[test]
+    fn test_inline_math() {
+        // A formula is never broken across lines, even where it has spaces
+        let input = "The identity $e^{i\\pi} + 1 = 0$ relates five constants, and $a + b = c$ too.";
+        let mut formatter =
+            Formatter::with_options(30, WrapMode::Always, OrderedListMode::default());
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "The identity\n$e^{i\\pi} + 1 = 0$ relates\nfive constants, and\n$a + b = c$ too.\n"
+        );
+        let mut formatter =
+            Formatter::with_options(30, WrapMode::Always, OrderedListMode::default());
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // A dollar amount isn't math
+        let input = "It costs $5 and $10.\n";
+        assert_eq!(format_markdown(input), input);
+    }
+
+    #[test]
+    fn test_display_math() {
+        let input = "Before\n$$\nx = \\frac{1}{2}\n$$\n\n> $$a + b$$\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "Before\n\n$$\nx = \\frac{1}{2}\n$$\n\n> $$a + b$$\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        // Prose around display math in one paragraph is split off into blocks
+        let output = format_markdown("Text $$x^2$$ more text\n");
+        assert_eq!(output, "Text\n\n$$x^2$$\n\nmore text\n");
+        assert_eq!(format_markdown(&output), output);
+
+        // In a tight list item it starts on the next line, so the list stays tight
+        let input = "- item\n  $$\n  a\n  b\n  $$\n  after\n- next\n";
+        assert_eq!(format_markdown(input), input);
+    }
+
+    #[test]
+    fn test_trim_code_trailing_whitespace() {
+        let input =
+            "```python\ndef f():  \n    return 1\t\n\n  \n    pass\n```\n\n> ```\n> x  \n> ```\n";
+
+        // Off by default: code is kept byte-for-byte
+        assert_eq!(format_markdown(input), input);
+
+        let mut formatter = Formatter::new(80).trim_code_trailing_whitespace(true);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "```python\ndef f():\n    return 1\n\n\n    pass\n```\n\n> ```\n> x\n> ```\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+    }
+
+    #[test]
+    fn test_code_block_style() {
+        let input =
+            "Text:\n\n    let x = 1;\n\n      indented more\n\n> quote\n>\n>     quoted code\n";
+
+        // Indented code blocks become fenced by default
+        assert_eq!(
+            format_markdown(input),
+            "Text:\n\n```\nlet x = 1;\n\n  indented more\n```\n\n> quote\n>\n> ```\n> quoted code\n> ```\n"
+        );
+
+        let mut formatter = Formatter::new(80).code_block_style(crate::CodeBlockStyle::Preserve);
+        assert_eq!(formatter.format(parse_markdown(input)), input);
+
+        // Fenced blocks stay fenced
+        let fenced = "```rust\nlet x = 1;\n```\n";
+        assert_eq!(formatter.format(parse_markdown(fenced)), fenced);
+
+        // Inside list items, and as an item's first block
+        let list = "- item\n\n      code\n\n-     first\n      second\n";
+        assert_eq!(formatter.format(parse_markdown(list)), list);
+
+        // Right after a list, four spaces would continue its last item
+        assert_eq!(
+            formatter.format(parse_markdown("-    item\n\n    code\n")),
+            "- item\n\n```\ncode\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_collapse_blank_lines() {
+        assert_eq!(format_markdown("A\n\n\n\n\nB"), "A\n\nB\n");
+
+        // ...but code blocks and raw HTML are kept byte-for-byte, also inside
+        // list items and footnote definitions
+        let input = "```\na\n\n\n\nb\n```\n\n- item\n\n  ```\n  a\n\n\n\n  b\n  ```\n";
+        assert_eq!(format_markdown(input), input);
+        let input = "<!--\n\n\n\nx\n-->\n\nA\n";
+        assert_eq!(format_markdown(input), input);
+        let input = "x[^1]\n\n[^1]: note\n\n    ```\n    a\n\n\n\n    b\n    ```\n\n    <!--\n\n\n    -->\n";
+        assert_eq!(format_markdown(input), input);
+    }
+
+    #[test]
+    fn test_max_blank_lines() {
+        let input = "# A\n\n\n\nText\n\n\n- a\n\n\n  b\n\n```\nx\n\n\n\ny\n```\n";
+
+        // One blank line (the default) between blocks, whatever the source has
+        let mut formatter = Formatter::new(80).max_blank_lines(1);
+        assert_eq!(
+            formatter.format_document(input),
+            "# A\n\nText\n\n- a\n\n  b\n\n```\nx\n\n\n\ny\n```\n"
+        );
+
+        // Up to two between top-level blocks; inside the list item it's still one
+        let mut formatter = Formatter::new(80).max_blank_lines(2);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "# A\n\n\nText\n\n\n- a\n\n  b\n\n```\nx\n\n\n\ny\n```\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+    }
+
+    #[test]
+    fn test_no_leading_blank_lines() {
+        let blocks = [
+            "# Heading\n",
+            "Paragraph\n",
+            "- item\n",
+            "1. item\n",
+            "> quote\n",
+            "```\ncode\n```\n",
+            "---\n",
+            "| a |\n| - |\n| 1 |\n",
+            "<!-- comment -->\n",
+        ];
+
+        for block in blocks {
+            for leading in ["\n", "\n\n\n", "  \n\t\n"] {
+                let input = format!("{}{}", leading, block);
+                let output = format_markdown(&input);
+                assert!(
+                    !output.starts_with(char::is_whitespace),
+                    "{:?} -> {:?}",
+                    input,
+                    output
+                );
+
+                let input = format!("---\ntitle: Test\n---\n{}{}", leading, block);
+                let mut formatter = Formatter::new(80);
+                let output = formatter.format_document(&input);
+                let body = output.strip_prefix("---\ntitle: Test\n---\n\n");
+                assert!(
+                    body.is_some_and(|body| !body.starts_with(char::is_whitespace)),
+                    "{:?} -> {:?}",
+                    input,
+                    output
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_preserve_mode_whitespace() {
+        // Line breaks stay; whitespace within each line collapses to one space
+        let input = "Some   text\twith  gaps \n   indented   continuation\nhard break   \nbackslash\\\nend\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "Some text with gaps\nindented continuation\nhard break  \nbackslash  \nend\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        let input = "- item   one\n    continued    here\n\n> quoted   text\n>    more\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "- item one\n  continued here\n\n> quoted text\n> more\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+    }
+
+    #[test]
+    fn test_no_spurious_hard_breaks() {
+        // A long line that gets wrapped should NOT have hard breaks (when using always mode)
+        let input = "This is a very long line that needs to be wrapped because it exceeds eighty characters.";
+        let output = format_markdown_always(input);
+        // Should not contain hard breaks (two spaces before newline)
+        assert!(
+            !output.contains("  \n"),
+            "Wrapped lines should not have hard breaks"
+        );
+    }
+
+    #[test]
+    fn test_emphasis_across_hard_break() {
+        let input = "> *line one is long enough to need wrapping at width thirty  \n> line two*\n\n- _item one\\\n  item two_\n";
+        let expected = [
+            (
+                WrapMode::Preserve,
+                "> *line one is long enough to need wrapping at width thirty  \n> line two*\n\n- *item one  \n  item two*\n",
+            ),
+            (
+                WrapMode::Always,
+                "> *line one is long enough to\n> need wrapping at width\n> thirty  \n> line two*\n\n- *item one  \n  item two*\n",
+            ),
+            (
+                WrapMode::Never,
+                "> *line one is long enough to need wrapping at width thirty  \n> line two*\n\n- *item one  \n  item two*\n",
+            ),
+        ];
+
+        for (mode, expected) in expected {
+            let format = |s: &str| Formatter::with_wrap_mode(30, mode).format(parse_markdown(s));
+            let output = format(input);
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(format(&output), output, "{:?} should be idempotent", mode);
+        }
+    }
+
+    fn format_markdown_table_style(input: &str, table_style: TableStyle) -> String {
+        let events = parse_markdown(input);
+        let mut formatter = Formatter::new(80).table_style(table_style);
+        formatter.format(events)
+    }
+
+    #[test]
+    fn test_bullet_style() {
+        use crate::BulletStyle;
+
+        let input = "- one\n  + *two*\n    * three\n- four\n\n1. ordered\n   - mixed\n";
+        for (style, marker) in [
+            (BulletStyle::Dash, '-'),
+            (BulletStyle::Star, '*'),
+            (BulletStyle::Plus, '+'),
+        ] {
+            let mut formatter = Formatter::new(80).bullet_style(style);
+            let output = formatter.format(parse_markdown(input));
+            let expected = "- one\n  - *two*\n    - three\n- four\n\n1. ordered\n   - mixed\n"
+                .replace('-', &marker.to_string());
+            assert_eq!(output, expected, "{:?}", style);
+            assert_eq!(formatter.format(parse_markdown(&output)), output);
+        }
+
+        assert_eq!("*".parse::<BulletStyle>(), Ok(BulletStyle::Star));
+        assert_eq!("plus".parse::<BulletStyle>(), Ok(BulletStyle::Plus));
+    }
+
+    #[test]
+    fn test_indent_style() {
+        use crate::IndentStyle;
+
+        let input = "- one\n  more\n  - nested\n    - deeper\n- ```\n  code\n  ```\n\n1. a\n   1. b\n\n> - q\n>   - r\n";
+
+        let mut formatter = Formatter::new(80).indent_style(IndentStyle::Tab);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "-\tone\n\tmore\n\t-\tnested\n\t\t-\tdeeper\n-\t```\n\tcode\n\t```\n\n1.\ta\n\t1.\tb\n\n> - q\n>   - r\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+        // Same document structure as the space-indented version
+        assert_eq!(format_markdown(&output), format_markdown(input));
+
+        let mut formatter = Formatter::new(80).indent_style(IndentStyle::Spaces(4));
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "-   one\n    more\n    -   nested\n        -   deeper\n-   ```\n    code\n    ```\n\n1.  a\n    1.  b\n\n> -   q\n>     -   r\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+        assert_eq!(format_markdown(&output), format_markdown(input));
+
+        // Tabs count to the next tab stop when wrapping
+        let mut formatter =
+            Formatter::with_options(20, WrapMode::Always, OrderedListMode::default())
+                .indent_style(IndentStyle::Tab);
+        let output = formatter.format(parse_markdown("- one two three four five six"));
+        assert_eq!(output, "-\tone two three\n\tfour five six\n");
+
+        assert_eq!("tab".parse::<IndentStyle>(), Ok(IndentStyle::Tab));
+        assert_eq!("4".parse::<IndentStyle>(), Ok(IndentStyle::Spaces(4)));
+        assert!("8".parse::<IndentStyle>().is_err());
+    }
+
+    #[test]
+    fn test_ordered_list_delimiter() {
+        use crate::OrderedListDelimiter;
+
+        let mut formatter = Formatter::new(20).ordered_list_delimiter(OrderedListDelimiter::Paren);
+        let input = "1) First\n2) Second\n";
+        assert_eq!(formatter.format(parse_markdown(input)), input);
+        assert_eq!(
+            formatter.format(parse_markdown("1. First\n2. Second")),
+            input
+        );
+
+        // Wrapped lines and nested lists line up after the delimiter
+        let mut formatter = Formatter::with_options(20, WrapMode::Always, OrderedListMode::One)
+            .ordered_list_delimiter(OrderedListDelimiter::Paren);
+        let input = "1. A long item that has to wrap\n   1. nested\n";
+        let expected = "1) A long item that\n   has to wrap\n   1) nested\n";
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(output, expected);
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        assert_eq!(
+            ")".parse::<OrderedListDelimiter>(),
+            Ok(OrderedListDelimiter::Paren)
+        );
+    }
+
+    #[test]
+    fn test_wrap_wide_characters() {
+        let input = "日本語の 文章を 折り返す テストです 幅は 二倍で 数えます";
+        let mut formatter =
+            Formatter::with_options(20, WrapMode::Always, OrderedListMode::default());
+        let output = formatter.format(parse_markdown(input));
+        // Each CJK character takes two columns, so lines fill up to 20 columns
+        // (10 characters) rather than 20 bytes or 20 characters
+        assert_eq!(
+            output,
+            "日本語の 文章を\n折り返す テストです\n幅は 二倍で 数えます\n"
+        );
+
+        let mut formatter =
+            Formatter::with_options(24, WrapMode::Always, OrderedListMode::default());
+        let output = formatter.format(parse_markdown(&format!("> {}", input)));
+        assert_eq!(
+            output,
+            "> 日本語の 文章を\n> 折り返す テストです\n> 幅は 二倍で 数えます\n"
+        );
+    }
+
+    #[test]
+    fn test_align_ordered_markers() {
+        let mut input: String = (1..=10).map(|n| format!("{}. Item {}\n", n, n)).collect();
+        input = input.replace("Item 2\n", "Item 2\nwith more text\n");
+        input = input.replace("Item 10\n", "Item 10\n    - nested\n");
+        input.push_str("\nAfter.\n\n1. short\n2. list\n");
+
+        let mut formatter = Formatter::new(80).align_ordered_markers(true);
+        let output = formatter.format(parse_markdown(&input));
+        assert_eq!(
+            output,
+            " 1. Item 1\n 2. Item 2\n    with more text\n 3. Item 3\n 4. Item 4\n 5. Item 5\n \
+             6. Item 6\n 7. Item 7\n 8. Item 8\n 9. Item 9\n10. Item 10\n    - nested\n\n\
+             After.\n\n1. short\n2. list\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // Every period is in the same column, and content starts right after it
+        for line in output.lines().take(11).filter(|l| l.contains(". Item")) {
+            assert_eq!(line.find(". Item"), Some(2), "{:?}", line);
+        }
+
+        // Off by default
+        assert!(format_markdown(&input).starts_with("1. Item 1\n"));
+    }
+
+    #[test]
+    fn test_table_padded_and_compact_styles() {
+        let input = "|Name|Age|\n|:--|--:|\n|Alice|30|\n|Bob|4|";
+
+        let padded = format_markdown_table_style(input, TableStyle::Padded);
+        assert_eq!(
+            padded,
+            "| Name  | Age |\n| :---- | --: |\n| Alice |  30 |\n| Bob   |   4 |\n"
+        );
+
+        let compact = format_markdown_table_style(input, TableStyle::Compact);
+        assert_eq!(
+            compact,
+            "| Name | Age |\n| :-- | --: |\n| Alice | 30 |\n| Bob | 4 |\n"
+        );
+
+        assert_eq!(
+            padded,
+            format_markdown_table_style(&padded, TableStyle::Padded),
+            "Padded tables should be idempotent"
+        );
+        assert_eq!(
+            compact,
+            format_markdown_table_style(&compact, TableStyle::Compact),
+            "Compact tables should be idempotent"
+        );
+
+        // Wide characters take two columns, so the pipes still line up
+        let input = "| 名前 | x |\n|---|:-:|\n| 東京タワー | 🎉 |\n";
+        let padded = format_markdown_table_style(input, TableStyle::Padded);
+        assert_eq!(
+            padded,
+            "| 名前       |  x  |\n| ---------- | :-: |\n| 東京タワー | 🎉  |\n"
+        );
+        assert_eq!(
+            padded,
+            format_markdown_table_style(&padded, TableStyle::Padded)
+        );
+    }
+
+    #[test]
+    fn test_tables_fixture() {
+        // The fixture is already formatted, so formatting must leave it alone
+        assert_eq!(format_markdown(TABLES), TABLES);
+
+        // Unpadded source cells format into the same aligned table, with
+        // code, emphasis, links and escaped pipes kept intact
+        let source = "|Option|Type|Default|Description|\n|:-|:-:|-:|---|\n|`--width`|`usize`|80|Line *width* for **wrapping**|\n|`--wrap`|enum|`preserve`|See [modes](#modes) and ~~old~~ docs|\n|`a \\| b`|pipe|–|Escaped \\| pipe|\n|Ünïcödé|ok| |empty cell|\n";
+        let output = format_markdown(source);
+        let table_start = TABLES.find("| Option").unwrap();
+        let table_end = TABLES.find("\n\nText after.").unwrap() + 1;
+        assert_eq!(output, &TABLES[table_start..table_end]);
+    }
+
+    #[test]
+    fn test_tables_in_list_items() {
+        let table = "| x   | y   |\n  | --- | --- |\n  | 1   | 2   |\n";
+        for input in [
+            format!("- a\n\n  {}", table),
+            format!("- a\n  {}- b\n", table),
+            format!("- {}- b\n", table),
+            format!("> 1. a\n>\n>    {}", table.replace("\n  ", "\n>    ")),
+        ] {
+            let output = format_markdown(&input);
+            assert_eq!(output, input);
+            assert!(parse_markdown(&output).iter().any(|e| matches!(
+                e,
+                pulldown_cmark::Event::Start(pulldown_cmark::Tag::Table(_))
+            )));
+        }
+
+        // Reformatted tables keep the indentation of the item
+        assert_eq!(
+            format_markdown("- a\n\n  | x | y |\n  |---|---|\n  | 1 | 2 |\n"),
+            format!("- a\n\n  {}", table)
+        );
+    }
+
+    #[test]
+    fn test_table_compact_escapes_pipes() {
+        let input = "| A | B |\n| - | - |\n| x \\| y | z |";
+        let output = format_markdown_table_style(input, TableStyle::Compact);
+        assert!(output.contains("| x \\| y | z |"));
+    }
+
+    #[test]
+    fn test_table_cells_escape_pipes() {
+        let input = "| A | B |\n| - | - |\n| a \\| b | `c \\| d` |\n| [l](/x\\|y \"t\\|u\") | $\\|x\\|$ |\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "| A                 | B        |\n\
+             | ----------------- | -------- |\n\
+             | a \\| b            | `c \\| d` |\n\
+             | [l](/x\\|y \"t\\|u\") | $\\|x\\|$  |\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        // Every row keeps its two cells
+        let cells = parse_markdown(&output)
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    pulldown_cmark::Event::Start(pulldown_cmark::Tag::TableCell)
+                )
+            })
+            .count();
+        assert_eq!(cells, 6);
+    }
+
+    #[test]
+    fn test_numeric_columns_right_aligned() {
+        let input = "| Item | Count | Note |\n|---|---|:-|\n| Apples | 1,024 | 3 |\n| Pears | -7.5 | x |\n| Plums | | 12% |";
+
+        // Off by default
+        assert!(format_markdown(input).contains("| ------ | ----- | :--- |"));
+
+        let mut formatter = Formatter::new(80).align_numeric_columns(true);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "| Item   | Count | Note |\n\
+             | ------ | ----: | :--- |\n\
+             | Apples | 1,024 | 3    |\n\
+             | Pears  |  -7.5 | x    |\n\
+             | Plums  |       | 12%  |\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+    }
+
+    #[test]
+    fn test_edge_soft_breaks_are_trimmed() {
+        use pulldown_cmark::{Event, Tag, TagEnd};
+
+        for wrap_mode in [WrapMode::Always, WrapMode::Never, WrapMode::Preserve] {
+            let events = vec![
+                Event::Start(Tag::Paragraph),
+                Event::SoftBreak,
+                Event::Text("Hello world".into()),
+                Event::SoftBreak,
+                Event::End(TagEnd::Paragraph),
+                Event::Start(Tag::Paragraph),
+                Event::Text("Next".into()),
+                Event::SoftBreak,
+                Event::End(TagEnd::Paragraph),
+            ];
+            let mut formatter = Formatter::with_wrap_mode(80, wrap_mode);
+            let output = formatter.format(events);
+            assert_eq!(
+                output, "Hello world\n\nNext\n",
+                "Edge soft breaks should be trimmed in {:?} mode",
+                wrap_mode
+            );
+        }
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_input_source_errors() {
+        use crate::cli::{Args, CliError};
+        use clap::Parser;
+
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "notes\n").unwrap();
+        let sources = |args: &[&str]| {
+            Args::try_parse_from(std::iter::once("mdfmt").chain(args.iter().copied()))
+                .unwrap()
+                .get_input_sources()
+        };
+
+        let error = sources(&[notes.to_str().unwrap()]).unwrap_err();
+        assert!(
+            matches!(&error, CliError::NotMarkdown { path, embedded: false, .. } if *path == notes),
+            "{:?}",
+            error
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "File '{}' is not a markdown file (.md, .markdown)",
+                notes.display()
+            )
+        );
+        assert_eq!(error.exit_code(), 1);
+
+        let error = sources(&[]).unwrap_err();
+        assert!(matches!(error, CliError::NoInput));
+        assert_eq!(error.exit_code(), 2);
+        assert!(matches!(
+            sources(&["[invalid"]).unwrap_err(),
+            CliError::InvalidGlob { .. }
+        ));
+        assert!(matches!(
+            sources(&[dir.path().to_str().unwrap()]).unwrap_err(),
+            CliError::NoFilesFound { .. }
+        ));
+    }
+
+    #[test]
+    fn test_analyze_reports_missing_relative_link_targets() {
+        use crate::{analyze, AnalyzeOptions, DiagnosticKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("exists.md"), "# Exists\n").unwrap();
+
+        let input = "# Doc\n\nSee [here](exists.md#intro) and [there](missing.md).\n\n[web](https://example.com)\n";
+
+        // Without a base path, links aren't checked
+        let analysis = analyze(input, &AnalyzeOptions::default());
+        assert_eq!(analysis.links.len(), 3);
+        assert!(analysis.diagnostics.is_empty());
+
+        let options = AnalyzeOptions {
+            base_path: Some(dir.path().to_path_buf()),
+            ..AnalyzeOptions::default()
+        };
+        let analysis = analyze(input, &options);
+        assert_eq!(analysis.diagnostics.len(), 1);
+        let diagnostic = &analysis.diagnostics[0];
+        assert_eq!(diagnostic.kind, DiagnosticKind::MissingLinkTarget);
+        assert!(diagnostic.message.contains("missing.md"));
+        assert_eq!((diagnostic.line, diagnostic.column), (3, 33));
+    }
+
+    #[test]
+    fn test_blank_lines_in_list_items() {
+        // Paragraphs of an item are separated by one (indented) blank line,
+        // and so are the items of the loose list
+        let input = "1. First paragraph.\n\n\n   Second paragraph.\n2. Next item.\n";
+        let expected = "1. First paragraph.\n\n   Second paragraph.\n\n2. Next item.\n";
+        assert_eq!(format_markdown(input), expected);
+        assert_eq!(format_markdown(expected), expected);
+
+        // Blocks in items of tight lists follow each other directly
+        for input in [
+            "- text\n  ```\n  code\n  ```\n- next\n",
+            "- text\n  ***\n- next\n",
+            "- ***\n- next\n",
+        ] {
+            let output = format_markdown(input);
+            assert_eq!(output, input);
+            assert_eq!(format_markdown(&output), output);
+        }
+    }
+
+    #[test]
+    fn test_html_in_lists() {
+        // Inline HTML stays in its paragraph
+        for input in [
+            "H<sub>2</sub>O is water.\n",
+            "- H<sub>2</sub>O\n- line<br>break\n",
+        ] {
+            assert_eq!(format_markdown(input), input);
+        }
+
+        // HTML blocks in tight lists don't get blank lines around them
+        for input in [
+            "- text\n  <div>block</div>\n- next\n",
+            "- <div>first</div>\n- next\n",
+            "- text\n\n  <div>loose</div>\n\n- next\n",
+        ] {
+            let output = format_markdown(input);
+            assert_eq!(output, input);
+            assert_eq!(format_markdown(&output), output);
+        }
+    }
+
+    #[test]
+    fn test_task_lists() {
+        let input = "* [x] Done\n* [ ]   Not done yet, with text long enough to wrap\n";
+        let expected = "- [x] Done\n- [ ] Not done yet, with text\n  long enough to wrap\n";
+        let mut formatter = Formatter::with_wrap_mode(30, WrapMode::Always);
+        assert_eq!(formatter.format_document(input), expected);
+        assert_eq!(formatter.format_document(expected), expected);
+
+        // Checked items are `[x]` unless upper case is chosen
+        let mut formatter = Formatter::new(80).task_marker_case(crate::TaskMarkerCase::Upper);
+        assert_eq!(
+            formatter.format_document("- [x] a\n- [X] b\n- [ ] c\n"),
+            "- [X] a\n- [X] b\n- [ ] c\n"
+        );
+        assert_eq!(format_markdown("- [X] a\n"), "- [x] a\n");
+
+        // The checkbox stays on the line of the first word, however long
+        let mut formatter = Formatter::with_wrap_mode(12, WrapMode::Always);
+        assert_eq!(
+            formatter.format_document("- [ ] unbreakable_word\n"),
+            "- [ ] unbreakable_word\n"
+        );
+    }
+
+    #[test]
+    fn test_definition_lists() {
+        let input = "Apple\n:   A red fruit\ngrown on trees.\n:   A company.\n\nBanana\n: Yellow.\n\n    Long and curved.\n";
+        let expected = "Apple\n\n: A red fruit grown on trees.\n\n: A company.\n\nBanana\n\n: Yellow.\n\n  Long and curved.\n";
+        let output = format_markdown_always(input);
+        assert_eq!(output, expected);
+        assert_eq!(format_markdown_always(&output), output);
+
+        // Wrapped definitions line up after the `: `, also in containers
+        let output = Formatter::with_wrap_mode(24, WrapMode::Always)
+            .format_document("- Term\n  : A definition that wraps\n\n> *Term*\n> : Short.\n");
+        assert_eq!(
+            output,
+            "- Term\n  : A definition that\n    wraps\n\n> *Term*\n> : Short.\n"
+        );
+
+        // Blocks in definitions are indented under the `: `, and a tight list
+        // stays tight
+        for (input, expected) in [
+            ("Term\n: > quote\n", "Term\n:\n  > quote\n"),
+            ("Term\n: - a\n  - b\n", "Term\n:\n  - a\n  - b\n"),
+            ("Term\n: a\n  > q\n: b\n", "Term\n: a\n  > q\n: b\n"),
+            (
+                "Term\n: para\n\n  > quote\n\n  ```\n  code\n  ```\n",
+                "Term\n\n: para\n\n  > quote\n\n  ```\n  code\n  ```\n",
+            ),
+            ("Term\n\n: def\n", "Term\n\n: def\n"),
+        ] {
+            let output = format_markdown(input);
+            assert_eq!(output, expected);
+            assert_eq!(format_markdown(&output), output);
+        }
+
+        // So are blockquotes in list items
+        let input = "- > quote\n- a\n\n  > quote\n\n1. x\n   > q\n   > - z\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "-\n  > quote\n\n- a\n\n  > quote\n\n1. x\n   > q\n   >\n   > - z\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+    }
+
+    #[test]
+    fn test_footnotes() {
+        let input = "Text with a note[^1] and another[^long].\n\n[^1]: The first note.\n\n## Next\n\n[^long]: A longer note that is wrapped onto a second line.\n\nMore.\n";
+
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "Text with a note[^1] and another[^long].\n\n## Next\n\nMore.\n\n[^1]: The first note.\n\n[^long]: A longer note that is wrapped onto a second line.\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        // Wrapped definitions continue four spaces in, and the marker counts
+        // toward the line width
+        let wrapped = format_markdown_always(&input.replace(
+            "wrapped onto",
+            "wrapped well past the print width of eighty characters onto",
+        ));
+        assert!(
+            wrapped.ends_with("[^long]: A longer note that is wrapped well past the print width of eighty\n    characters onto a second line.\n"),
+            "{}",
+            wrapped
+        );
+        assert_eq!(format_markdown_always(&wrapped), wrapped);
+    }
+
+    #[test]
+    fn test_dangling_reference_kept_as_text() {
+        use crate::{analyze, AnalyzeOptions, DiagnosticKind, LinkStyle};
+
+        let input = "See [text][nope], [also][] and ![img][gone], or just [brackets].\n";
+        for style in [LinkStyle::Inline, LinkStyle::Reference] {
+            let mut formatter = Formatter::new(80).link_style(style);
+            assert_eq!(formatter.format(parse_markdown(input)), input);
+        }
+
+        let analysis = analyze(input, &AnalyzeOptions::default());
+        let labels: Vec<(usize, &str)> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| {
+                assert_eq!(d.kind, DiagnosticKind::UndefinedReference);
+                (d.column, d.message.as_str())
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                (
+                    5,
+                    "Reference '[nope]' has no definition and is kept as text"
+                ),
+                (
+                    19,
+                    "Reference '[also]' has no definition and is kept as text"
+                ),
+                (
+                    32,
+                    "Reference '[gone]' has no definition and is kept as text"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reference_links() {
+        use crate::LinkStyle;
+
+        let input = "Read [the docs](https://example.com/a/very/long/path) and\n[the *same* docs](https://example.com/a/very/long/path), or\n[the FAQ](https://example.com/faq \"FAQ\").\n\n- see [docs](https://example.com/a/very/long/path)\n";
+
+        // Inline stays the default
+        let output = format_markdown(input);
+        assert!(output.contains("[the docs](https://example.com/a/very/long/path)"));
+
+        // Repeated URLs share an id, and the definitions follow the body
+        let mut formatter = Formatter::new(80).link_style(LinkStyle::Reference);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "Read [the docs][1] and\n[the *same* docs][1], or\n[the FAQ][2].\n\n- see [docs][1]\n\n[1]: https://example.com/a/very/long/path\n[2]: https://example.com/faq \"FAQ\"\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+    }
+
+    #[test]
+    fn test_preserve_reference_links() {
+        use crate::LinkStyle;
+
+        let input = "See [the docs][Docs], [docs][], [Docs] and\n[the FAQ](https://example.com/faq). ![Logo][logo]\n\n[docs]: https://example.com/docs \"The docs\"\n[logo]:   /logo.png\n[unused]: https://example.com/unused\n";
+        let mut formatter = Formatter::new(80).link_style(LinkStyle::PreserveReference);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "See [the docs][Docs], [docs][], [Docs] and\n[the FAQ](https://example.com/faq). ![Logo][logo]\n\n[Docs]: https://example.com/docs \"The docs\"\n[logo]: /logo.png\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+
+        // When formatting changes the text of `[text]` or `[text][]`, the
+        // label is written out so the link still finds its definition
+        let input = "[_foo_], [Bob's page][], [a\\*b] and ![*logo*]\n\n[_foo_]: /u\n[Bob's page]: /b\n[a\\*b]: /c\n[*logo*]: /l.png\n";
+        let mut formatter = Formatter::new(80)
+            .link_style(LinkStyle::PreserveReference)
+            .punctuation(crate::Punctuation::Smart);
+        let output = formatter.format_document(input);
+        assert_eq!(
+            output,
+            "[*foo*][_foo_], [Bob\u{2019}s page][Bob's page], [a*b][a\\*b] and ![*logo*]\n\n[_foo_]: /u\n[Bob's page]: /b\n[a\\*b]: /c\n[*logo*]: /l.png\n"
+        );
+        assert_eq!(formatter.format_document(&output), output);
+
+        // The other styles resolve the references
+        assert_eq!(
+            format_markdown("[x][1]\n\n[1]: https://example.com\n"),
+            "[x](https://example.com)\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_url_definition() {
+        use crate::LinkStyle;
+
+        let input = "A [link][e], ![image][e] and [titled][t].\n\n[e]: <>\n[t]: <> \"Title\"\n";
+
+        let mut formatter = Formatter::new(80);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "A [link](<>), ![image](<>) and [titled](<> \"Title\").\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        let mut formatter = Formatter::new(80).link_style(LinkStyle::Reference);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "A [link][1], ![image](<>) and [titled][2].\n\n[1]: <>\n[2]: <> \"Title\"\n"
+        );
+        assert_eq!(formatter.format(parse_markdown(&output)), output);
+
+        // So do destinations with spaces or unbalanced parentheses
+        let input = "[a](<a b.md>), [b](<b(.md>), [c](c\\).md) and [d](d(1).md)\n\n[r]: <my file.md>\n\n[r][]\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "[a](<a b.md>), [b](<b(.md>), [c](<c).md>) and [d](d(1).md)\n\n[r](<my file.md>)\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+    }
+
+    #[test]
+    fn test_validate() {
+        use crate::{validate, AnalyzeOptions, DiagnosticKind, Severity};
+
+        let strict = AnalyzeOptions {
+            strict: true,
+            ..AnalyzeOptions::default()
+        };
+
+        let flawed =
+            "# Title\r\n\r\n### Skipped a level\n\nSee [docs][missing].\n\n<div>raw</div>\n";
+        let found: Vec<(DiagnosticKind, Severity, usize)> = validate(flawed, &strict)
+            .iter()
+            .map(|d| (d.kind, d.severity, d.line))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DiagnosticKind::LineEndings, Severity::Warning, 3),
+                (DiagnosticKind::HeadingLevelSkip, Severity::Warning, 3),
+                (DiagnosticKind::UndefinedReference, Severity::Warning, 5),
+                (DiagnosticKind::Unsupported, Severity::Error, 7),
+            ]
+        );
+
+        let clean =
+            "# Title\n\n## Section\n\n### Detail\n\n## Another [link](https://example.com)\n";
+        assert!(validate(clean, &strict).is_empty());
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_raw_html() {
+        use crate::{analyze, AnalyzeOptions, DiagnosticKind};
+
+        let strict = AnalyzeOptions {
+            strict: true,
+            ..AnalyzeOptions::default()
+        };
+
+        let analysis = analyze("Intro\n\n<div>\nraw\n</div>\n", &strict);
+        assert!(analysis.has_errors());
+        assert_eq!(analysis.diagnostics[0].kind, DiagnosticKind::Unsupported);
+        assert_eq!(analysis.diagnostics[0].line, 3);
+
+        let analysis = analyze("Just some *plain* prose.\n", &strict);
+        assert!(!analysis.has_errors());
+        assert!(analysis.diagnostics.is_empty());
+
+        // Comments, such as the ignore directives, aren't rendered and pass
+        let input = format!(
+            "{}\n\n{}\n\n- x\n\n<!--\nnote\n-->\n\nText <!-- aside --> here.\n",
+            crate::IGNORE_FILE_DIRECTIVE,
+            crate::IGNORE_DIRECTIVE
+        );
+        let analysis = analyze(&input, &strict);
+        assert!(
+            analysis.diagnostics.is_empty(),
+            "{:?}",
+            analysis.diagnostics
+        );
+
+        // Without strict mode raw HTML passes through silently
+        let analysis = analyze("<div>\nraw\n</div>\n", &AnalyzeOptions::default());
+        assert!(!analysis.has_errors());
+    }
+
+    #[test]
+    fn test_concat_documents_keeps_lists_separate() {
+        use crate::concat_documents;
+        use pulldown_cmark::{Event, Tag};
+
+        let mut formatter = Formatter::new(80);
+        let combined =
+            concat_documents(&["Intro\n\n- a\n- b", "- c\n- d", "Outro"], &mut formatter);
+
+        let list_count = parse_markdown(&combined)
+            .iter()
+            .filter(|e| matches!(e, Event::Start(Tag::List(_))))
+            .count();
+        assert_eq!(
+            list_count, 2,
+            "Adjacent lists should not merge: {:?}",
+            combined
+        );
+        assert!(combined.ends_with("- d\n\nOutro\n"));
+
+        // Reusing the formatter must not leak state between fragments
+        assert!(!combined.contains("- a\n- b\n- c"));
+        assert_eq!(combined, format_markdown(&combined));
+    }
+
+    #[test]
+    fn test_punctuation_style() {
+        use crate::Punctuation;
+
+        let input = "\"Hello,\" she said -- it's \"*really*\" fine---right?\n\n- ('single') item with `\"code\" -- here`\n\nSee <https://example.com/a--b> and [\"this\"](https://example.com/c--d).\n";
+        let smart = "\u{201C}Hello,\u{201D} she said \u{2013} it\u{2019}s \u{201C}*really*\u{201D} fine\u{2014}right?\n\n- (\u{2018}single\u{2019}) item with `\"code\" -- here`\n\nSee <https://example.com/a--b> and [\u{201C}this\u{201D}](https://example.com/c--d).\n";
+        let straight = "\"Hello,\" she said -- it's \"*really*\" fine---right?\n\n- ('single') item with `\"code\" -- here`\n\nSee <https://example.com/a--b> and [\"this\"](https://example.com/c--d).\n";
+
+        let format = |input: &str, punctuation| {
+            Formatter::new(80)
+                .punctuation(punctuation)
+                .format(parse_markdown(input))
+        };
+        assert_eq!(format(input, Punctuation::Smart), smart);
+        assert_eq!(format(smart, Punctuation::Smart), smart);
+        assert_eq!(format(smart, Punctuation::Straight), straight);
+        assert_eq!(format(straight, Punctuation::Straight), straight);
+        assert_eq!(format(smart, Punctuation::Keep), smart);
+
+        // Code blocks and math are never touched
+        let verbatim =
+            "```\n\"quoted\" -- it's\n```\n\nMath $\"a\" -- b$ and\n\n$$\n'x' --- y\n$$\n";
+        assert_eq!(format(verbatim, Punctuation::Smart), verbatim);
+    }
+
+    #[test]
+    fn test_line_ending_policy() {
+        use crate::LineEnding;
+
+        let crlf = "# Title\r\n\r\nSome  text\r\n\r\n```\r\ncode\r\n```\r\n";
+        let lf = "# Title\n\nSome text\n\n```\ncode\n```\n";
+        let crlf_formatted = lf.replace('\n', "\r\n");
+
+        let format = |input: &str, line_ending| {
+            Formatter::new(80)
+                .line_ending(line_ending)
+                .format_document(input)
+        };
+        assert_eq!(format(crlf, LineEnding::Lf), lf);
+        assert_eq!(format(crlf, LineEnding::Crlf), crlf_formatted);
+        assert_eq!(format(lf, LineEnding::Crlf), crlf_formatted);
+        assert_eq!(format(crlf, LineEnding::Preserve), crlf_formatted);
+        assert_eq!(format(lf, LineEnding::Preserve), lf);
+
+        // A well-formatted CRLF file is unchanged when its endings are kept
+        assert_eq!(
+            format(&crlf_formatted, LineEnding::Preserve),
+            crlf_formatted
+        );
+    }
+
+    #[test]
+    fn test_carriage_return_line_endings() {
+        use crate::{analyze, normalize_line_endings, AnalyzeOptions, DiagnosticKind};
+
+        let input = "---\rtitle: Old\r---\r# Title\rPara one\r\r```\rcode\r```\r";
+        let normalized = normalize_line_endings(input);
+        assert!(!normalized.contains('\r'));
+
+        let output = format_markdown_full(&normalized);
+        assert_eq!(
+            output,
+            "---\ntitle: Old\n---\n\n# Title\n\nPara one\n\n```\ncode\n```\n"
+        );
+
+        // CRLF pairs are left alone here
+        assert_eq!(normalize_line_endings("a\r\nb"), "a\r\nb");
+
+        let analysis = analyze(input, &AnalyzeOptions::default());
+        assert_eq!(analysis.diagnostics[0].kind, DiagnosticKind::LineEndings);
+    }
+
+    #[test]
+    fn test_reference_definitions_at_section_end() {
+        use crate::{LinkStyle, ReferencePlacement};
+
+        let input = "# First\n\nSee [a](https://a.example) and [b](https://b.example).\n\n## Second\n\nSee [c](https://c.example).\n\n### Deeper\n\nAgain [a](https://a.example).";
+        let mut formatter = Formatter::new(80)
+            .link_style(LinkStyle::Reference)
+            .reference_placement(ReferencePlacement::SectionEnd);
+        let output = formatter.format(parse_markdown(input));
+        assert_eq!(
+            output,
+            "# First\n\nSee [a][1] and [b][2].\n\n[1]: https://a.example\n[2]: https://b.example\n\n## Second\n\nSee [c][3].\n\n### Deeper\n\nAgain [a][1].\n\n[3]: https://c.example\n"
+        );
+        assert_eq!(output, formatter.format(parse_markdown(&output)));
+    }
+
+    // ==========================================================
+    // Fixture-Based Tests
+    // ==========================================================
+
+    const SIMPLE_GOOD: &str = include_str!("../tests/fixtures/simple-good.md");
+    const SIMPLE_BAD: &str = include_str!("../tests/fixtures/simple-bad.md");
+    const COMPLEX_GOOD: &str = include_str!("../tests/fixtures/complex-good.md");
+    const COMPLEX_BAD: &str = include_str!("../tests/fixtures/complex-bad.md");
+    const REFERENCE_TITLES: &str = include_str!("../tests/fixtures/reference-titles.md");
+    const HEADING_IN_LIST: &str = include_str!("../tests/fixtures/heading-in-list.md");
+    const ITEM_STARTS_LATER: &str = include_str!("../tests/fixtures/item-starts-later.md");
+    const TABLES: &str = include_str!("../tests/fixtures/tables.md");
+    const TIGHT_LIST: &str = include_str!("../tests/fixtures/tight-list.md");
+    const LOOSE_LIST: &str = include_str!("../tests/fixtures/loose-list.md");
+
+    #[test]
+    fn test_simple_good_is_idempotent() {
+        let formatted = format_markdown(SIMPLE_GOOD);
+        let reformatted = format_markdown(&formatted);
+        assert_eq!(
+            formatted, reformatted,
+            "simple-good.md should be idempotent"
+        );
+    }
 
     #[test]
     fn test_simple_bad_formats_correctly() {
@@ -321,6 +2434,140 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reference_titles_are_canonical() {
+        let formatted = format_markdown(REFERENCE_TITLES);
+        assert!(formatted.contains("[one](https://example.com/a \"Title A\")"));
+        assert!(formatted.contains("[two](https://example.com/b \"Title B\")"));
+        assert!(formatted.contains("[three](https://example.com/c \"Title C\")"));
+        assert!(formatted.contains("[four](https://example.com/d \"Title D\")"));
+
+        let reformatted = format_markdown(&formatted);
+        assert_eq!(
+            formatted, reformatted,
+            "reference-titles.md should be idempotent"
+        );
+    }
+
+    #[test]
+    fn test_list_looseness_is_preserved() {
+        // Both fixtures are already formatted: tight lists stay tight and
+        // loose lists keep their blank lines between items
+        assert_eq!(format_markdown(TIGHT_LIST), TIGHT_LIST);
+        assert_eq!(format_markdown(LOOSE_LIST), LOOSE_LIST);
+        assert_eq!(format_markdown_always(LOOSE_LIST), LOOSE_LIST);
+
+        // A list is loose if any of its items is separated by a blank line
+        let output = format_markdown("- a\n- b\n\n- c\n\n\n- d\n");
+        assert_eq!(output, "- a\n\n- b\n\n- c\n\n- d\n");
+        assert_eq!(format_markdown(&output), output);
+
+        // A loose item's blocks make the whole list loose
+        let output = format_markdown("1. x\n\n   more\n2. y\n");
+        assert_eq!(output, "1. x\n\n   more\n\n2. y\n");
+        assert_eq!(format_markdown(&output), output);
+    }
+
+    #[test]
+    fn test_heading_in_list_item_stays_in_list() {
+        // The fixture is already formatted, so it must come back unchanged
+        assert_eq!(format_markdown(HEADING_IN_LIST), HEADING_IN_LIST);
+        assert_eq!(format_markdown_always(HEADING_IN_LIST), HEADING_IN_LIST);
+
+        // Headings are indented under the marker, not emitted at column 0
+        let output = format_markdown(
+            "- Item
+## Heading
+- # Marker line",
+        );
+        assert_eq!(
+            output,
+            "- Item
+
+## Heading
+
+- # Marker line
+"
+        );
+        let output = format_markdown(
+            "- Item
+  ## Heading
+  text",
+        );
+        assert_eq!(
+            output,
+            "- Item
+  ## Heading
+  text
+"
+        );
+    }
+
+    #[test]
+    fn test_item_content_starting_on_later_line() {
+        let formatted = format_markdown(ITEM_STARTS_LATER);
+        assert_eq!(
+            formatted,
+            ITEM_STARTS_LATER.replace(
+                "-\n  Text on the line after the marker.",
+                "- Text on the line after the marker."
+            )
+        );
+        assert_eq!(format_markdown(&formatted), formatted);
+
+        // Content after a bare marker joins it; later blocks stay indented
+        assert_eq!(
+            format_markdown("-\n  text\n\n  more\n- b"),
+            "- text\n\n  more\n\n- b\n"
+        );
+        // A blank line right after the marker ends the (empty) item
+        assert_eq!(format_markdown("-\n\n  text"), "-\n\ntext\n");
+    }
+
+    #[test]
+    fn test_link_title_quotes_are_escaped() {
+        let input = "[x](https://example.com 'Say \"hi\"')";
+        let output = format_markdown(input);
+        assert_eq!(output, "[x](https://example.com \"Say \\\"hi\\\"\")\n");
+        assert_eq!(output, format_markdown(&output));
+    }
+
+    #[test]
+    fn test_code_block_containing_fences() {
+        let input = "~~~markdown\n# Example\n\n```rust\nfn main() {}\n```\n~~~\n\n- item\n\n  ~~~\n  ````\n  nested\n  ````\n  ~~~\n\n~~~ a`b\ncode\n~~~\n";
+        let output = format_markdown(input);
+        assert_eq!(
+            output,
+            "````markdown\n# Example\n\n```rust\nfn main() {}\n```\n````\n\n- item\n\n  `````\n  ````\n  nested\n  ````\n  `````\n\n~~~a`b\ncode\n~~~\n"
+        );
+        assert_eq!(format_markdown(&output), output);
+
+        // The sample inside stays code rather than becoming a heading
+        let events = parse_markdown(&output);
+        assert!(!events.iter().any(|e| matches!(
+            e,
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Heading { .. })
+        )));
+
+        // Indented code converted to a fence gets a longer one too
+        let output = format_markdown("Text\n\n    ```\n    inside\n    ```\n");
+        assert_eq!(output, "Text\n\n````\n```\ninside\n```\n````\n");
+        assert_eq!(format_markdown(&output), output);
+
+        // Code ending the input without a newline still gets its own closing line
+        for (input, expected) in [
+            (
+                "Text\n\n    code\n    more",
+                "Text\n\n```\ncode\nmore\n```\n",
+            ),
+            ("```\ncode", "```\ncode\n```\n"),
+        ] {
+            let output = format_markdown(input);
+            assert_eq!(output, expected);
+            assert_eq!(format_markdown(&output), output);
+        }
+    }
+
     #[test]
     fn test_complex_preserves_code_blocks() {
         let formatted = format_markdown_full(COMPLEX_GOOD);
@@ -1,41 +1,148 @@
-use clap::Parser;
-use md_formatter::cli::{Args, InputSource};
-use md_formatter::{extract_frontmatter, parse_markdown, Formatter};
+use clap::{CommandFactory, FromArgMatches};
+use md_formatter::cli::{Args, ConfigCache, FileReport, InputSource, OutputFormat};
+use md_formatter::{
+    analyze, diff_stat, format_embedded, has_ignore_file_directive, has_lone_carriage_returns,
+    normalize_line_endings, unified_diff, AnalyzeOptions, DocumentStats, Formatter, Punctuation,
+    Severity,
+};
+use rayon::prelude::*;
+use std::fmt::Display;
 use std::fs;
 use std::io::{self, Read};
+use std::path::PathBuf;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+type Error = Box<dyn std::error::Error + Send + Sync>;
 
-    let sources = args.get_input_sources()?;
+/// What processing one source prints, held back until it's done so that
+/// files processed in parallel are still reported in order
+#[derive(Debug, Default)]
+struct Output {
+    stdout: String,
+    stderr: String,
+}
+
+impl Output {
+    fn print(&mut self, text: &str) {
+        self.stdout.push_str(text);
+    }
+
+    fn eprint(&mut self, text: &str) {
+        self.stderr.push_str(text);
+    }
+
+    fn eprintln(&mut self, message: impl Display) {
+        self.stderr.push_str(&format!("{}\n", message));
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Precedence: command line flags, then the nearest `.mdfmt.toml`, then
+    // the built-in defaults. Excludes come from the config of the current
+    // directory, the other settings from the config of each file's directory.
+    let mut configs = ConfigCache::new();
+    let cwd = std::env::current_dir()?;
+    let sources = match configs.find(&cwd)? {
+        Some(config) => args.with_config(&config, &matches).get_input_sources(),
+        None => args.get_input_sources(),
+    };
+    let sources = sources.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    });
+    if args.list_files {
+        for source in &sources {
+            println!("{}", source_label(source, &args));
+        }
+        return Ok(());
+    }
+    let json = args.format == OutputFormat::Json;
     let mut has_errors = false;
     let mut files_checked = 0;
     let mut files_would_change = 0;
+    let mut files_written = 0;
+    let mut files_formatted = 0;
+    let mut reports = Vec::new();
 
-    for source in sources {
-        match process_source(&source, &args) {
+    // Config files are looked up first; only formatting runs in parallel
+    let jobs: Vec<(InputSource, Result<Args, String>)> = sources
+        .into_iter()
+        .map(|source| {
+            let dir = match &source {
+                InputSource::File(path) => path.parent(),
+                InputSource::Stdin => args.stdin_filepath.as_ref().and_then(|path| path.parent()),
+            };
+            let file_args =
+                configs
+                    .find(&cwd.join(dir.unwrap_or(&cwd)))
+                    .map(|config| match config {
+                        Some(config) => args.with_config(&config, &matches),
+                        None => args.clone(),
+                    });
+            (source, file_args)
+        })
+        .collect();
+    let run = |(source, file_args): &(InputSource, Result<Args, String>)| {
+        let mut output = Output::default();
+        let result = match file_args {
+            Ok(file_args) => process_source(source, file_args, &mut output),
+            Err(e) => Err(e.clone().into()),
+        };
+        (result, output)
+    };
+    let results: Vec<_> = if args.jobs > 1 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs as usize)
+            .build()?
+            .install(|| jobs.par_iter().map(run).collect())
+    } else {
+        jobs.iter().map(run).collect()
+    };
+
+    for ((source, _), (result, output)) in jobs.iter().zip(results) {
+        print!("{}", output.stdout);
+        eprint!("{}", output.stderr);
+        match &result {
             Ok(changed) => {
-                if args.check {
+                if args.check || args.diff {
                     files_checked += 1;
-                    if changed {
+                    if *changed {
                         files_would_change += 1;
                         has_errors = true;
                     }
+                } else if args.write && matches!(source, InputSource::File(_)) {
+                    files_written += 1;
+                    if *changed {
+                        files_formatted += 1;
+                    }
                 }
             }
             Err(e) => {
-                eprintln!("Error: {}", e);
+                if !json {
+                    eprintln!("Error: {}", e);
+                }
                 has_errors = true;
             }
         }
+        reports.push(FileReport {
+            path: source_label(source, &args),
+            changed: *result.as_ref().unwrap_or(&false),
+            error: result.err().map(|e| e.to_string()),
+        });
     }
 
-    if args.check && files_checked > 0 {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else if args.check && files_checked > 0 {
         if files_would_change > 0 {
             eprintln!("{} file(s) would be reformatted", files_would_change);
         } else {
             eprintln!("All {} file(s) are formatted correctly", files_checked);
         }
+    } else if files_written > 0 {
+        eprintln!("Formatted {} of {} file(s)", files_formatted, files_written);
     }
 
     if has_errors {
@@ -45,7 +152,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn process_source(source: &InputSource, args: &Args) -> Result<bool, Box<dyn std::error::Error>> {
+fn process_source(source: &InputSource, args: &Args, out: &mut Output) -> Result<bool, Error> {
     let (content, path_for_output) = match source {
         InputSource::Stdin => {
             let mut buffer = String::new();
@@ -53,48 +160,235 @@ fn process_source(source: &InputSource, args: &Args) -> Result<bool, Box<dyn std
             (buffer, None)
         }
         InputSource::File(path) => {
+            // Check the size before reading so huge or binary files can't blow up memory
+            if let Some(max) = args.max_file_size {
+                let size = fs::metadata(path)?.len();
+                if size > max {
+                    return Err(format!(
+                        "Skipped '{}': file is {} bytes, larger than --max-file-size ({} bytes)",
+                        path.display(),
+                        size,
+                        max
+                    )
+                    .into());
+                }
+            }
             let content = fs::read_to_string(path)?;
             (content, Some(path.clone()))
         }
     };
 
-    // Extract frontmatter if present
-    let (frontmatter, markdown_content) = extract_frontmatter(&content);
+    let label = source_label(source, args);
+    let json = args.format == OutputFormat::Json;
 
-    // Parse and format the markdown content (without frontmatter)
-    let events = parse_markdown(markdown_content);
-    let wrap_mode: md_formatter::WrapMode = args.wrap.into();
-    let ordered_list_mode: md_formatter::OrderedListMode = args.ordered_list.into();
-    let mut formatter = Formatter::with_options(args.width, wrap_mode, ordered_list_mode);
-    let formatted = formatter.format(events);
+    // Old Mac (CR-only) line endings are converted before anything else,
+    // except in files that opt out of formatting, which are kept as they are
+    let normalized = normalize_line_endings(&content);
+    let ignored = args.embedded.is_none() && has_ignore_file_directive(&normalized);
+    if has_lone_carriage_returns(&content) && !json && !ignored {
+        out.eprintln(format!(
+            "Note: {}: converted carriage-return line endings to LF",
+            label
+        ));
+    }
+
+    if args.check_links || args.strict {
+        // Relative links resolve against the file's directory (or cwd for stdin)
+        let base_path = if !args.check_links {
+            None
+        } else {
+            match path_for_output.as_ref().or(args.stdin_filepath.as_ref()) {
+                Some(path) => path.parent().map(PathBuf::from),
+                None => std::env::current_dir().ok(),
+            }
+        };
+        let options = AnalyzeOptions {
+            base_path,
+            strict: args.strict,
+        };
+        let analysis = analyze(&normalized, &options);
+        for diagnostic in &analysis.diagnostics {
+            let severity = match diagnostic.severity {
+                Severity::Warning => "Warning",
+                Severity::Error => "Error",
+            };
+            out.eprintln(format!(
+                "{}: {}:{}:{}: {}",
+                severity, label, diagnostic.line, diagnostic.column, diagnostic.message
+            ));
+        }
+        if analysis.has_errors() {
+            return Err(format!("{}: unsupported constructs (strict mode)", label).into());
+        }
+    }
 
-    // Prepend frontmatter if it was present
-    let final_output = if let Some(fm) = frontmatter {
-        fm + &formatted
+    let mut formatter = build_formatter(args);
+    let mut format = |input: &str| match args.embedded {
+        Some(language) => format_embedded(&mut formatter, input, language),
+        None => formatter.format_document(input),
+    };
+    let final_output = if ignored {
+        content.clone()
     } else {
-        formatted
+        format(&normalized)
     };
 
+    if args.verify && !ignored {
+        // Formatting the output again must be a no-op; otherwise the formatter
+        // has a bug and the unstable output must not be written
+        let second_pass = format(&final_output);
+        if second_pass != final_output {
+            out.eprint(&unified_diff(
+                &final_output,
+                &second_pass,
+                &label,
+                &format!("{} (second pass)", label),
+            ));
+            return Err(format!("{}: formatting is not idempotent", label).into());
+        }
+    }
+
     let changed = content != final_output;
 
-    // Output
-    if let Some(path) = path_for_output {
+    if args.stats {
+        out.eprintln(format!(
+            "Stats: {}: {}",
+            label,
+            stats_summary(&final_output)
+        ));
+    }
+
+    // Output (in JSON mode, only the report at the end is printed)
+    if json {
+        if args.write && !args.check && changed {
+            if let Some(path) = path_for_output {
+                fs::write(&path, &final_output)?;
+            }
+        }
+    } else if args.diff {
+        if changed {
+            out.print(&unified_diff(
+                &content,
+                &final_output,
+                &label,
+                &format!("{} (formatted)", label),
+            ));
+        }
+    } else if let Some(path) = path_for_output {
         if args.check {
-            if changed {
-                eprintln!("Would reformat: {}", path.display());
+            if changed && args.diff_stat {
+                let (added, removed) = diff_stat(&content, &final_output);
+                out.eprintln(format!("{}: +{} -{}", path.display(), added, removed));
+            } else if changed && only_line_endings_differ(&content, &final_output) {
+                out.eprintln(format!(
+                    "Would reformat: {} (line endings only)",
+                    path.display()
+                ));
+            } else if changed {
+                out.eprintln(format!("Would reformat: {}", path.display()));
             }
         } else if args.write {
             if changed {
                 fs::write(&path, &final_output)?;
-                eprintln!("Formatted: {}", path.display());
+                out.eprintln(format!("Formatted: {}", path.display()));
+            } else if args.verbose {
+                out.eprintln(format!("Unchanged: {}", path.display()));
             }
         } else {
-            print!("{}", final_output);
+            out.print(&final_output);
         }
     } else {
         // stdin
-        print!("{}", final_output);
+        out.print(&final_output);
     }
 
     Ok(changed)
 }
+
+/// Path shown in messages for a source: for stdin, the `--stdin-filepath`
+/// if given, or `<stdin>`
+fn source_label(source: &InputSource, args: &Args) -> String {
+    match source {
+        InputSource::File(path) => path.display().to_string(),
+        InputSource::Stdin => args
+            .stdin_filepath
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "<stdin>".to_string()),
+    }
+}
+
+/// One-line summary of a document's statistics, e.g. `2 headings (h1: 1,
+/// h2: 1), 0 list items, 1 link, 0 images, 0 code blocks, 12 words`
+fn stats_summary(document: &str) -> String {
+    let stats = DocumentStats::collect(document);
+    let count = |n: usize, noun: &str| format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" });
+
+    let levels: Vec<String> = (1..=6)
+        .filter(|&level| stats.headings[level - 1] > 0)
+        .map(|level| format!("h{}: {}", level, stats.headings[level - 1]))
+        .collect();
+    let mut headings = count(stats.heading_count(), "heading");
+    if !levels.is_empty() {
+        headings.push_str(&format!(" ({})", levels.join(", ")));
+    }
+
+    [
+        headings,
+        count(stats.list_items, "list item"),
+        count(stats.links, "link"),
+        count(stats.images, "image"),
+        count(stats.code_blocks, "code block"),
+        count(stats.words, "word"),
+    ]
+    .join(", ")
+}
+
+/// Whether two versions of a file are the same apart from CRLF vs LF
+fn only_line_endings_differ(a: &str, b: &str) -> bool {
+    a.replace("\r\n", "\n") == b.replace("\r\n", "\n")
+}
+
+/// Build a formatter configured from the CLI arguments
+fn build_formatter(args: &Args) -> Formatter {
+    let punctuation = if args.smart_punctuation {
+        Punctuation::Smart
+    } else if args.straight_punctuation {
+        Punctuation::Straight
+    } else {
+        Punctuation::Keep
+    };
+
+    Formatter::with_options(args.width, args.wrap, args.ordered_list)
+        .no_widows(args.no_widows)
+        .break_long_words(args.break_long_words)
+        .expand_tabs(args.expand_tabs.map(|width| width as usize))
+        .bullet_style(args.bullet)
+        .indent_style(args.indent)
+        .ordered_list_delimiter(args.ordered_delimiter)
+        .ordered_start(args.ordered_start)
+        .align_ordered_markers(args.align_ordered_markers)
+        .table_style(args.table_style)
+        .align_numeric_columns(args.align_numeric_columns)
+        .nested_list_spacing(args.nested_list_spacing)
+        .pseudo_heading_level(args.pseudo_headings)
+        .link_style(args.link_style)
+        .reference_placement(args.reference_placement)
+        .heading_style(args.heading_style)
+        .heading_close(args.heading_close)
+        .emphasis_marker(args.emphasis_marker)
+        .strong_marker(args.strong_marker)
+        .task_marker_case(args.task_marker_case)
+        .fix_heading_spacing(args.fix_heading_spacing)
+        .code_block_style(args.code_block_style)
+        .trim_code_trailing_whitespace(args.trim_code_trailing_whitespace)
+        .normalize_code_lang(args.normalize_code_lang)
+        .punctuation(punctuation)
+        .frontmatter_spacing(args.frontmatter_spacing)
+        .normalize_frontmatter(args.normalize_frontmatter)
+        .sort_frontmatter_keys(args.sort_frontmatter_keys)
+        .horizontal_rule(args.horizontal_rule)
+        .max_blank_lines(args.max_blank_lines)
+        .trailing_newline(!args.no_trailing_newline)
+        .line_ending(args.line_ending)
+}
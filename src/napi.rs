@@ -4,23 +4,94 @@
 
 use glob::glob;
 use napi_derive::napi;
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 
-use crate::{extract_frontmatter, parse_markdown, Formatter, OrderedListMode, WrapMode};
+use crate::formatter::MAX_LIST_NUMBER;
+use crate::{changed_ranges, default_code_lang_aliases, normalize_line_endings, Formatter};
 
 /// Default directories to exclude when searching
 const DEFAULT_EXCLUDES: &[&str] = &["node_modules", "target", ".git", "vendor", "dist", "build"];
 
+/// File extensions treated as Markdown unless `extensions` is given
+const DEFAULT_EXTENSIONS: &[&str] = &["md", "markdown"];
+
 /// Options for the markdown formatter
 #[napi(object)]
 pub struct FormatOptions {
-    /// Target line width for wrapping (default: 80)
+    /// Target line width for wrapping (default: 80); 0 puts each paragraph on one line
     pub width: Option<u32>,
     /// How to wrap prose: "always", "never", or "preserve" (default: "preserve")
     pub wrap: Option<String>,
-    /// How to number ordered lists: "ascending" (1, 2, 3) or "one" (all 1.) (default: "ascending")
+    /// How to number ordered lists: "ascending" (1, 2, 3), "one" (all 1.) or "preserve" (count up from the first number) (default: "ascending")
     pub ordered_list: Option<String>,
+    /// How to lay out tables: "padded" or "compact" (default: "padded")
+    pub table_style: Option<String>,
+    /// Spacing before nested lists: "tight" or "loose" (default: "tight")
+    pub nested_list_spacing: Option<String>,
+    /// Convert bold/italic-only paragraphs into headings of this level (default: off)
+    pub pseudo_heading_level: Option<u32>,
+    /// Blank lines after frontmatter: "normalize" (exactly one) or "preserve" (keep as-is) (default: "normalize")
+    pub frontmatter_spacing: Option<String>,
+    /// Right-align table columns without explicit alignment that contain only numbers (default: false)
+    pub align_numeric_columns: Option<bool>,
+    /// How to write horizontal rules, given as an example rule such as "---", "***" or "* * *" (default: "---")
+    pub horizontal_rule: Option<String>,
+    /// With wrap "always", avoid a single word alone on the last line of a paragraph (default: false)
+    pub no_widows: Option<bool>,
+    /// End the output with a newline; set to false to embed the result inline (default: true)
+    pub trailing_newline: Option<bool>,
+    /// Turn lines like "#Heading" (no space after the hashes) into headings (default: false)
+    pub fix_heading_spacing: Option<bool>,
+    /// Line endings to write: "lf", "crlf", or "preserve" to keep the input's (default: "lf")
+    pub line_ending: Option<String>,
+    /// How quotes and dashes in prose are written: "keep", "smart" (curly quotes, en/em dashes) or "straight" (default: "keep")
+    pub punctuation: Option<String>,
+    /// Right-align ordered list numbers so the periods line up (default: false)
+    pub align_ordered_markers: Option<bool>,
+    /// Marker for unordered list items: "-", "*" or "+" (also "dash", "star", "plus"; default: "-")
+    pub bullet: Option<String>,
+    /// Heading style: "atx" (default) or "setext-when-possible" (underlined level 1 and 2 headings)
+    pub heading_style: Option<String>,
+    /// Character after ordered list numbers: "period" (1.) or "paren" (1)) (default: "period")
+    pub ordered_delimiter: Option<String>,
+    /// How to write links: "inline" ([text](url)), "reference" ([text][n] with definitions collected at the end) or "preserve-reference" (keep the source's reference labels) (default: "inline")
+    pub link_style: Option<String>,
+    /// Where reference-style definitions go: "document" (end of the document) or "section" (end of each H1/H2 section) (default: "document")
+    pub reference_placement: Option<String>,
+    /// Strip trailing spaces and tabs from code block lines (default: false)
+    pub trim_code_trailing_whitespace: Option<bool>,
+    /// Lowercase code block languages and expand aliases such as "js" to "javascript" (default: false)
+    pub normalize_code_lang: Option<bool>,
+    /// Extra language aliases for normalizeCodeLang, e.g. { "tsx": "typescript" }, added to the built-in ones
+    pub code_lang_aliases: Option<HashMap<String, String>>,
+    /// Indentation of list item content: "tab", or 2 to 4 spaces such as "4" (default: "2")
+    pub indent: Option<String>,
+    /// Most blank lines kept in a row between top-level blocks (default: 1)
+    pub max_blank_lines: Option<u32>,
+    /// With wrap "always", split words longer than the width; links, code and math are never split (default: false)
+    pub break_long_words: Option<bool>,
+    /// Heading close: "open" (default) or "closed" (`# Title #`)
+    pub heading_close: Option<String>,
+    /// Re-indent YAML frontmatter consistently, keeping values as written; frontmatter with comments is left alone (default: false)
+    pub normalize_frontmatter: Option<bool>,
+    /// Sort the top-level keys of YAML frontmatter, normalizing it (default: false)
+    pub sort_frontmatter_keys: Option<bool>,
+    /// Replace each tab in prose (not code) with this many spaces before wrapping (default: off)
+    pub expand_tabs: Option<u32>,
+    /// Emphasis marker: "asterisk" (default) or "underscore" (`_text_`; `*` is kept inside words)
+    pub emphasis_marker: Option<String>,
+    /// Strong emphasis marker: "asterisk" (default) or "underscore" (`__text__`; `**` is kept inside words)
+    pub strong_marker: Option<String>,
+    /// Case of the x in checked task list items: "lower" (default, `[x]`) or "upper" (`[X]`)
+    pub task_marker_case: Option<String>,
+    /// How indented code blocks are written: "fenced" (default) or "preserve"
+    pub code_block_style: Option<String>,
+    /// Number each ordered list starts at with ascending numbering, nested lists too; at most 999999999 (default: 1)
+    pub ordered_start: Option<u32>,
 }
 
 /// Result of a format operation
@@ -30,14 +101,103 @@ pub struct FormatResult {
     pub content: String,
     /// Whether the content was changed
     pub changed: bool,
+    /// Regions of the input that formatting changes
+    pub ranges: Vec<ChangeRange>,
+}
+
+/// A region of the input that formatting changes (1-based, end exclusive)
+#[napi(object)]
+pub struct ChangeRange {
+    pub line: u32,
+    pub col: u32,
+    pub end_line: u32,
+    pub end_col: u32,
+}
+
+impl From<crate::ChangeRange> for ChangeRange {
+    fn from(range: crate::ChangeRange) -> Self {
+        Self {
+            line: range.line as u32,
+            col: range.column as u32,
+            end_line: range.end_line as u32,
+            end_col: range.end_column as u32,
+        }
+    }
 }
 
-fn parse_wrap_mode(wrap: Option<String>) -> WrapMode {
-    wrap.and_then(|s| s.parse().ok()).unwrap_or_default()
+/// Ranges of `original` that differ in `formatted`, in NAPI form
+fn ranges(original: &str, formatted: &str) -> Vec<ChangeRange> {
+    changed_ranges(original, formatted)
+        .into_iter()
+        .map(ChangeRange::from)
+        .collect()
 }
 
-fn parse_ordered_list_mode(mode: Option<String>) -> OrderedListMode {
-    mode.and_then(|s| s.parse().ok()).unwrap_or_default()
+/// Parse an optional string option, falling back to the default on absence or error
+fn parse_or_default<T: FromStr + Default>(value: &Option<String>) -> T {
+    value
+        .as_deref()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_default()
+}
+
+/// The built-in language aliases extended (or overridden) by the caller's
+fn code_lang_aliases(extra: &Option<HashMap<String, String>>) -> HashMap<String, String> {
+    let mut aliases = default_code_lang_aliases();
+    for (alias, name) in extra.iter().flatten() {
+        aliases.insert(alias.to_lowercase(), name.clone());
+    }
+    aliases
+}
+
+/// Build a formatter from the (optional) formatting options
+fn build_formatter(options: Option<&FormatOptions>) -> Formatter {
+    let Some(o) = options else {
+        return Formatter::new(80);
+    };
+
+    Formatter::with_options(
+        o.width.unwrap_or(80) as usize,
+        parse_or_default(&o.wrap),
+        parse_or_default(&o.ordered_list),
+    )
+    .table_style(parse_or_default(&o.table_style))
+    .nested_list_spacing(parse_or_default(&o.nested_list_spacing))
+    .pseudo_heading_level(o.pseudo_heading_level)
+    .frontmatter_spacing(parse_or_default(&o.frontmatter_spacing))
+    .align_numeric_columns(o.align_numeric_columns.unwrap_or(false))
+    .horizontal_rule(parse_or_default(&o.horizontal_rule))
+    .no_widows(o.no_widows.unwrap_or(false))
+    .trailing_newline(o.trailing_newline.unwrap_or(true))
+    .fix_heading_spacing(o.fix_heading_spacing.unwrap_or(false))
+    .line_ending(parse_or_default(&o.line_ending))
+    .punctuation(parse_or_default(&o.punctuation))
+    .align_ordered_markers(o.align_ordered_markers.unwrap_or(false))
+    .bullet_style(parse_or_default(&o.bullet))
+    .heading_style(parse_or_default(&o.heading_style))
+    .ordered_list_delimiter(parse_or_default(&o.ordered_delimiter))
+    .link_style(parse_or_default(&o.link_style))
+    .reference_placement(parse_or_default(&o.reference_placement))
+    .trim_code_trailing_whitespace(o.trim_code_trailing_whitespace.unwrap_or(false))
+    .normalize_code_lang(o.normalize_code_lang.unwrap_or(false))
+    .code_lang_aliases(code_lang_aliases(&o.code_lang_aliases))
+    .indent_style(parse_or_default(&o.indent))
+    .max_blank_lines(o.max_blank_lines.unwrap_or(1) as usize)
+    .break_long_words(o.break_long_words.unwrap_or(false))
+    .heading_close(parse_or_default(&o.heading_close))
+    .normalize_frontmatter(o.normalize_frontmatter.unwrap_or(false))
+    .sort_frontmatter_keys(o.sort_frontmatter_keys.unwrap_or(false))
+    .expand_tabs(o.expand_tabs.map(|width| width as usize))
+    .emphasis_marker(parse_or_default(&o.emphasis_marker))
+    .strong_marker(parse_or_default(&o.strong_marker))
+    .task_marker_case(parse_or_default(&o.task_marker_case))
+    .code_block_style(parse_or_default(&o.code_block_style))
+    .ordered_start(
+        o.ordered_start
+            .filter(|&start| u64::from(start) <= MAX_LIST_NUMBER)
+            .unwrap_or(1)
+            .into(),
+    )
 }
 
 /// Format a markdown string with the given options.
@@ -47,21 +207,8 @@ fn parse_ordered_list_mode(mode: Option<String>) -> OrderedListMode {
 /// @returns The formatted markdown string
 #[napi]
 pub fn format_markdown(input: String, options: Option<FormatOptions>) -> String {
-    let width = options.as_ref().and_then(|o| o.width).unwrap_or(80) as usize;
-    let wrap_mode = parse_wrap_mode(options.as_ref().and_then(|o| o.wrap.clone()));
-    let ordered_list_mode =
-        parse_ordered_list_mode(options.as_ref().and_then(|o| o.ordered_list.clone()));
-
-    let (frontmatter, content) = extract_frontmatter(&input);
-    let events = parse_markdown(content);
-    let mut formatter = Formatter::with_options(width, wrap_mode, ordered_list_mode);
-    let formatted = formatter.format(events);
-
-    if let Some(fm) = frontmatter {
-        fm + &formatted
-    } else {
-        formatted
-    }
+    let normalized = normalize_line_endings(&input);
+    build_formatter(options.as_ref()).format_document(&normalized)
 }
 
 /// Format a markdown string and return both the result and whether it changed.
@@ -71,24 +218,12 @@ pub fn format_markdown(input: String, options: Option<FormatOptions>) -> String
 /// @returns An object with `content` (formatted string) and `changed` (boolean)
 #[napi]
 pub fn format_markdown_with_result(input: String, options: Option<FormatOptions>) -> FormatResult {
-    let width = options.as_ref().and_then(|o| o.width).unwrap_or(80) as usize;
-    let wrap_mode = parse_wrap_mode(options.as_ref().and_then(|o| o.wrap.clone()));
-    let ordered_list_mode =
-        parse_ordered_list_mode(options.as_ref().and_then(|o| o.ordered_list.clone()));
-
-    let (frontmatter, content) = extract_frontmatter(&input);
-    let events = parse_markdown(content);
-    let mut formatter = Formatter::with_options(width, wrap_mode, ordered_list_mode);
-    let formatted_content = formatter.format(events);
-
-    let formatted = if let Some(fm) = frontmatter {
-        fm + &formatted_content
-    } else {
-        formatted_content
-    };
+    let normalized = normalize_line_endings(&input);
+    let formatted = build_formatter(options.as_ref()).format_document(&normalized);
 
     let changed = formatted != input;
     FormatResult {
+        ranges: ranges(&input, &formatted),
         content: formatted,
         changed,
     }
@@ -101,21 +236,8 @@ pub fn format_markdown_with_result(input: String, options: Option<FormatOptions>
 /// @returns true if the content is already formatted, false otherwise
 #[napi]
 pub fn check_markdown(input: String, options: Option<FormatOptions>) -> bool {
-    let width = options.as_ref().and_then(|o| o.width).unwrap_or(80) as usize;
-    let wrap_mode = parse_wrap_mode(options.as_ref().and_then(|o| o.wrap.clone()));
-    let ordered_list_mode =
-        parse_ordered_list_mode(options.as_ref().and_then(|o| o.ordered_list.clone()));
-
-    let (frontmatter, content) = extract_frontmatter(&input);
-    let events = parse_markdown(content);
-    let mut formatter = Formatter::with_options(width, wrap_mode, ordered_list_mode);
-    let formatted_content = formatter.format(events);
-
-    let formatted = if let Some(fm) = frontmatter {
-        fm + &formatted_content
-    } else {
-        formatted_content
-    };
+    let normalized = normalize_line_endings(&input);
+    let formatted = build_formatter(options.as_ref()).format_document(&normalized);
 
     formatted == input
 }
@@ -129,26 +251,141 @@ pub struct FileResult {
     pub changed: bool,
     /// Error message if the file could not be processed
     pub error: Option<String>,
+    /// Regions of the original file that formatting changes (empty on error)
+    pub ranges: Vec<ChangeRange>,
 }
 
 /// Options for file operations
 #[napi(object)]
 pub struct FileOptions {
-    /// Target line width for wrapping (default: 80)
+    /// Target line width for wrapping (default: 80); 0 puts each paragraph on one line
     pub width: Option<u32>,
     /// How to wrap prose: "always", "never", or "preserve" (default: "preserve")
     pub wrap: Option<String>,
-    /// How to number ordered lists: "ascending" (1, 2, 3) or "one" (all 1.) (default: "ascending")
+    /// How to number ordered lists: "ascending" (1, 2, 3), "one" (all 1.) or "preserve" (count up from the first number) (default: "ascending")
     pub ordered_list: Option<String>,
+    /// How to lay out tables: "padded" or "compact" (default: "padded")
+    pub table_style: Option<String>,
+    /// Spacing before nested lists: "tight" or "loose" (default: "tight")
+    pub nested_list_spacing: Option<String>,
+    /// Convert bold/italic-only paragraphs into headings of this level (default: off)
+    pub pseudo_heading_level: Option<u32>,
+    /// Blank lines after frontmatter: "normalize" (exactly one) or "preserve" (keep as-is) (default: "normalize")
+    pub frontmatter_spacing: Option<String>,
+    /// Right-align table columns without explicit alignment that contain only numbers (default: false)
+    pub align_numeric_columns: Option<bool>,
+    /// How to write horizontal rules, given as an example rule such as "---", "***" or "* * *" (default: "---")
+    pub horizontal_rule: Option<String>,
+    /// With wrap "always", avoid a single word alone on the last line of a paragraph (default: false)
+    pub no_widows: Option<bool>,
+    /// End the output with a newline; set to false to embed the result inline (default: true)
+    pub trailing_newline: Option<bool>,
+    /// Turn lines like "#Heading" (no space after the hashes) into headings (default: false)
+    pub fix_heading_spacing: Option<bool>,
+    /// Line endings to write: "lf", "crlf", or "preserve" to keep the input's (default: "lf")
+    pub line_ending: Option<String>,
+    /// How quotes and dashes in prose are written: "keep", "smart" (curly quotes, en/em dashes) or "straight" (default: "keep")
+    pub punctuation: Option<String>,
+    /// Right-align ordered list numbers so the periods line up (default: false)
+    pub align_ordered_markers: Option<bool>,
+    /// Marker for unordered list items: "-", "*" or "+" (also "dash", "star", "plus"; default: "-")
+    pub bullet: Option<String>,
+    /// Heading style: "atx" (default) or "setext-when-possible" (underlined level 1 and 2 headings)
+    pub heading_style: Option<String>,
+    /// Character after ordered list numbers: "period" (1.) or "paren" (1)) (default: "period")
+    pub ordered_delimiter: Option<String>,
+    /// How to write links: "inline" ([text](url)), "reference" ([text][n] with definitions collected at the end) or "preserve-reference" (keep the source's reference labels) (default: "inline")
+    pub link_style: Option<String>,
+    /// Where reference-style definitions go: "document" (end of the document) or "section" (end of each H1/H2 section) (default: "document")
+    pub reference_placement: Option<String>,
+    /// Strip trailing spaces and tabs from code block lines (default: false)
+    pub trim_code_trailing_whitespace: Option<bool>,
+    /// Lowercase code block languages and expand aliases such as "js" to "javascript" (default: false)
+    pub normalize_code_lang: Option<bool>,
+    /// Extra language aliases for normalizeCodeLang, e.g. { "tsx": "typescript" }, added to the built-in ones
+    pub code_lang_aliases: Option<HashMap<String, String>>,
+    /// Indentation of list item content: "tab", or 2 to 4 spaces such as "4" (default: "2")
+    pub indent: Option<String>,
+    /// Most blank lines kept in a row between top-level blocks (default: 1)
+    pub max_blank_lines: Option<u32>,
+    /// With wrap "always", split words longer than the width; links, code and math are never split (default: false)
+    pub break_long_words: Option<bool>,
+    /// Heading close: "open" (default) or "closed" (`# Title #`)
+    pub heading_close: Option<String>,
+    /// Re-indent YAML frontmatter consistently, keeping values as written; frontmatter with comments is left alone (default: false)
+    pub normalize_frontmatter: Option<bool>,
+    /// Sort the top-level keys of YAML frontmatter, normalizing it (default: false)
+    pub sort_frontmatter_keys: Option<bool>,
+    /// Replace each tab in prose (not code) with this many spaces before wrapping (default: off)
+    pub expand_tabs: Option<u32>,
+    /// Emphasis marker: "asterisk" (default) or "underscore" (`_text_`; `*` is kept inside words)
+    pub emphasis_marker: Option<String>,
+    /// Strong emphasis marker: "asterisk" (default) or "underscore" (`__text__`; `**` is kept inside words)
+    pub strong_marker: Option<String>,
+    /// Case of the x in checked task list items: "lower" (default, `[x]`) or "upper" (`[X]`)
+    pub task_marker_case: Option<String>,
+    /// How indented code blocks are written: "fenced" (default) or "preserve"
+    pub code_block_style: Option<String>,
+    /// Number each ordered list starts at with ascending numbering, nested lists too; at most 999999999 (default: 1)
+    pub ordered_start: Option<u32>,
     /// Additional directories to exclude
     pub exclude: Option<Vec<String>>,
     /// Don't exclude any directories by default
     pub no_default_excludes: Option<bool>,
+    /// Skip files larger than this many bytes (reported as an error)
+    pub max_file_size: Option<u32>,
+    /// File extensions treated as Markdown, e.g. ["md", "mdx"] (default: ["md", "markdown"])
+    pub extensions: Option<Vec<String>>,
+}
+
+impl From<&FileOptions> for FormatOptions {
+    fn from(options: &FileOptions) -> Self {
+        Self {
+            width: options.width,
+            wrap: options.wrap.clone(),
+            ordered_list: options.ordered_list.clone(),
+            table_style: options.table_style.clone(),
+            nested_list_spacing: options.nested_list_spacing.clone(),
+            pseudo_heading_level: options.pseudo_heading_level,
+            frontmatter_spacing: options.frontmatter_spacing.clone(),
+            align_numeric_columns: options.align_numeric_columns,
+            horizontal_rule: options.horizontal_rule.clone(),
+            no_widows: options.no_widows,
+            trailing_newline: options.trailing_newline,
+            fix_heading_spacing: options.fix_heading_spacing,
+            line_ending: options.line_ending.clone(),
+            punctuation: options.punctuation.clone(),
+            align_ordered_markers: options.align_ordered_markers,
+            bullet: options.bullet.clone(),
+            heading_style: options.heading_style.clone(),
+            ordered_delimiter: options.ordered_delimiter.clone(),
+            link_style: options.link_style.clone(),
+            reference_placement: options.reference_placement.clone(),
+            trim_code_trailing_whitespace: options.trim_code_trailing_whitespace,
+            normalize_code_lang: options.normalize_code_lang,
+            code_lang_aliases: options.code_lang_aliases.clone(),
+            indent: options.indent.clone(),
+            max_blank_lines: options.max_blank_lines,
+            break_long_words: options.break_long_words,
+            heading_close: options.heading_close.clone(),
+            normalize_frontmatter: options.normalize_frontmatter,
+            sort_frontmatter_keys: options.sort_frontmatter_keys,
+            expand_tabs: options.expand_tabs,
+            emphasis_marker: options.emphasis_marker.clone(),
+            strong_marker: options.strong_marker.clone(),
+            task_marker_case: options.task_marker_case.clone(),
+            code_block_style: options.code_block_style.clone(),
+            ordered_start: options.ordered_start,
+        }
+    }
 }
 
-fn is_markdown_file(path: &std::path::Path) -> bool {
+fn is_markdown_file(path: &std::path::Path, extensions: &[String]) -> bool {
     path.extension()
-        .map(|ext| ext.to_string_lossy().to_lowercase() == "md")
+        .map(|ext| {
+            let ext = ext.to_string_lossy().to_lowercase();
+            extensions.contains(&ext)
+        })
         .unwrap_or(false)
 }
 
@@ -185,19 +422,34 @@ fn get_excludes(options: &Option<FileOptions>) -> Vec<String> {
     excludes
 }
 
-fn resolve_patterns(patterns: Vec<String>, excludes: &[String]) -> Vec<PathBuf> {
+/// Lowercased extensions (without the dot) of the files to format
+fn get_extensions(options: &Option<FileOptions>) -> Vec<String> {
+    match options.as_ref().and_then(|o| o.extensions.as_ref()) {
+        Some(extensions) => extensions
+            .iter()
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .collect(),
+        None => DEFAULT_EXTENSIONS.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+fn resolve_patterns(
+    patterns: Vec<String>,
+    excludes: &[String],
+    extensions: &[String],
+) -> Vec<PathBuf> {
     let mut files = Vec::new();
 
     for pattern in patterns {
         let path = PathBuf::from(&pattern);
 
         if path.is_dir() {
-            // If it's a directory, find all .md files recursively
-            let glob_pattern = format!("{}/**/*.md", pattern);
+            // If it's a directory, find all Markdown files recursively
+            let glob_pattern = format!("{}/**/*", pattern);
             if let Ok(entries) = glob(&glob_pattern) {
                 for entry in entries.flatten() {
                     if entry.is_file()
-                        && is_markdown_file(&entry)
+                        && is_markdown_file(&entry, extensions)
                         && !should_exclude(&entry, excludes)
                     {
                         files.push(entry);
@@ -206,7 +458,7 @@ fn resolve_patterns(patterns: Vec<String>, excludes: &[String]) -> Vec<PathBuf>
             }
         } else if path.is_file() {
             // Single file
-            if is_markdown_file(&path) {
+            if is_markdown_file(&path, extensions) {
                 files.push(path);
             }
         } else {
@@ -214,7 +466,7 @@ fn resolve_patterns(patterns: Vec<String>, excludes: &[String]) -> Vec<PathBuf>
             if let Ok(entries) = glob(&pattern) {
                 for entry in entries.flatten() {
                     if entry.is_file()
-                        && is_markdown_file(&entry)
+                        && is_markdown_file(&entry, extensions)
                         && !should_exclude(&entry, excludes)
                     {
                         files.push(entry);
@@ -227,71 +479,84 @@ fn resolve_patterns(patterns: Vec<String>, excludes: &[String]) -> Vec<PathBuf>
     files
 }
 
-fn format_file_content(content: &str, options: &Option<FileOptions>) -> String {
-    let width = options.as_ref().and_then(|o| o.width).unwrap_or(80) as usize;
-    let wrap_mode = parse_wrap_mode(options.as_ref().and_then(|o| o.wrap.clone()));
-    let ordered_list_mode =
-        parse_ordered_list_mode(options.as_ref().and_then(|o| o.ordered_list.clone()));
-
-    let (frontmatter, md_content) = extract_frontmatter(content);
-    let events = parse_markdown(md_content);
-    let mut formatter = Formatter::with_options(width, wrap_mode, ordered_list_mode);
-    let formatted = formatter.format(events);
-
-    if let Some(fm) = frontmatter {
-        fm + &formatted
-    } else {
-        formatted
+/// Read a file, refusing (before reading) ones larger than `maxFileSize`
+fn read_file(path: &std::path::Path, options: &Option<FileOptions>) -> Result<String, String> {
+    if let Some(max) = options.as_ref().and_then(|o| o.max_file_size) {
+        let size = fs::metadata(path)
+            .map_err(|e| format!("Failed to read: {}", e))?
+            .len();
+        if size > u64::from(max) {
+            return Err(format!(
+                "Skipped: file is {} bytes, larger than maxFileSize ({} bytes)",
+                size, max
+            ));
+        }
     }
+    fs::read_to_string(path).map_err(|e| format!("Failed to read: {}", e))
+}
+
+fn format_file_content(content: &str, options: &Option<FileOptions>) -> String {
+    let format_options = options.as_ref().map(FormatOptions::from);
+    let normalized = normalize_line_endings(content);
+    build_formatter(format_options.as_ref()).format_document(&normalized)
 }
 
 /// Format files matching the given patterns and write changes to disk.
 ///
 /// @param patterns - File paths, directories, or glob patterns
 /// @param options - Optional formatting and file options
-/// @returns Array of results for each file processed
+/// @returns Array of results for each file processed, sorted by path
 #[napi]
 pub fn format_files(patterns: Vec<String>, options: Option<FileOptions>) -> Vec<FileResult> {
     let excludes = get_excludes(&options);
-    let files = resolve_patterns(patterns, &excludes);
-    let mut results = Vec::new();
-
-    for path in files {
-        let path_str = path.display().to_string();
-
-        match fs::read_to_string(&path) {
-            Ok(content) => {
-                let formatted = format_file_content(&content, &options);
-                let changed = formatted != content;
-
-                if changed {
-                    if let Err(e) = fs::write(&path, &formatted) {
-                        results.push(FileResult {
-                            path: path_str,
-                            changed: false,
-                            error: Some(format!("Failed to write: {}", e)),
-                        });
-                        continue;
-                    }
-                }
+    let files = resolve_patterns(patterns, &excludes, &get_extensions(&options));
+
+    // Each file is read, formatted and written independently, so they can be
+    // processed in parallel; sorting keeps the result order deterministic
+    let mut results: Vec<FileResult> = files
+        .par_iter()
+        .map(|path| format_file(path, &options))
+        .collect();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    results
+}
 
-                results.push(FileResult {
-                    path: path_str,
-                    changed,
-                    error: None,
-                });
-            }
-            Err(e) => {
-                results.push(FileResult {
-                    path: path_str,
-                    changed: false,
-                    error: Some(format!("Failed to read: {}", e)),
-                });
+/// Format a single file in place
+fn format_file(path: &std::path::Path, options: &Option<FileOptions>) -> FileResult {
+    let path_str = path.display().to_string();
+
+    let content = match read_file(path, options) {
+        Ok(content) => content,
+        Err(e) => {
+            return FileResult {
+                path: path_str,
+                changed: false,
+                error: Some(e),
+                ranges: Vec::new(),
             }
         }
+    };
+
+    let formatted = format_file_content(&content, options);
+    let changed = formatted != content;
+
+    if changed {
+        if let Err(e) = fs::write(path, &formatted) {
+            return FileResult {
+                path: path_str,
+                changed: false,
+                error: Some(format!("Failed to write: {}", e)),
+                ranges: Vec::new(),
+            };
+        }
     }
 
-    results
+    FileResult {
+        path: path_str,
+        changed,
+        error: None,
+        ranges: ranges(&content, &formatted),
+    }
 }
 
 /// Check if files matching the given patterns are formatted correctly.
@@ -302,13 +567,13 @@ pub fn format_files(patterns: Vec<String>, options: Option<FileOptions>) -> Vec<
 #[napi]
 pub fn check_files(patterns: Vec<String>, options: Option<FileOptions>) -> Vec<FileResult> {
     let excludes = get_excludes(&options);
-    let files = resolve_patterns(patterns, &excludes);
+    let files = resolve_patterns(patterns, &excludes, &get_extensions(&options));
     let mut results = Vec::new();
 
     for path in files {
         let path_str = path.display().to_string();
 
-        match fs::read_to_string(&path) {
+        match read_file(&path, &options) {
             Ok(content) => {
                 let formatted = format_file_content(&content, &options);
                 let changed = formatted != content;
@@ -317,13 +582,15 @@ pub fn check_files(patterns: Vec<String>, options: Option<FileOptions>) -> Vec<F
                     path: path_str,
                     changed,
                     error: None,
+                    ranges: ranges(&content, &formatted),
                 });
             }
             Err(e) => {
                 results.push(FileResult {
                     path: path_str,
                     changed: false,
-                    error: Some(format!("Failed to read: {}", e)),
+                    error: Some(e),
+                    ranges: Vec::new(),
                 });
             }
         }
@@ -1,25 +1,470 @@
-use pulldown_cmark::{Event, Parser};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 
-/// Extract YAML frontmatter from markdown input if present
-/// Returns (frontmatter, remaining_input)
-pub fn extract_frontmatter(input: &str) -> (Option<String>, &str) {
-    if !input.starts_with("---\n") {
-        return (None, input);
+/// Whether the input uses carriage-return-only (old Mac) line endings anywhere
+pub fn has_lone_carriage_returns(input: &str) -> bool {
+    find_lone_carriage_return(input).is_some()
+}
+
+/// Byte offset of the first `\r` that isn't part of a `\r\n` pair
+pub(crate) fn find_lone_carriage_return(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    bytes
+        .iter()
+        .enumerate()
+        .find(|&(i, &b)| b == b'\r' && bytes.get(i + 1) != Some(&b'\n'))
+        .map(|(i, _)| i)
+}
+
+/// Whether the input uses CRLF line endings, judged by its first line
+pub fn uses_crlf(input: &str) -> bool {
+    input.find('\n').is_some_and(|i| input[..i].ends_with('\r'))
+}
+
+/// Convert lone `\r` line endings to `\n` so the rest of the pipeline
+/// (frontmatter detection, code blocks) sees ordinary lines
+pub fn normalize_line_endings(input: &str) -> Cow<'_, str> {
+    if !has_lone_carriage_returns(input) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut result = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\r' && chars.peek() != Some(&'\n') {
+            result.push('\n');
+        } else {
+            result.push(c);
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// Insert the missing space in `#Heading`-style lines (which CommonMark
+/// reads as plain text) so they parse as ATX headings. Lines inside fenced
+/// code blocks are left alone.
+pub fn fix_atx_heading_spacing(input: &str) -> Cow<'_, str> {
+    let mut result = String::with_capacity(input.len());
+    let mut fence: Option<(char, usize)> = None;
+    let mut changed = false;
+
+    for line in input.split_inclusive('\n') {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let rest = &line[indent..];
+
+        if indent <= 3 {
+            let marker = rest.chars().next().unwrap_or(' ');
+            let run = rest.chars().take_while(|&c| c == marker).count();
+            match fence {
+                Some((open, len)) if marker == open && run >= len => fence = None,
+                None if matches!(marker, '`' | '~') && run >= 3 => fence = Some((marker, run)),
+                None if marker == '#' && run <= 6 => {
+                    let after = &rest[run..];
+                    if after.starts_with(|c: char| !c.is_whitespace()) {
+                        result.push_str(&line[..indent + run]);
+                        result.push(' ');
+                        result.push_str(after);
+                        changed = true;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+        result.push_str(line);
     }
 
-    // Find the closing ---
-    let after_opening = &input[4..]; // Skip first "---\n"
-    if let Some(end_pos) = after_opening.find("\n---\n") {
-        let frontmatter = after_opening[..end_pos].to_string();
-        let remaining = &after_opening[end_pos + 5..]; // Skip "\n---\n"
-                                                       // Include the frontmatter with opening and closing markers, plus blank line
-        (Some(format!("---\n{}\n---\n\n", frontmatter)), remaining)
+    if changed {
+        Cow::Owned(result)
     } else {
-        (None, input)
+        Cow::Borrowed(input)
+    }
+}
+
+/// Comment that turns formatting off for a whole file when it is the first
+/// thing in the document (after any frontmatter)
+pub const IGNORE_FILE_DIRECTIVE: &str = "<!-- mdfmt-ignore-file -->";
+
+/// Comment that keeps the top-level block right after it exactly as written
+pub const IGNORE_DIRECTIVE: &str = "<!-- mdfmt-ignore -->";
+
+/// Whether the document opts out of formatting with `IGNORE_FILE_DIRECTIVE`
+/// (a leading byte order mark doesn't count as content)
+pub fn has_ignore_file_directive(input: &str) -> bool {
+    let input = input.strip_prefix('\u{FEFF}').unwrap_or(input);
+    let (_, content) = extract_frontmatter(input);
+    content.trim_start().starts_with(IGNORE_FILE_DIRECTIVE)
+}
+
+/// Extract frontmatter from markdown input if present: YAML between `---`
+/// lines or TOML between `+++` lines (Hugo, Zola). The closing line may be
+/// the last line of the input, without a newline.
+/// Returns (frontmatter, remaining_input); the frontmatter keeps its own
+/// delimiters and the input's line ending (LF or CRLF).
+///
+/// Splits only with `strip_prefix`/`split_once`, never with computed byte
+/// offsets, so it can't slice through a multibyte character.
+pub fn extract_frontmatter(input: &str) -> (Option<String>, &str) {
+    for delimiter in ["---", "+++"] {
+        let Some(rest) = input.strip_prefix(delimiter) else {
+            continue;
+        };
+        let (after_opening, newline) = if let Some(after) = rest.strip_prefix('\n') {
+            (after, "\n")
+        } else if let Some(after) = rest.strip_prefix("\r\n") {
+            (after, "\r\n")
+        } else {
+            continue;
+        };
+
+        // Find the closing delimiter, which may also end the input
+        let closing = format!("{0}{1}{0}", newline, delimiter);
+        let at_end = after_opening
+            .strip_suffix(&format!("{}{}", newline, delimiter))
+            .map(|frontmatter| (frontmatter, ""));
+        return match after_opening.split_once(&closing).or(at_end) {
+            // Include the frontmatter with opening and closing markers, plus blank line
+            Some((frontmatter, remaining)) => (
+                Some(format!(
+                    "{0}{2}{1}{2}{0}{2}{2}",
+                    delimiter, frontmatter, newline
+                )),
+                remaining,
+            ),
+            None => (None, input),
+        };
+    }
+    (None, input)
+}
+
+/// Tidy YAML frontmatter (as returned by `extract_frontmatter`, with its
+/// `---` lines): nested blocks are re-indented by two spaces per level, and
+/// keys get one space before their value, and the top-level keys are sorted
+/// if `sort_keys` is set. Scalars keep their source text, so `1.10`, `0012`
+/// and `~` stay as written.
+///
+/// Returns `None` for TOML frontmatter, invalid YAML, YAML that isn't a
+/// mapping, YAML with comments (which would be dropped or misplaced) and
+/// YAML whose data the rewrite would change; those are left as written.
+/// Always `None` without the `yaml` feature.
+pub fn normalize_yaml_frontmatter(frontmatter: &str, sort_keys: bool) -> Option<String> {
+    let yaml = frontmatter
+        .strip_prefix("---\n")?
+        .trim_end_matches('\n')
+        .strip_suffix("---")?;
+    let yaml = tidy_yaml(yaml, sort_keys)?;
+
+    let blank_lines = &frontmatter[frontmatter.trim_end_matches('\n').len()..];
+    Some(format!("---\n{}---{}", yaml, blank_lines))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn tidy_yaml(_yaml: &str, _sort_keys: bool) -> Option<String> {
+    None
+}
+
+/// Rewrite a YAML mapping line by line, checking that the result loads to
+/// the same data
+#[cfg(feature = "yaml")]
+fn tidy_yaml(yaml: &str, sort_keys: bool) -> Option<String> {
+    use yaml_rust2::{Yaml, YamlLoader};
+
+    let load = |text: &str| {
+        let mut docs = YamlLoader::load_from_str(text).ok()?;
+        (docs.len() == 1).then(|| docs.remove(0))
+    };
+    let Yaml::Hash(mapping) = load(yaml)? else {
+        return None;
+    };
+    if has_yaml_comment(yaml, &Yaml::Hash(mapping.clone()), load) {
+        return None;
+    }
+
+    // A line at column 0 starts a top-level entry; indented lines, blank
+    // lines and the items of a sequence written at column 0 belong to it
+    let mut entries: Vec<Vec<&str>> = Vec::new();
+    for line in yaml.lines() {
+        let line = line.trim_end();
+        let continues = line.is_empty()
+            || line.starts_with([' ', '\t'])
+            || line == "-"
+            || line.starts_with("- ");
+        match entries.last_mut() {
+            Some(entry) if continues => entry.push(line),
+            None if line.is_empty() => {}
+            _ if continues => return None,
+            _ => entries.push(vec![line]),
+        }
+    }
+    if entries.len() != mapping.len() {
+        return None;
     }
+
+    let mut entries: Vec<(String, Vec<String>)> = mapping
+        .keys()
+        .zip(entries)
+        .map(|(key, lines)| {
+            let key = key.as_str().unwrap_or(lines[0]).to_string();
+            (key, reindent_yaml_entry(&lines))
+        })
+        .collect();
+    if sort_keys {
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    let mut result = String::new();
+    for (_, lines) in &entries {
+        let end = lines.iter().rposition(|line| !line.is_empty())? + 1;
+        for line in &lines[..end] {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+
+    // Only the order of the top-level keys may change
+    let Yaml::Hash(tidied) = load(&result)? else {
+        return None;
+    };
+    let same = tidied.len() == mapping.len()
+        && mapping
+            .iter()
+            .all(|(key, value)| tidied.get(key) == Some(value));
+    same.then_some(result)
+}
+
+/// Whether any `#` that could start a comment does: the YAML without the
+/// rest of that line still loads to the same data
+#[cfg(feature = "yaml")]
+fn has_yaml_comment(
+    yaml: &str,
+    data: &yaml_rust2::Yaml,
+    load: impl Fn(&str) -> Option<yaml_rust2::Yaml>,
+) -> bool {
+    let lines: Vec<&str> = yaml.lines().collect();
+    lines.iter().enumerate().any(|(n, line)| {
+        line.char_indices()
+            .filter(|&(i, c)| c == '#' && line[..i].chars().last().is_none_or(char::is_whitespace))
+            .any(|(i, _)| {
+                let mut without = lines.clone();
+                without[n] = &line[..i];
+                load(&without.join("\n")).as_ref() == Some(data)
+            })
+    })
+}
+
+/// Indent the lines of one top-level entry by two spaces per nesting level.
+/// Lines starting a key or a sequence item set the levels; other lines
+/// (block scalars, continued values) move along with the line before them.
+#[cfg(feature = "yaml")]
+fn reindent_yaml_entry(lines: &[&str]) -> Vec<String> {
+    let indent = |line: &str| line.len() - line.trim_start_matches(' ').len();
+    let is_item = |text: &str| text == "-" || text.starts_with("- ");
+    let is_key = |text: &str| {
+        text.split_once(": ")
+            .map(|(key, _)| key)
+            .or_else(|| text.strip_suffix(':'))
+            .is_some_and(|key| !key.contains(['"', '\'', '#', '[', '{']))
+    };
+
+    // Columns where nested blocks start, including the content after `- `
+    let mut columns = std::collections::BTreeSet::new();
+    for line in lines {
+        let text = line.trim_start_matches(' ');
+        if is_item(text) {
+            columns.insert(indent(line));
+            columns.insert(indent(line) + 2);
+        } else if is_key(text) {
+            columns.insert(indent(line));
+        }
+    }
+    let level = |column: usize| columns.iter().take_while(|&&c| c < column).count() * 2;
+
+    let mut shift = 0isize;
+    lines
+        .iter()
+        .map(|line| {
+            let text = line.trim_start_matches(' ');
+            if text.is_empty() {
+                return String::new();
+            }
+            let column = indent(line);
+            if is_item(text) || is_key(text) {
+                shift = level(column) as isize - column as isize;
+            }
+            let column = (column as isize + shift).max(0) as usize;
+            // One space between a key and its value
+            match text.split_once(": ").filter(|_| is_key(text)) {
+                Some((key, value)) => {
+                    format!("{}{}: {}", " ".repeat(column), key, value.trim_start())
+                }
+                None => format!("{}{}", " ".repeat(column), text),
+            }
+        })
+        .collect()
 }
 
-/// Parse markdown into events (GFM tables not needed for basic support)
+/// Numeric level (1-6) of a heading, without relying on the enum's discriminants
+pub(crate) fn heading_level(level: HeadingLevel) -> u32 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+/// Parser extensions shared by formatting and analysis (GFM tables,
+/// footnotes and alerts, `$` math)
+pub(crate) fn parser_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_MATH
+        | Options::ENABLE_GFM
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_DEFINITION_LIST
+}
+
+/// Parse markdown into events
 pub fn parse_markdown(input: &str) -> Vec<Event<'_>> {
-    Parser::new(input).collect()
+    Parser::new_ext(input, parser_options()).collect()
+}
+
+/// Number of blank lines before each top-level block (and thematic break)
+/// of `input`, in document order
+pub(crate) fn blank_lines_before_blocks(input: &str) -> VecDeque<usize> {
+    let mut counts = VecDeque::new();
+    let mut depth = 0;
+    for (event, range) in Parser::new_ext(input, parser_options()).into_offset_iter() {
+        match event {
+            Event::Start(_) | Event::Rule if depth == 0 => {
+                let before = &input[..range.start];
+                let gap = &before[before.trim_end().len()..];
+                counts.push_back(gap.matches('\n').count().saturating_sub(1));
+            }
+            _ => {}
+        }
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+    }
+    counts
+}
+
+/// Source text of each top-level block (or thematic break) that follows an
+/// `IGNORE_DIRECTIVE` comment block, in document order
+pub(crate) fn ignored_blocks(input: &str) -> VecDeque<String> {
+    let mut blocks = VecDeque::new();
+    let mut depth = 0;
+    let mut after_directive = false;
+    for (event, range) in Parser::new_ext(input, parser_options()).into_offset_iter() {
+        match &event {
+            Event::Start(_) | Event::Rule if depth == 0 && after_directive => {
+                blocks.push_back(input[range].trim_end().to_string());
+                after_directive = false;
+            }
+            Event::Html(html) if depth == 1 && html.trim() == IGNORE_DIRECTIVE => {
+                after_directive = true;
+            }
+            _ => {}
+        }
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Counts of the elements of a document, as printed by `--stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocumentStats {
+    /// Headings per level (index 0 is level 1)
+    pub headings: [usize; 6],
+    pub list_items: usize,
+    pub links: usize,
+    pub images: usize,
+    pub code_blocks: usize,
+    /// Words of prose: text outside code blocks and raw HTML, including
+    /// link text and image descriptions
+    pub words: usize,
+}
+
+impl DocumentStats {
+    /// Statistics of a document (any frontmatter isn't counted)
+    pub fn collect(input: &str) -> Self {
+        let (_, content) = extract_frontmatter(input);
+        Self::from_events(&parse_markdown(content))
+    }
+
+    /// Statistics of a parsed document, from the same events the formatter uses
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut stats = Self::default();
+        let mut in_code_block = false;
+        // Whether the last text ended inside a word, so a word split across
+        // events (e.g. `**bold**er`) counts once
+        let mut in_word = false;
+
+        for event in events {
+            match event {
+                Event::Start(tag) => {
+                    match tag {
+                        Tag::Heading { level, .. } => {
+                            stats.headings[heading_level(*level) as usize - 1] += 1
+                        }
+                        Tag::Item => stats.list_items += 1,
+                        Tag::Link { .. } => stats.links += 1,
+                        Tag::Image { .. } => stats.images += 1,
+                        Tag::CodeBlock(_) => {
+                            stats.code_blocks += 1;
+                            in_code_block = true;
+                        }
+                        _ => {}
+                    }
+                    if !is_inline_tag(tag) {
+                        in_word = false;
+                    }
+                }
+                Event::End(TagEnd::CodeBlock) => in_code_block = false,
+                Event::Text(text) | Event::Code(text) if !in_code_block => {
+                    for (i, part) in text.split(char::is_whitespace).enumerate() {
+                        if i > 0 {
+                            in_word = false;
+                        }
+                        if !part.is_empty() {
+                            stats.words += usize::from(!in_word);
+                            in_word = true;
+                        }
+                    }
+                }
+                Event::End(_) => {}
+                _ => in_word = false,
+            }
+        }
+        stats
+    }
+
+    /// Total number of headings at all levels
+    pub fn heading_count(&self) -> usize {
+        self.headings.iter().sum()
+    }
+}
+
+/// Whether a tag is an inline span, which continues the surrounding text
+fn is_inline_tag(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis
+            | Tag::Strong
+            | Tag::Strikethrough
+            | Tag::Superscript
+            | Tag::Subscript
+            | Tag::Link { .. }
+            | Tag::Image { .. }
+    )
 }
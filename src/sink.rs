@@ -0,0 +1,63 @@
+//! Output sinks
+//!
+//! The formatter writes finished output through `OutputSink` rather than
+//! straight into a `String`, so callers can render into other targets
+//! (an editor's rope, a writer that hashes as it goes, ...).
+
+use std::io::{self, Write};
+
+/// Destination for formatted markdown
+pub trait OutputSink {
+    /// Append a string
+    fn push_str(&mut self, s: &str);
+
+    /// Append a single character
+    fn push_char(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+}
+
+impl OutputSink for String {
+    fn push_str(&mut self, s: &str) {
+        String::push_str(self, s);
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.push(c);
+    }
+}
+
+/// Sink writing to an `io::Write`. Writing stops at the first error, which
+/// `finish` returns.
+#[derive(Debug)]
+pub struct WriterSink<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flush the writer and return the first error, if any
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for WriterSink<W> {
+    fn push_str(&mut self, s: &str) {
+        if self.error.is_none() {
+            if let Err(error) = self.writer.write_all(s.as_bytes()) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
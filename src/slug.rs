@@ -0,0 +1,44 @@
+//! Heading anchor ids
+//!
+//! The one slug implementation used for heading anchors, following GitHub's
+//! rules (as implemented by `github-slugger`) so anchors match what GitHub
+//! renders.
+
+use std::collections::HashMap;
+
+/// Slug for a single heading text, without de-duplication: lowercase, drop
+/// everything but letters, numbers, `_`, `-` and spaces, then turn each space
+/// into `-`
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ' '))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Produces unique slugs for the headings of one document: repeated slugs get
+/// `-1`, `-2`, ... appended in order of appearance
+#[derive(Debug, Clone, Default)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Unique slug for the next heading with this text
+    pub fn slug(&mut self, text: &str) -> String {
+        let original = slugify(text);
+        let mut slug = original.clone();
+        while self.occurrences.contains_key(&slug) {
+            let count = self.occurrences.entry(original.clone()).or_insert(0);
+            *count += 1;
+            slug = format!("{}-{}", original, count);
+        }
+        self.occurrences.insert(slug.clone(), 0);
+        slug
+    }
+}
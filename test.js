@@ -2,7 +2,11 @@ const {
   formatMarkdown,
   formatMarkdownWithResult,
   checkMarkdown,
+  checkFiles,
 } = require("./index.js");
+const fs = require("fs");
+const os = require("os");
+const path = require("path");
 
 // Test basic formatting
 const input = `#  Heading with extra space
@@ -48,4 +52,31 @@ if (formatted === secondPass) {
   process.exit(1);
 }
 
+// Test which file extensions are picked up
+console.log("\nTesting file extensions...");
+const dir = fs.mkdtempSync(path.join(os.tmpdir(), "mdfmt-"));
+for (const name of ["a.md", "b.markdown", "c.mdx", "d.txt"]) {
+  fs.writeFileSync(path.join(dir, name), "# Title\n");
+}
+const found = (options) =>
+  checkFiles([dir], options)
+    .map((r) => path.basename(r.path))
+    .sort()
+    .join(",");
+const expectations = [
+  [undefined, "a.md,b.markdown"],
+  [{ extensions: ["md", "markdown", "mdx"] }, "a.md,b.markdown,c.mdx"],
+  [{ extensions: [".mdx"] }, "c.mdx"],
+];
+for (const [options, expected] of expectations) {
+  const actual = found(options);
+  if (actual !== expected) {
+    console.error(`✗ Expected ${expected}, found ${actual}`);
+    process.exit(1);
+  }
+}
+fs.rmSync(dir, { recursive: true, force: true });
+console.log("✓ Extensions are respected");
+
 console.log("\n✓ All tests passed!");
+
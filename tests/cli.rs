@@ -0,0 +1,569 @@
+//! Integration tests for the `mdfmt` binary
+
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn mdfmt(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mdfmt"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run mdfmt")
+}
+
+/// Run mdfmt with `input` piped to its stdin
+fn mdfmt_stdin(args: &[&str], dir: &Path, input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mdfmt"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run mdfmt");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().expect("failed to run mdfmt")
+}
+
+#[test]
+fn verify_passes_on_stable_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("doc.md"),
+        "#  Title\n\nSome *text* here.\n\n* a\n* b\n",
+    )
+    .unwrap();
+
+    let output = mdfmt(&["--verify", "--write", "doc.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("doc.md")).unwrap(),
+        "# Title\n\nSome *text* here.\n\n- a\n- b\n"
+    );
+}
+
+#[test]
+fn self_check_passes_on_stable_fixture() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let output = mdfmt(&["--self-check", "tight-list.md"], &fixtures);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixtures.join("tight-list.md")).unwrap()
+    );
+}
+
+#[test]
+fn max_file_size_skips_large_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("small.md"), "# Small\n").unwrap();
+    fs::write(dir.path().join("big.md"), "# Big\n\n".repeat(100)).unwrap();
+
+    let output = mdfmt(&["--check", "--max-file-size", "64", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Skipped")
+            && stderr.contains("big.md")
+            && stderr.contains("--max-file-size"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("All 1 file(s) are formatted correctly"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn ignore_file_directive_reports_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = "<!-- mdfmt-ignore-file -->\n#  Generated\n* keep   as is\n";
+    fs::write(dir.path().join("generated.md"), content).unwrap();
+
+    let output = mdfmt(&["--check", "."], dir.path());
+    assert!(output.status.success());
+
+    let output = mdfmt(&["--write", "."], dir.path());
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("generated.md")).unwrap(),
+        content
+    );
+
+    // A file with old Mac line endings is left as it is too
+    let cr_only = "<!-- mdfmt-ignore-file -->\r#  Generated\r* keep   as is\r";
+    fs::write(dir.path().join("generated.md"), cr_only).unwrap();
+    let output = mdfmt(&["--check", "."], dir.path());
+    assert!(output.status.success());
+    let output = mdfmt(&["--write", "."], dir.path());
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("generated.md")).unwrap(),
+        cr_only
+    );
+
+    // The directives are comments, which strict mode lets through
+    let block = "# Title\n\n<!-- mdfmt-ignore -->\n\n*  kept   as is\n";
+    fs::write(dir.path().join("block.md"), block).unwrap();
+    let output = mdfmt(&["--strict", "--check", "."], dir.path());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn since_limits_to_files_changed_in_git() {
+    let dir = tempfile::tempdir().unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run git")
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("old.md"), "* untouched\n").unwrap();
+    fs::write(dir.path().join("docs/edited.md"), "# Doc\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&[
+        "-c",
+        "user.name=test",
+        "-c",
+        "user.email=test@example.com",
+        "commit",
+        "-qm",
+        "initial",
+    ]);
+
+    fs::write(dir.path().join("docs/edited.md"), "# Doc\n* changed\n").unwrap();
+
+    let output = mdfmt(&["--check", "--since", "HEAD", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("edited.md"), "{}", stderr);
+    assert!(!stderr.contains("old.md"), "{}", stderr);
+    assert!(
+        stderr.contains("1 file(s) would be reformatted"),
+        "{}",
+        stderr
+    );
+
+    // Nothing changed inside the given paths: nothing to do, not an error
+    let output = mdfmt(&["--check", "--since", "HEAD", "old.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn embedded_formats_rust_doc_comments() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("lib.rs"),
+        "/// Adds   *two*  numbers\nfn add(a: i32,   b: i32) {}\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("README.md"), "#  Untouched\n").unwrap();
+
+    let output = mdfmt(&["--embedded", "rust", "--write", "."], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("lib.rs")).unwrap(),
+        "/// Adds *two* numbers\nfn add(a: i32,   b: i32) {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("README.md")).unwrap(),
+        "#  Untouched\n"
+    );
+}
+
+#[test]
+fn check_reports_line_ending_only_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("doc.md"), "# Title\r\n\r\nSome text.\r\n").unwrap();
+
+    let output = mdfmt(
+        &["--check", "--line-ending", "preserve", "doc.md"],
+        dir.path(),
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = mdfmt(&["--check", "doc.md"], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Would reformat: doc.md (line endings only)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn diff_prints_changes_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("messy.md"), "#  Title\n\n* a\n").unwrap();
+    fs::write(dir.path().join("clean.md"), "# Clean\n").unwrap();
+
+    let output = mdfmt(&["--diff", "."], dir.path());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("--- messy.md\n+++ messy.md (formatted)\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-#  Title\n+# Title\n") && stdout.contains("-* a\n+- a\n"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("clean.md"), "{}", stdout);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("messy.md")).unwrap(),
+        "#  Title\n\n* a\n"
+    );
+
+    let output = mdfmt(&["--diff", "clean.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn directories_respect_gitignore() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(".gitignore"), "ignored.md\ngenerated/\n").unwrap();
+    fs::create_dir(dir.path().join("generated")).unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    for file in ["kept.md", "ignored.md", "generated/api.md", "docs/guide.md"] {
+        fs::write(dir.path().join(file), "*  messy\n").unwrap();
+    }
+
+    let output = mdfmt(&["--check", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Would reformat: kept.md"), "{}", stderr);
+    assert!(
+        stderr.contains("Would reformat: docs/guide.md"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("ignored.md"), "{}", stderr);
+    assert!(!stderr.contains("generated"), "{}", stderr);
+
+    let output = mdfmt(&["--check", "--no-gitignore", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("4 file(s) would be reformatted"),
+        "{}",
+        stderr
+    );
+
+    // Files named explicitly are formatted even when ignored
+    let output = mdfmt(&["--check", "ignored.md"], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Would reformat: ignored.md"), "{}", stderr);
+}
+
+#[test]
+fn stdin_filepath_names_the_buffer_in_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("docs/other.md"), "# Other\n").unwrap();
+    let input = "See [other](other.md) and [gone](gone.md).\n";
+
+    let args = [
+        "--stdin",
+        "--stdin-filepath",
+        "docs/page.md",
+        "--check-links",
+    ];
+    let output = mdfmt_stdin(&args, dir.path(), input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    // Relative links resolve against the named file's directory
+    assert!(stderr.contains("Warning: docs/page.md:1:"), "{}", stderr);
+    assert!(stderr.contains("gone.md"), "{}", stderr);
+    assert!(!stderr.contains("other.md"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), input);
+
+    // The named file isn't read (it doesn't even exist)
+    let output = mdfmt_stdin(
+        &["--stdin", "--stdin-filepath", "missing.md"],
+        dir.path(),
+        "#  Hi\n",
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "# Hi\n");
+
+    let output = mdfmt(&["--stdin-filepath", "page.md", "docs"], dir.path());
+    assert!(!output.status.success());
+}
+
+#[test]
+fn check_with_json_format_reports_each_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("clean.md"), "# Clean\n").unwrap();
+    fs::write(dir.path().join("messy.md"), "#  Messy\n").unwrap();
+    fs::write(dir.path().join("bad.md"), [0xff, 0xfe]).unwrap();
+
+    let output = mdfmt(&["--check", "--format", "json", "."], dir.path());
+    assert!(!output.status.success());
+    assert!(output.stderr.is_empty(), "{:?}", output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let reports: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let reports = reports.as_array().unwrap();
+    assert_eq!(reports.len(), 3, "{}", stdout);
+    assert_eq!(reports[0]["path"], "bad.md");
+    assert_eq!(reports[0]["changed"], false);
+    assert!(reports[0]["error"].is_string(), "{}", stdout);
+    assert_eq!(
+        reports[1],
+        serde_json::json!({"path": "clean.md", "changed": false, "error": null})
+    );
+    assert_eq!(
+        reports[2],
+        serde_json::json!({"path": "messy.md", "changed": true, "error": null})
+    );
+
+    // Nothing would change: success, and the files are left alone
+    let output = mdfmt(&["--check", "--format", "json", "clean.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("messy.md")).unwrap(),
+        "#  Messy\n"
+    );
+}
+
+#[test]
+fn extensions_select_markdown_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    for name in ["a.md", "b.markdown", "c.MD", "d.mdx", "e.txt"] {
+        fs::write(dir.path().join("docs").join(name), "#  Title\n").unwrap();
+    }
+    let reformatted = |output: &Output| {
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(|line| line.strip_prefix("Would reformat: docs/"))
+            .map(String::from)
+            .collect();
+        files.sort();
+        files
+    };
+
+    // .md and .markdown by default, in any case
+    let output = mdfmt(&["--check", "docs"], dir.path());
+    assert_eq!(reformatted(&output), ["a.md", "b.markdown", "c.MD"]);
+
+    let output = mdfmt(&["--check", "--no-gitignore", "docs"], dir.path());
+    assert_eq!(reformatted(&output), ["a.md", "b.markdown", "c.MD"]);
+
+    let output = mdfmt(&["--check", "--extensions", "mdx,.md", "docs"], dir.path());
+    assert_eq!(reformatted(&output), ["a.md", "c.MD", "d.mdx"]);
+
+    // Files named explicitly must have one of the extensions too
+    let output = mdfmt(&["--check", "docs/d.mdx"], dir.path());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("not a markdown file (.md, .markdown)"),
+        "{:?}",
+        output
+    );
+    let output = mdfmt(
+        &["--check", "--extensions", "mdx", "docs/d.mdx"],
+        dir.path(),
+    );
+    assert_eq!(reformatted(&output), ["d.mdx"]);
+}
+
+#[test]
+fn config_file_sets_defaults_for_files_below_it() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join(".git")).unwrap();
+    fs::create_dir_all(dir.path().join("docs/guide")).unwrap();
+    fs::create_dir(dir.path().join("skipped")).unwrap();
+    fs::write(
+        dir.path().join(".mdfmt.toml"),
+        "width = 20\nwrap = \"always\"\nbullet = \"star\"\nexclude = [\"skipped\"]\n",
+    )
+    .unwrap();
+    let input = "- one two three four five six\n";
+    fs::write(dir.path().join("docs/guide/a.md"), input).unwrap();
+    fs::write(dir.path().join("skipped/b.md"), input).unwrap();
+
+    // Found from the file's directory upwards
+    let output = mdfmt(&["docs/guide/a.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "* one two three four\n  five six\n"
+    );
+
+    // Flags on the command line take precedence
+    let output = mdfmt(&["--width", "80", "docs/guide/a.md"], dir.path());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "* one two three four five six\n"
+    );
+
+    // Excludes apply to directory searches
+    let output = mdfmt(&["--check", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Would reformat: docs/guide/a.md"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("skipped"), "{}", stderr);
+
+    // A nearer config file replaces the outer one entirely
+    fs::write(dir.path().join("docs/.mdfmt.toml"), "wrap = \"never\"\n").unwrap();
+    let output = mdfmt(&["docs/guide/a.md"], dir.path());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "- one two three four five six\n"
+    );
+
+    // Invalid settings are reported with the file they come from
+    fs::write(
+        dir.path().join("docs/.mdfmt.toml"),
+        "wrap = \"sometimes\"\n",
+    )
+    .unwrap();
+    let output = mdfmt(&["docs/guide/a.md"], dir.path());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(".mdfmt.toml"), "{}", stderr);
+}
+
+#[test]
+fn jobs_process_files_in_parallel_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let names: Vec<String> = (0..40).map(|i| format!("doc{:02}.md", i)).collect();
+    for (i, name) in names.iter().enumerate() {
+        fs::write(dir.path().join(name), format!("#  Doc {}\n* item\n", i)).unwrap();
+    }
+    fs::write(dir.path().join("bad.md"), [0xff, 0xfe]).unwrap();
+
+    // Printed output comes in file order
+    let output = mdfmt(&["--jobs", "4", "."], dir.path());
+    let expected: String = (0..40)
+        .map(|i| format!("# Doc {}\n\n- item\n", i))
+        .collect();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // Every file is written, and the unreadable one still fails the run
+    let output = mdfmt(&["--jobs", "4", "--write", "."], dir.path());
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error:"));
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(
+            fs::read_to_string(dir.path().join(name)).unwrap(),
+            format!("# Doc {}\n\n- item\n", i)
+        );
+    }
+
+    let output = mdfmt(&["--jobs", "4", "--check", "."], dir.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("All 40 file(s) are formatted correctly"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn stats_are_printed_without_changing_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "# Title\n\n## Links\n\nSee [the docs](https://example.com).\n";
+    fs::write(dir.path().join("a.md"), input).unwrap();
+
+    let output = mdfmt(&["--stats", "a.md"], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Stats: a.md: 2 headings (h1: 1, h2: 1), 0 list items, 1 link, 0 images, 0 code blocks, 5 words\n"
+    );
+}
+
+#[test]
+fn write_prints_a_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.md"), "# A\n").unwrap();
+    fs::write(dir.path().join("b.md"), "#  B\n* item\n").unwrap();
+    fs::write(dir.path().join("c.md"), "# C\n").unwrap();
+
+    let output = mdfmt(&["--write", "."], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Formatted: b.md\nFormatted 1 of 3 file(s)\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.md")).unwrap(),
+        "# B\n\n- item\n"
+    );
+
+    // Everything is formatted now; --verbose lists the unchanged files
+    let output = mdfmt(&["--write", "--verbose", "."], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Unchanged: a.md\nUnchanged: b.md\nUnchanged: c.md\nFormatted 0 of 3 file(s)\n"
+    );
+}
+
+#[test]
+fn list_files_prints_resolved_paths_without_formatting() {
+    let dir = tempfile::tempdir().unwrap();
+    for path in [
+        "docs/guide.md",
+        "docs/api/index.md",
+        "docs/drafts/wip.md",
+        "node_modules/pkg/README.md",
+    ] {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "#  Unformatted\n").unwrap();
+    }
+
+    let output = mdfmt(&["--list-files", "--exclude", "drafts", "."], dir.path());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "docs/api/index.md\ndocs/guide.md\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("docs/guide.md")).unwrap(),
+        "#  Unformatted\n"
+    );
+}
+
+#[test]
+fn check_diff_stat_counts_changed_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.md"), "# A\n").unwrap();
+    // The heading and both bullets change; the blank line is added
+    fs::write(dir.path().join("b.md"), "#  B\n* one\n* two\n").unwrap();
+
+    let output = mdfmt(&["--check", "--diff-stat", "."], dir.path());
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "b.md: +4 -3\n1 file(s) would be reformatted\n"
+    );
+}